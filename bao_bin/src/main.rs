@@ -1,10 +1,14 @@
 use arrayref::array_ref;
 use failure::{err_msg, Error};
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs::{File, OpenOptions};
 use std::io;
 use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -16,6 +20,11 @@ Usage: bao hash [<inputs>...]
        bao decode <hash> [<input>] [<output>] [--outboard=<file>] [--start=<offset>] [--count=<count>]
        bao slice <start> <count> [<input>] [<output>] [--outboard=<file>]
        bao decode-slice <hash> <start> <count> [<input>] [<output>]
+       bao coarsen-outboard <group-log> [<input>] [<output>]
+       bao refine-outboard <hash> <group-log> [<input>] [<output>] --outboard=<file>
+       bao diff <hash-a> <outboard-a> <hash-b> <outboard-b>
+       bao serve <address> <inputs>...
+       bao get <address> <hash> [<output>] [--start=<offset>] [--count=<count>]
        bao (--help | --version)
 ";
 
@@ -26,12 +35,23 @@ struct Args {
     cmd_hash: bool,
     cmd_slice: bool,
     cmd_decode_slice: bool,
+    cmd_coarsen_outboard: bool,
+    cmd_refine_outboard: bool,
+    cmd_diff: bool,
+    cmd_serve: bool,
+    cmd_get: bool,
+    arg_address: String,
     arg_input: Option<PathBuf>,
     arg_inputs: Vec<PathBuf>,
     arg_output: Option<PathBuf>,
     arg_hash: String,
+    arg_hash_a: String,
+    arg_hash_b: String,
     arg_start: u64,
     arg_count: u64,
+    arg_group_log: u8,
+    arg_outboard_a: PathBuf,
+    arg_outboard_b: PathBuf,
     flag_count: Option<u64>,
     flag_help: bool,
     flag_outboard: Option<PathBuf>,
@@ -58,6 +78,16 @@ fn main() -> Result<(), Error> {
         slice(&args)?;
     } else if args.cmd_decode_slice {
         decode_slice(&args)?;
+    } else if args.cmd_coarsen_outboard {
+        coarsen_outboard(&args)?;
+    } else if args.cmd_refine_outboard {
+        refine_outboard(&args)?;
+    } else if args.cmd_diff {
+        diff(&args)?;
+    } else if args.cmd_serve {
+        serve(&args)?;
+    } else if args.cmd_get {
+        get(&args)?;
     } else {
         unreachable!();
     }
@@ -91,9 +121,7 @@ fn hash_one(maybe_path: &Option<PathBuf>) -> Result<bao::Hash, Error> {
         #[cfg(feature = "rayon")]
         {
             // multi-threaded
-            hash = blake3::Hasher::new()
-                .update_with_join::<blake3::join::RayonJoin>(&map)
-                .finalize();
+            hash = blake3::Hasher::new().update_rayon(&map).finalize();
         }
         #[cfg(not(feature = "rayon"))]
         {
@@ -161,7 +189,7 @@ fn encode(args: &Args) -> Result<(), Error> {
 fn decode(args: &Args) -> Result<(), Error> {
     let input = open_input(&args.arg_input)?;
     let mut output = open_output(&args.arg_output)?;
-    let hash = parse_hash(args)?;
+    let hash = parse_hash(&args.arg_hash)?;
     let outboard;
     let mut generic_decoder;
     let mut file_decoder;
@@ -224,12 +252,121 @@ fn slice(args: &Args) -> Result<(), Error> {
 fn decode_slice(args: &Args) -> Result<(), Error> {
     let input = open_input(&args.arg_input)?;
     let mut output = open_output(&args.arg_output)?;
-    let hash = parse_hash(&args)?;
+    let hash = parse_hash(&args.arg_hash)?;
     let mut decoder = bao::decode::SliceDecoder::new(input, &hash, args.arg_start, args.arg_count);
     allow_broken_pipe(copy_reader_to_writer(&mut decoder, &mut output))?;
     Ok(())
 }
 
+// The input is a full outboard encoding, and the output is the outboard encoding for chunk groups.
+fn coarsen_outboard(args: &Args) -> Result<(), Error> {
+    let group_log = parse_group_log(args)?;
+    let input = open_input(&args.arg_input)?;
+    let mut output = io::BufWriter::new(open_output(&args.arg_output)?);
+    bao::outboard::coarsen_outboard(io::BufReader::new(input), &mut output, group_log)?;
+    output.flush()?;
+    Ok(())
+}
+
+// The input is the content, the --outboard file is the outboard encoding for chunk groups, and
+// the output is the full outboard encoding. The coarse outboard has no parent nodes when the
+// content is a single chunk group, so the root hash is only checked here at the end.
+fn refine_outboard(args: &Args) -> Result<(), Error> {
+    let hash = parse_hash(&args.arg_hash)?;
+    let group_log = parse_group_log(args)?;
+    let input = open_input(&args.arg_input)?;
+    let coarse = open_input(&args.flag_outboard)?;
+    let mut output = io::BufWriter::new(open_output(&args.arg_output)?);
+    let refined_hash = bao::outboard::refine_outboard(
+        io::BufReader::new(input),
+        io::BufReader::new(coarse),
+        &mut output,
+        group_log,
+    )?;
+    output.flush()?;
+    // Hash implements constant time equality.
+    if refined_hash != hash {
+        return Err(err_msg("hash mismatch"));
+    }
+    Ok(())
+}
+
+// Compare two outboard encodings of content with the same length, given with their root hashes,
+// and print the ranges of content that differ, one per line as an offset and a count, like the
+// arguments to `bao slice`.
+fn diff(args: &Args) -> Result<(), Error> {
+    let hash_a = parse_hash(&args.arg_hash_a)?;
+    let hash_b = parse_hash(&args.arg_hash_b)?;
+    let a = bao::outboard::PreOrderOutboard::new(File::open(&args.arg_outboard_a)?);
+    let b = bao::outboard::PreOrderOutboard::new(File::open(&args.arg_outboard_b)?);
+    for range in bao::outboard::diff_outboards(a, &hash_a, b, &hash_b)? {
+        println!("{} {}", range.start, range.end - range.start);
+    }
+    Ok(())
+}
+
+// Serves the input files by hash, with their outboard encodings in memory.
+struct FileProvider {
+    files: HashMap<bao::Hash, (PathBuf, Arc<[u8]>)>,
+}
+
+impl bao::protocol::Provider for FileProvider {
+    type Content = File;
+    type Outboard = bao::outboard::PreOrderOutboard<io::Cursor<Arc<[u8]>>>;
+
+    fn open(&self, hash: &bao::Hash) -> io::Result<Option<(Self::Content, Self::Outboard)>> {
+        let (path, outboard) = match self.files.get(hash) {
+            Some(file) => file,
+            None => return Ok(None),
+        };
+        let outboard = bao::outboard::PreOrderOutboard::new(io::Cursor::new(outboard.clone()));
+        Ok(Some((File::open(path)?, outboard)))
+    }
+}
+
+// Print the hash of each input, and then the address, and serve the inputs with the slice
+// protocol until killed. Each connection gets its own thread.
+fn serve(args: &Args) -> Result<(), Error> {
+    let mut files = HashMap::new();
+    for path in &args.arg_inputs {
+        let mut input = File::open(path)?;
+        let mut outboard = Vec::new();
+        let mut encoder = bao::encode::Encoder::new_outboard(io::Cursor::new(&mut outboard));
+        copy_reader_to_writer(&mut input, &mut encoder)?;
+        let hash = encoder.finalize()?;
+        println!("{}  {}", hash.to_hex(), path.to_string_lossy());
+        files.insert(hash, (path.clone(), Arc::from(outboard)));
+    }
+    let listener = TcpListener::bind(&args.arg_address)?;
+    println!("listening on {}", listener.local_addr()?);
+    let provider = Arc::new(FileProvider { files });
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let provider = provider.clone();
+        thread::spawn(move || {
+            if let Err(e) = bao::protocol::serve(&*provider, stream) {
+                eprintln!("bao: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Like decode-slice, but from a server, and the output is only the content.
+fn get(args: &Args) -> Result<(), Error> {
+    let hash = parse_hash(&args.arg_hash)?;
+    let mut client = bao::protocol::Client::new(TcpStream::connect(&args.arg_address)?);
+    let mut output = open_output(&args.arg_output)?;
+    let start = args.flag_start.unwrap_or(0);
+    let end = match args.flag_count {
+        Some(count) => start.saturating_add(count),
+        None => u64::MAX,
+    };
+    let range = start..end;
+    allow_broken_pipe(client.get(&hash, std::slice::from_ref(&range), &mut output))?;
+    Ok(())
+}
+
 fn open_input(maybe_path: &Option<PathBuf>) -> Result<Input, Error> {
     Ok(
         if let Some(ref path) = path_if_some_and_not_dash(maybe_path) {
@@ -248,7 +385,7 @@ enum Input {
 impl Input {
     fn require_file(self) -> Result<File, Error> {
         match self {
-            Input::Stdin => Err(err_msg(format!("input must be a real file"))),
+            Input::Stdin => Err(err_msg("input must be a real file")),
             Input::File(file) => Ok(file),
         }
     }
@@ -285,7 +422,7 @@ enum Output {
 impl Output {
     fn require_file(self) -> Result<File, Error> {
         match self {
-            Output::Stdout => Err(err_msg(format!("output must be a real file"))),
+            Output::Stdout => Err(err_msg("output must be a real file")),
             Output::File(file) => Ok(file),
         }
     }
@@ -329,7 +466,7 @@ fn maybe_memmap_input(input: &Input) -> Result<Option<memmap::Mmap>, Error> {
     Ok(if !metadata.is_file() {
         // Not a real file.
         None
-    } else if file_size > isize::max_value() as u64 {
+    } else if file_size > isize::MAX as u64 {
         // Too long to safely map. https://github.com/danburkert/memmap-rs/issues/69
         None
     } else if file_size == 0 {
@@ -344,20 +481,27 @@ fn maybe_memmap_input(input: &Input) -> Result<Option<memmap::Mmap>, Error> {
         let map = unsafe {
             memmap::MmapOptions::new()
                 .len(metadata.len() as usize)
-                .map(&in_file)?
+                .map(in_file)?
         };
         Some(map)
     })
 }
 
-fn parse_hash(args: &Args) -> Result<bao::Hash, Error> {
-    let hash_vec = hex::decode(&args.arg_hash).map_err(|_| err_msg("invalid hex"))?;
+fn parse_hash(hex: &str) -> Result<bao::Hash, Error> {
+    let hash_vec = hex::decode(hex).map_err(|_| err_msg("invalid hex"))?;
     if hash_vec.len() != bao::HASH_SIZE {
         return Err(err_msg("wrong length hash"));
     };
     Ok((*array_ref!(hash_vec, 0, bao::HASH_SIZE)).into())
 }
 
+fn parse_group_log(args: &Args) -> Result<u8, Error> {
+    if args.arg_group_log > bao::encode::MAX_CHUNK_GROUP_LOG {
+        return Err(err_msg("group log too large"));
+    }
+    Ok(args.arg_group_log)
+}
+
 // When streaming out decoded content, it's acceptable for the caller to pipe us
 // into e.g. `head -c 100`. We catch closed pipe errors in that case and avoid
 // erroring out.
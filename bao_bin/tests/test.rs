@@ -2,6 +2,7 @@ use duct::cmd;
 use rand::prelude::*;
 use std::env::consts::EXE_EXTENSION;
 use std::fs;
+use std::io::{self, BufRead};
 use std::path::{Path, PathBuf};
 use std::sync::Once;
 use tempfile::tempdir;
@@ -277,3 +278,227 @@ fn test_slice() {
     .unwrap();
     assert_hash_mismatch(&output);
 }
+
+#[test]
+fn test_coarsen_refine_outboard() {
+    let input_len = 1_000_000;
+    let mut input = vec![0; input_len];
+    rand::thread_rng().fill_bytes(&mut input);
+    let dir = tempdir().unwrap();
+    let input_path = dir.path().join("input");
+    fs::write(&input_path, &input).unwrap();
+    let outboard_path = dir.path().join("outboard");
+    cmd!(
+        bao_exe(),
+        "encode",
+        &input_path,
+        "--outboard",
+        &outboard_path
+    )
+    .run()
+    .unwrap();
+
+    // The coarse outboard is smaller, and refining it gives back the original.
+    let coarse_path = dir.path().join("coarse");
+    cmd!(
+        bao_exe(),
+        "coarsen-outboard",
+        "4",
+        &outboard_path,
+        &coarse_path
+    )
+    .run()
+    .unwrap();
+    let coarse_len = fs::metadata(&coarse_path).unwrap().len();
+    assert!(coarse_len < fs::metadata(&outboard_path).unwrap().len() / 10);
+    let hash = blake3::hash(&input).to_hex();
+    let refined = cmd!(
+        bao_exe(),
+        "refine-outboard",
+        &*hash,
+        "4",
+        &input_path,
+        "--outboard",
+        &coarse_path
+    )
+    .stdout_capture()
+    .run()
+    .unwrap()
+    .stdout;
+    assert_eq!(fs::read(&outboard_path).unwrap(), refined);
+
+    // Refining against a different hash fails.
+    let output = cmd!(
+        bao_exe(),
+        "refine-outboard",
+        &*blake3::hash(b"foo").to_hex(),
+        "4",
+        &input_path,
+        "--outboard",
+        &coarse_path
+    )
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!output.status.success());
+
+    // Refining with different content fails.
+    input[input_len / 2] ^= 1;
+    let output = cmd!(
+        bao_exe(),
+        "refine-outboard",
+        &*hash,
+        "4",
+        "--outboard",
+        &coarse_path
+    )
+    .stdin_bytes(&*input)
+    .stdout_capture()
+    .stderr_capture()
+    .unchecked()
+    .run()
+    .unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_diff() {
+    let input_len = 1_000_000;
+    let mut input = vec![0; input_len];
+    rand::thread_rng().fill_bytes(&mut input);
+    let mut changed = input.clone();
+    changed[0] ^= 1;
+    changed[500_000] ^= 1;
+    changed[500_000 + 1024] ^= 1;
+    let dir = tempdir().unwrap();
+    let mut hashes = Vec::new();
+    let mut outboard_paths = Vec::new();
+    for (name, content) in [("a", &input), ("b", &changed)] {
+        hashes.push(blake3::hash(content).to_hex());
+        let input_path = dir.path().join(name);
+        fs::write(&input_path, content).unwrap();
+        let outboard_path = dir.path().join(format!("{}.obao", name));
+        cmd!(
+            bao_exe(),
+            "encode",
+            &input_path,
+            "--outboard",
+            &outboard_path
+        )
+        .run()
+        .unwrap();
+        outboard_paths.push(outboard_path);
+    }
+
+    // The changed chunks, with the two next to each other merged, as offsets and counts.
+    let output = cmd!(
+        bao_exe(),
+        "diff",
+        &*hashes[0],
+        &outboard_paths[0],
+        &*hashes[1],
+        &outboard_paths[1]
+    )
+    .read()
+    .unwrap();
+    let start = 500_000 / 1024 * 1024;
+    assert_eq!(format!("0 1024\n{} 2048", start), output);
+    let output = cmd!(
+        bao_exe(),
+        "diff",
+        &*hashes[0],
+        &outboard_paths[0],
+        &*hashes[0],
+        &outboard_paths[0]
+    )
+    .read()
+    .unwrap();
+    assert_eq!("", output);
+
+    // Content of a single chunk has no parent nodes, and the hashes tell whether it changed.
+    let small_path = dir.path().join("small.obao");
+    cmd!(bao_exe(), "encode", "-", "--outboard", &small_path)
+        .stdin_bytes(&input[..100])
+        .run()
+        .unwrap();
+    let small_hash = blake3::hash(&input[..100]).to_hex();
+    let changed_hash = blake3::hash(&changed[..100]).to_hex();
+    let output = cmd!(
+        bao_exe(),
+        "diff",
+        &*small_hash,
+        &small_path,
+        &*small_hash,
+        &small_path
+    )
+    .read()
+    .unwrap();
+    assert_eq!("", output);
+    let output = cmd!(
+        bao_exe(),
+        "diff",
+        &*small_hash,
+        &small_path,
+        &*changed_hash,
+        &small_path
+    )
+    .read()
+    .unwrap();
+    assert_eq!("0 100", output);
+}
+
+#[test]
+fn test_serve_get() {
+    let input_len = 1_000_000;
+    let mut input = vec![0; input_len];
+    rand::thread_rng().fill_bytes(&mut input);
+    let dir = tempdir().unwrap();
+    let input_path = dir.path().join("input");
+    fs::write(&input_path, &input).unwrap();
+
+    // The server prints the hash of each input, and then its address.
+    let server = cmd!(bao_exe(), "serve", "127.0.0.1:0", &input_path)
+        .stdout_capture()
+        .reader()
+        .unwrap();
+    let mut lines = io::BufReader::new(&server).lines();
+    let hash_line = lines.next().unwrap().unwrap();
+    let hash = hash_line.split_whitespace().next().unwrap().to_string();
+    assert_eq!(&*blake3::hash(&input).to_hex(), hash);
+    let address_line = lines.next().unwrap().unwrap();
+    let address = address_line
+        .strip_prefix("listening on ")
+        .unwrap()
+        .to_string();
+
+    let output = cmd!(bao_exe(), "get", &address, &hash)
+        .stdout_capture()
+        .run()
+        .unwrap();
+    assert_eq!(input, output.stdout);
+    let output = cmd!(
+        bao_exe(),
+        "get",
+        &address,
+        &hash,
+        "--start=500000",
+        "--count=1000"
+    )
+    .stdout_capture()
+    .run()
+    .unwrap();
+    assert_eq!(&input[500_000..501_000], &*output.stdout);
+
+    // An unknown hash fails.
+    let unknown = blake3::hash(b"foo").to_hex().to_string();
+    let result = cmd!(bao_exe(), "get", &address, &unknown)
+        .stdout_null()
+        .stderr_null()
+        .unchecked()
+        .run()
+        .unwrap();
+    assert!(!result.status.success());
+    server.kill().unwrap();
+}
@@ -37,41 +37,669 @@
 
 use crate::encode;
 use crate::encode::NextRead;
-use crate::{Finalization, Hash, CHUNK_SIZE, HEADER_SIZE, MAX_DEPTH, PARENT_SIZE};
+use crate::outboard::{OutboardReader, OutboardStore, Subtree};
+use crate::{Finalization, Hash, Mode, CHUNK_SIZE, HEADER_SIZE, MAX_DEPTH, PARENT_SIZE};
 use arrayref::array_ref;
 use arrayvec::ArrayVec;
 use std::cmp;
+use std::collections::BTreeMap;
 use std::error;
 use std::fmt;
 use std::io;
 use std::io::prelude::*;
 use std::io::SeekFrom;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
 
 /// Decode an entire slice in the default combined mode into a bytes vector.
 /// This is a convenience wrapper around `Decoder`.
+///
+/// This function doesn't allocate more than the length of `encoded`, but it trusts the caller to
+/// have bounded that length. To decode untrusted input with explicit limits, see
+/// [`DecoderBuilder::decode`](struct.DecoderBuilder.html#method.decode).
 pub fn decode(encoded: impl AsRef<[u8]>, hash: &Hash) -> io::Result<Vec<u8>> {
-    let bytes = encoded.as_ref();
-    if bytes.len() < HEADER_SIZE {
+    DecoderBuilder::new().decode(encoded, hash)
+}
+
+/// Decode an entire slice in the default combined mode into a caller-provided buffer, returning
+/// the content length. The buffer must be at least that long.
+///
+/// Each chunk is verified in place in `encoded` before it's copied to `output`, so if decoding
+/// fails, `output` contains only verified chunks and whatever bytes it held before. With the
+/// `rayon` feature enabled, large encodings are verified on multiple threads. For the keyed_hash
+/// and derive_key modes, see
+/// [`DecoderBuilder::decode_into`](struct.DecoderBuilder.html#method.decode_into).
+pub fn decode_into(encoded: impl AsRef<[u8]>, hash: &Hash, output: &mut [u8]) -> io::Result<usize> {
+    DecoderBuilder::new().decode_into(encoded, hash, output)
+}
+
+/// Decode an entire slice in the default combined mode into a file, returning the content length.
+///
+/// The file is first truncated and zero-filled to the content length, and then each chunk is
+/// verified in place in `encoded` and written to its position in the file. If decoding fails, the
+/// file contains only verified chunks, with zeros elsewhere, even if it had other bytes before.
+/// With the `rayon` feature enabled, large encodings are verified and written on multiple threads.
+/// For the keyed_hash and derive_key modes, see
+/// [`DecoderBuilder::decode_to_file`](struct.DecoderBuilder.html#method.decode_to_file).
+#[cfg(any(unix, windows))]
+pub fn decode_to_file(
+    encoded: impl AsRef<[u8]>,
+    hash: &Hash,
+    output: &std::fs::File,
+) -> io::Result<u64> {
+    DecoderBuilder::new().decode_to_file(encoded, hash, output)
+}
+
+// Parse the length header of an in-memory combined encoding, and return the encoded tree that
+// follows it, which is checked to be long enough.
+fn split_encoding(encoded: &[u8]) -> io::Result<(&[u8], u64)> {
+    if encoded.len() < HEADER_SIZE {
         return Err(Error::Truncated.into());
     }
-    let content_len = crate::decode_len(array_ref!(bytes, 0, HEADER_SIZE));
-    // Sanity check the length before making a potentially large allocation.
-    if (bytes.len() as u128) < encode::encoded_size(content_len) {
+    let content_len = crate::decode_len(array_ref!(encoded, 0, HEADER_SIZE));
+    let tree_size = encode::encoded_subtree_size(content_len);
+    if ((encoded.len() - HEADER_SIZE) as u128) < tree_size {
         return Err(Error::Truncated.into());
     }
-    // There's no way to avoid zeroing this vector without unsafe code, because
-    // Decoder::initializer is the default (safe) zeroing implementation anyway.
-    let mut vec = vec![0; content_len as usize];
-    let mut reader = Decoder::new(bytes, hash);
-    reader.read_exact(&mut vec)?;
-    // One more read to confirm EOF. This is redundant in most cases, but in
-    // the empty encoding case read_exact won't do any reads at all, and the Ok
-    // return from this call will be the only thing that verifies the hash.
-    // Note that this will never hit the inner reader; we'll receive EOF from
-    // the VerifyState.
-    let n = reader.read(&mut [0])?;
-    debug_assert_eq!(n, 0, "must be EOF");
-    Ok(vec)
+    Ok((&encoded[HEADER_SIZE..][..tree_size as usize], content_len))
+}
+
+// Somewhere to put verified chunks, which can be split in two for the subtrees of a parent node.
+trait Destination: Send {
+    fn split(self, mid: u64) -> (Self, Self)
+    where
+        Self: Sized;
+
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()>;
+}
+
+impl Destination for &mut [u8] {
+    fn split(self, mid: u64) -> (Self, Self) {
+        self.split_at_mut(mid as usize)
+    }
+
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        self.copy_from_slice(chunk);
+        Ok(())
+    }
+}
+
+#[cfg(any(unix, windows))]
+struct FileDestination<'a> {
+    file: &'a std::fs::File,
+    offset: u64,
+}
+
+#[cfg(any(unix, windows))]
+impl Destination for FileDestination<'_> {
+    fn split(self, mid: u64) -> (Self, Self) {
+        let right = FileDestination {
+            file: self.file,
+            offset: self.offset + mid,
+        };
+        (self, right)
+    }
+
+    fn write_chunk(&mut self, chunk: &[u8]) -> io::Result<()> {
+        write_all_at(self.file, chunk, self.offset)
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn write_all_at(file: &std::fs::File, buf: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, buf, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn write_all_at(
+    file: &std::fs::File,
+    mut buf: &[u8],
+    mut offset: u64,
+) -> io::Result<()> {
+    while !buf.is_empty() {
+        match std::os::windows::fs::FileExt::seek_write(file, buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// Subtrees smaller than this aren't worth sending to another thread.
+#[cfg(feature = "rayon")]
+const PARALLEL_MIN_LEN: u64 = 64 * CHUNK_SIZE as u64;
+
+#[cfg(feature = "rayon")]
+fn join<A, B, RA, RB>(content_len: u64, a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA + Send,
+    B: FnOnce() -> RB + Send,
+    RA: Send,
+    RB: Send,
+{
+    if content_len >= PARALLEL_MIN_LEN {
+        rayon::join(a, b)
+    } else {
+        (a(), b())
+    }
+}
+
+#[cfg(not(feature = "rayon"))]
+fn join<A, B, RA, RB>(_content_len: u64, a: A, b: B) -> (RA, RB)
+where
+    A: FnOnce() -> RA,
+    B: FnOnce() -> RB,
+{
+    (a(), b())
+}
+
+// Something that verify_node reads the nodes of a tree from, and hands them back to once they're
+// verified. The decoders that verify subtrees recursively, rather than streaming them, all go
+// through this, so that there's one copy of the verification itself.
+pub(crate) trait VerifiedTree {
+    // Return the verified CVs of the children of this subtree, if they're already known.
+    fn cached_children(&self, _subtree: Subtree) -> Option<(Hash, Hash)> {
+        None
+    }
+
+    fn read_parent(&mut self, subtree: Subtree) -> io::Result<[u8; PARENT_SIZE]>;
+
+    // Called with each parent node after it's verified.
+    fn verified_parent(&mut self, _subtree: Subtree, _parent: &[u8; PARENT_SIZE]) {}
+
+    // Return true if this chunk has already been verified, so that it can be skipped.
+    fn chunk_is_verified(&self, _chunk_index: u64) -> bool {
+        false
+    }
+
+    // Fill `buf`, which is the size of the chunk.
+    fn read_chunk(&mut self, subtree: Subtree, buf: &mut [u8]) -> io::Result<()>;
+
+    // Called with each chunk after it's verified.
+    fn verified_chunk(&mut self, subtree: Subtree, chunk: &[u8]) -> io::Result<()>;
+}
+
+// Verify the root node of a subtree against its CV, which must already be verified. For a parent
+// node, return the children with their verified CVs. For a chunk, return None.
+pub(crate) fn verify_node(
+    tree: &mut impl VerifiedTree,
+    mode: &Mode,
+    content_len: u64,
+    subtree: Subtree,
+    cv: &Hash,
+    finalization: Finalization,
+) -> io::Result<Option<[(Subtree, Hash); 2]>> {
+    if subtree.num_chunks == 1 {
+        if tree.chunk_is_verified(subtree.start_chunk) {
+            return Ok(None);
+        }
+        let mut chunk = [0; CHUNK_SIZE];
+        let chunk = &mut chunk[..encode::chunk_size(subtree.start_chunk, content_len)];
+        tree.read_chunk(subtree, chunk)?;
+        let chunk_hash = mode.chunk_cv(subtree.start_chunk, chunk, finalization.is_root());
+        // Hash implements constant time equality.
+        if &chunk_hash != cv {
+            return Err(Error::HashMismatch.into());
+        }
+        tree.verified_chunk(subtree, chunk)?;
+        return Ok(None);
+    }
+    let (left_cv, right_cv) = match tree.cached_children(subtree) {
+        Some(cvs) => cvs,
+        None => {
+            let parent = tree.read_parent(subtree)?;
+            let (left_cv, right_cv) = crate::split_parent(&parent);
+            let computed = mode.parent_cv(&left_cv, &right_cv, finalization.is_root());
+            // Hash implements constant time equality.
+            if &computed != cv {
+                return Err(Error::HashMismatch.into());
+            }
+            tree.verified_parent(subtree, &parent);
+            (left_cv, right_cv)
+        }
+    };
+    let (left, right) = subtree.children();
+    Ok(Some([(left, left_cv), (right, right_cv)]))
+}
+
+// Verify the chunks of a subtree that overlap `chunks`, and the parent nodes above them, in
+// pre-order. The subtree's CV must already be verified.
+pub(crate) fn verify_subtrees(
+    tree: &mut impl VerifiedTree,
+    mode: &Mode,
+    content_len: u64,
+    subtree: Subtree,
+    cv: &Hash,
+    finalization: Finalization,
+    chunks: &Range<u64>,
+) -> io::Result<()> {
+    if !subtree.overlaps(chunks) {
+        return Ok(());
+    }
+    if let Some([(left, left_cv), (right, right_cv)]) =
+        verify_node(tree, mode, content_len, subtree, cv, finalization)?
+    {
+        let not_root = Finalization::NotRoot;
+        verify_subtrees(tree, mode, content_len, left, &left_cv, not_root, chunks)?;
+        verify_subtrees(tree, mode, content_len, right, &right_cv, not_root, chunks)?;
+    }
+    Ok(())
+}
+
+// The whole tree of a combined encoding in memory, after the header, and somewhere to put the
+// chunks of one of its subtrees.
+struct EncodedTree<'a, D> {
+    encoded: &'a [u8],
+    dest: D,
+}
+
+impl<D: Destination> VerifiedTree for EncodedTree<'_, D> {
+    fn read_parent(&mut self, subtree: Subtree) -> io::Result<[u8; PARENT_SIZE]> {
+        let offset = combined_offset(subtree) as usize;
+        Ok(*array_ref!(self.encoded, offset, PARENT_SIZE))
+    }
+
+    fn read_chunk(&mut self, subtree: Subtree, buf: &mut [u8]) -> io::Result<()> {
+        let offset = combined_offset(subtree) as usize;
+        buf.copy_from_slice(&self.encoded[offset..][..buf.len()]);
+        Ok(())
+    }
+
+    fn verified_chunk(&mut self, _subtree: Subtree, chunk: &[u8]) -> io::Result<()> {
+        self.dest.write_chunk(chunk)
+    }
+}
+
+// The offset of a subtree in a combined encoding, after the header. Everything before it in
+// pre-order is a parent node or a whole chunk.
+pub(crate) fn combined_offset(subtree: Subtree) -> u64 {
+    subtree.parent_index * PARENT_SIZE as u64 + subtree.start_chunk * CHUNK_SIZE as u64
+}
+
+// Recursively verify a subtree of a combined encoding that's entirely in memory, with the two
+// halves of large subtrees on different threads. Chunks are only handed to the destination after
+// they're verified.
+fn verify_encoded<D: Destination>(
+    encoded: &[u8],
+    mode: &Mode,
+    content_len: u64,
+    subtree: Subtree,
+    cv: &Hash,
+    finalization: Finalization,
+    dest: D,
+) -> io::Result<()> {
+    let mut tree = EncodedTree { encoded, dest };
+    let children = verify_node(&mut tree, mode, content_len, subtree, cv, finalization)?;
+    let [(left, left_cv), (right, right_cv)] = match children {
+        Some(children) => children,
+        None => return Ok(()),
+    };
+    let left_len = left.num_chunks * CHUNK_SIZE as u64;
+    let (left_dest, right_dest) = tree.dest.split(left_len);
+    let subtree_start = subtree.start_chunk * CHUNK_SIZE as u64;
+    let subtree_len = cmp::min(
+        content_len - subtree_start,
+        subtree.num_chunks * CHUNK_SIZE as u64,
+    );
+    let (left_result, right_result) = join(
+        subtree_len,
+        || {
+            verify_encoded(
+                encoded,
+                mode,
+                content_len,
+                left,
+                &left_cv,
+                Finalization::NotRoot,
+                left_dest,
+            )
+        },
+        || {
+            verify_encoded(
+                encoded,
+                mode,
+                content_len,
+                right,
+                &right_cv,
+                Finalization::NotRoot,
+                right_dest,
+            )
+        },
+    );
+    left_result.and(right_result)
+}
+
+// Size limits for untrusted encodings. These are checked against the length header, before the
+// header is fed to the VerifyState, so that no caller ever allocates or reads based on a length
+// that exceeds them.
+#[derive(Clone, Copy, Debug, Default)]
+struct Limits {
+    max_content_len: Option<u64>,
+    max_encoded_size: Option<u64>,
+}
+
+impl Limits {
+    fn check(&self, content_len: u64, encoded_size: u128) -> Result<(), Error> {
+        if let Some(max) = self.max_content_len {
+            if content_len > max {
+                return Err(Error::LimitExceeded);
+            }
+        }
+        if let Some(max) = self.max_encoded_size {
+            if encoded_size > max as u128 {
+                return Err(Error::LimitExceeded);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A builder for decoders that enforce size limits on untrusted encodings.
+///
+/// The length header at the front of an encoding isn't verified until the final chunk is read, so
+/// a malicious encoding can claim to be as large as it likes. The decoders produced by this
+/// builder check the claimed length against the configured limits as soon as they read the
+/// header, and return `Error::LimitExceeded` (converted to `ErrorKind::InvalidData`) before doing
+/// any allocation or reading based on that length. For slices, the encoded size is the size of the
+/// slice (see `encode::slice_size`), rather than the size of the whole encoding.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::io::prelude::*;
+///
+/// let (encoded, hash) = bao::encode::encode(b"some input");
+///
+/// let mut builder = bao::decode::DecoderBuilder::new();
+/// builder.max_content_len(1_000_000);
+/// assert_eq!(b"some input", &*builder.decode(&encoded, &hash)?);
+///
+/// builder.max_content_len(5);
+/// let mut decoder = builder.build(&*encoded, &hash);
+/// let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+/// assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DecoderBuilder {
+    limits: Limits,
+    cv_cache_capacity: Option<usize>,
+    mode: Mode,
+    chunk_group_log: u8,
+}
+
+impl DecoderBuilder {
+    /// Create a new `DecoderBuilder` with no limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject encodings whose length header claims more than `len` content bytes.
+    pub fn max_content_len(&mut self, len: u64) -> &mut Self {
+        self.limits.max_content_len = Some(len);
+        self
+    }
+
+    /// Reject encodings whose length header implies more than `size` encoded bytes. In outboard
+    /// mode, that includes both the input and the outboard encoding.
+    pub fn max_encoded_size(&mut self, size: u64) -> &mut Self {
+        self.limits.max_encoded_size = Some(size);
+        self
+    }
+
+    /// Let `Decoder` cache up to `capacity` verified subtree hashes, to speed up seeking.
+    ///
+    /// Without a cache, every leftward seek starts over at the root of the tree, re-reading and
+    /// re-verifying all the parent nodes on the way down to the target. With a cache, seeking
+    /// skips parent nodes whose subtree hashes have already been verified, and resumes from the
+    /// deepest cached subtree. Only hashes from verified parent nodes are cached. When the cache
+    /// is full, the smallest subtrees are evicted first. Each entry takes about 50 bytes, and a
+    /// capacity of twice the tree height (about 2 * log2 of the number of chunks) is enough to
+    /// cache the path to one position. `SliceDecoder` doesn't seek, and it ignores this setting.
+    /// `ReadAtDecoder` always has a cache, and this overrides its default capacity.
+    pub fn cv_cache_capacity(&mut self, capacity: usize) -> &mut Self {
+        self.cv_cache_capacity = Some(capacity);
+        self
+    }
+
+    /// Decode encodings made in the BLAKE3 keyed_hash mode with `key`, as by
+    /// [`Encoder::new_keyed`](../encode/struct.Encoder.html#method.new_keyed). This applies to
+    /// [`decode`](#method.decode) and to all the decoders this builder builds.
+    pub fn keyed(&mut self, key: &[u8; blake3::KEY_LEN]) -> &mut Self {
+        self.mode = Mode::KeyedHash(*key);
+        self
+    }
+
+    /// Decode encodings made in the BLAKE3 derive_key mode with `context`, as by
+    /// [`Encoder::new_derive_key`](../encode/struct.Encoder.html#method.new_derive_key). This
+    /// applies to [`decode`](#method.decode) and to all the decoders this builder builds.
+    pub fn derive_key(&mut self, context: &str) -> &mut Self {
+        self.mode = Mode::derive_key(context);
+        self
+    }
+
+    /// Decode encodings made with chunk groups of 2^`chunk_group_log` chunks, as by
+    /// [`Encoder::new_grouped`](../encode/struct.Encoder.html#method.new_grouped). This applies
+    /// to [`decode`](#method.decode), the `Decoder`s, and the `SliceDecoder`s this builder builds.
+    /// `ReadAtDecoder` doesn't support chunk groups, and `build_read_at` and
+    /// `build_read_at_outboard` return an error of kind `InvalidInput` when this is non-zero.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_group_log` is greater than
+    /// [`MAX_CHUNK_GROUP_LOG`](../encode/constant.MAX_CHUNK_GROUP_LOG.html).
+    pub fn chunk_group_log(&mut self, chunk_group_log: u8) -> &mut Self {
+        assert!(
+            chunk_group_log <= encode::MAX_CHUNK_GROUP_LOG,
+            "chunk_group_log is too large",
+        );
+        self.chunk_group_log = chunk_group_log;
+        self
+    }
+
+    /// Decode an entire slice in the combined mode into a bytes vector, like
+    /// [`decode`](fn.decode.html), but checking the limits before allocating.
+    pub fn decode(&self, encoded: impl AsRef<[u8]>, hash: &Hash) -> io::Result<Vec<u8>> {
+        let bytes = encoded.as_ref();
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::Truncated.into());
+        }
+        let content_len = crate::decode_len(array_ref!(bytes, 0, HEADER_SIZE));
+        let encoded_size = encode::encoded_size_grouped(content_len, self.chunk_group_log);
+        self.limits.check(content_len, encoded_size)?;
+        // Sanity check the length before making a potentially large allocation.
+        if (bytes.len() as u128) < encoded_size {
+            return Err(Error::Truncated.into());
+        }
+        // There's no way to avoid zeroing this vector without unsafe code, because
+        // Decoder::initializer is the default (safe) zeroing implementation anyway.
+        let mut vec = vec![0; content_len as usize];
+        let mut reader = self.build(bytes, hash);
+        reader.read_exact(&mut vec)?;
+        // One more read to confirm EOF. This is redundant in most cases, but in
+        // the empty encoding case read_exact won't do any reads at all, and the Ok
+        // return from this call will be the only thing that verifies the hash.
+        // Note that this will never hit the inner reader; we'll receive EOF from
+        // the VerifyState.
+        let n = reader.read(&mut [0])?;
+        debug_assert_eq!(n, 0, "must be EOF");
+        Ok(vec)
+    }
+
+    /// Decode an entire slice in the combined mode into a caller-provided buffer, like
+    /// [`decode_into`](fn.decode_into.html), but in this builder's hash mode and checking the
+    /// limits first. This doesn't support chunk groups, and it returns an error of kind
+    /// `InvalidInput` if `chunk_group_log` is non-zero.
+    pub fn decode_into(
+        &self,
+        encoded: impl AsRef<[u8]>,
+        hash: &Hash,
+        output: &mut [u8],
+    ) -> io::Result<usize> {
+        let (tree, content_len) = self.split_ungrouped_encoding(encoded.as_ref())?;
+        if (output.len() as u64) < content_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "output buffer too small",
+            ));
+        }
+        let output = &mut output[..content_len as usize];
+        verify_encoded(
+            tree,
+            &self.mode,
+            content_len,
+            Subtree::root(content_len),
+            hash,
+            Finalization::Root,
+            output,
+        )?;
+        Ok(content_len as usize)
+    }
+
+    /// Decode an entire slice in the combined mode into a file, like
+    /// [`decode_to_file`](fn.decode_to_file.html), but in this builder's hash mode and checking
+    /// the limits first. Like [`decode_into`](#method.decode_into), this doesn't support chunk
+    /// groups.
+    #[cfg(any(unix, windows))]
+    pub fn decode_to_file(
+        &self,
+        encoded: impl AsRef<[u8]>,
+        hash: &Hash,
+        output: &std::fs::File,
+    ) -> io::Result<u64> {
+        let (tree, content_len) = self.split_ungrouped_encoding(encoded.as_ref())?;
+        // Truncating first clears any old bytes, so that the file can't be mistaken for verified
+        // content if decoding fails.
+        output.set_len(0)?;
+        output.set_len(content_len)?;
+        let dest = FileDestination {
+            file: output,
+            offset: 0,
+        };
+        verify_encoded(
+            tree,
+            &self.mode,
+            content_len,
+            Subtree::root(content_len),
+            hash,
+            Finalization::Root,
+            dest,
+        )?;
+        Ok(content_len)
+    }
+
+    fn split_ungrouped_encoding<'a>(&self, encoded: &'a [u8]) -> io::Result<(&'a [u8], u64)> {
+        if self.chunk_group_log != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "decoding in place doesn't support chunk groups",
+            ));
+        }
+        if encoded.len() >= HEADER_SIZE {
+            let content_len = crate::decode_len(array_ref!(encoded, 0, HEADER_SIZE));
+            self.limits
+                .check(content_len, encode::encoded_size(content_len))?;
+        }
+        split_encoding(encoded)
+    }
+
+    /// Build a `Decoder` for the combined mode, like `Decoder::new`.
+    pub fn build<T: Read>(&self, inner: T, hash: &Hash) -> Decoder<T, T> {
+        let mut decoder = Decoder::new(inner, hash);
+        decoder.shared.set_group_log(self.chunk_group_log);
+        decoder.shared.limits = self.limits;
+        decoder.shared.state.mode = self.mode;
+        decoder.shared.state.cv_cache = self.cv_cache_capacity.map(CvCache::new);
+        decoder
+    }
+
+    /// Build a `Decoder` for the outboard mode, like `Decoder::new_outboard`.
+    pub fn build_outboard<T: Read, O: Read>(
+        &self,
+        inner: T,
+        outboard: O,
+        hash: &Hash,
+    ) -> Decoder<T, O> {
+        let mut decoder = Decoder::new_outboard(inner, outboard, hash);
+        decoder.shared.set_group_log(self.chunk_group_log);
+        decoder.shared.limits = self.limits;
+        decoder.shared.state.mode = self.mode;
+        decoder.shared.state.cv_cache = self.cv_cache_capacity.map(CvCache::new);
+        decoder
+    }
+
+    /// Build a `ReadAtDecoder` for the combined mode, like `ReadAtDecoder::new`.
+    pub fn build_read_at<T: ReadAt>(
+        &self,
+        inner: T,
+        hash: &Hash,
+    ) -> io::Result<ReadAtDecoder<T, T>> {
+        self.check_read_at_group_log()?;
+        ReadAtDecoder::from_parts(
+            inner,
+            None,
+            hash,
+            self.mode,
+            self.limits,
+            self.read_at_cv_cache_capacity(),
+        )
+    }
+
+    /// Build a `ReadAtDecoder` for the outboard mode, like `ReadAtDecoder::new_outboard`.
+    pub fn build_read_at_outboard<T: ReadAt, O: ReadAt>(
+        &self,
+        inner: T,
+        outboard: O,
+        hash: &Hash,
+    ) -> io::Result<ReadAtDecoder<T, O>> {
+        self.check_read_at_group_log()?;
+        ReadAtDecoder::from_parts(
+            inner,
+            Some(outboard),
+            hash,
+            self.mode,
+            self.limits,
+            self.read_at_cv_cache_capacity(),
+        )
+    }
+
+    fn check_read_at_group_log(&self) -> io::Result<()> {
+        if self.chunk_group_log != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "ReadAtDecoder doesn't support chunk groups",
+            ));
+        }
+        Ok(())
+    }
+
+    fn read_at_cv_cache_capacity(&self) -> usize {
+        self.cv_cache_capacity
+            .unwrap_or(DEFAULT_SHARED_CV_CACHE_CAPACITY)
+    }
+
+    /// Build a `SliceDecoder`, like `SliceDecoder::new`.
+    pub fn build_slice<T: Read>(
+        &self,
+        inner: T,
+        hash: &Hash,
+        slice_start: u64,
+        slice_len: u64,
+    ) -> SliceDecoder<T> {
+        let mut decoder = SliceDecoder::new(inner, hash, slice_start, slice_len);
+        decoder.shared.set_group_log(self.chunk_group_log);
+        decoder.shared.limits = self.limits;
+        decoder.shared.state.mode = self.mode;
+        decoder
+    }
 }
 
 // This incremental verifier layers on top of encode::ParseState, and supports
@@ -81,6 +709,10 @@ struct VerifyState {
     stack: ArrayVec<[Hash; MAX_DEPTH]>,
     parser: encode::ParseState,
     root_hash: Hash,
+    mode: Mode,
+    // Only the Decoder uses this. The SliceDecoder has to read every parent
+    // node in its input, so skipping any would be a bug.
+    cv_cache: Option<CvCache>,
 }
 
 impl VerifyState {
@@ -91,6 +723,8 @@ impl VerifyState {
             stack,
             parser: encode::ParseState::new(),
             root_hash: *hash,
+            mode: Mode::Hash,
+            cv_cache: None,
         }
     }
 
@@ -102,26 +736,83 @@ impl VerifyState {
         self.parser.read_next()
     }
 
-    fn seek_next(&self, seek_to: u64) -> encode::SeekBookkeeping {
-        self.parser.seek_next(seek_to)
+    fn seek_next(&self, seek_to: u64) -> SeekBookkeeping {
+        self.skip_cached_parents(self.parser.seek_next(seek_to))
+    }
+
+    // If the seek is about to read a parent node whose children are already
+    // in the CV cache, skip that read and keep going, for as many parents as
+    // we can. Leftward seeks reset to the root, so this is what lets them
+    // resume from the deepest cached subtree instead. This includes parents
+    // that a finished seek leaves for the following read, since the read path
+    // doesn't seek and can't skip anything.
+    fn skip_cached_parents(&self, mut bookkeeping: encode::SeekBookkeeping) -> SeekBookkeeping {
+        let mut cached_stack: Option<ArrayVec<[Hash; MAX_DEPTH]>> = None;
+        if let Some(cache) = &self.cv_cache {
+            while bookkeeping.parent_next() {
+                let (left_position, right_position) = bookkeeping.next_parent_children();
+                let (left_child, right_child) =
+                    match (cache.get(left_position), cache.get(right_position)) {
+                        (Some(left_child), Some(right_child)) => (*left_child, *right_child),
+                        _ => break,
+                    };
+                let stack = cached_stack.get_or_insert_with(|| {
+                    let mut stack = self.stack.clone();
+                    Self::apply_bookkeeping(&mut stack, &self.root_hash, &bookkeeping);
+                    stack
+                });
+                // This is the same as feed_parent, except that the children
+                // were verified when they went into the cache.
+                stack.pop();
+                stack.push(right_child);
+                stack.push(left_child);
+                bookkeeping.advance_parent();
+                // Continuing the seek might skip rightward over subtrees.
+                while stack.len() > bookkeeping.stack_depth() {
+                    stack.pop();
+                }
+            }
+        }
+        SeekBookkeeping {
+            inner: bookkeeping,
+            cached_stack,
+        }
     }
 
-    fn seek_bookkeeping_done(&mut self, bookkeeping: encode::SeekBookkeeping) -> encode::NextRead {
+    fn apply_bookkeeping(
+        stack: &mut ArrayVec<[Hash; MAX_DEPTH]>,
+        root_hash: &Hash,
+        bookkeeping: &encode::SeekBookkeeping,
+    ) {
         // Leftward seeks require resetting the stack to the beginning.
         if bookkeeping.reset_to_root() {
-            self.stack.clear();
-            self.stack.push(self.root_hash);
+            stack.clear();
+            stack.push(*root_hash);
         }
         // Rightward seeks require popping subtrees off the stack.
-        debug_assert!(self.stack.len() >= bookkeeping.stack_depth());
-        while self.stack.len() > bookkeeping.stack_depth() {
-            self.stack.pop();
+        debug_assert!(stack.len() >= bookkeeping.stack_depth());
+        while stack.len() > bookkeeping.stack_depth() {
+            stack.pop();
+        }
+    }
+
+    fn seek_bookkeeping_done(&mut self, bookkeeping: SeekBookkeeping) -> encode::NextRead {
+        if let Some(cached_stack) = bookkeeping.cached_stack {
+            debug_assert_eq!(cached_stack.len(), bookkeeping.inner.stack_depth());
+            self.stack = cached_stack;
+        } else {
+            Self::apply_bookkeeping(&mut self.stack, &self.root_hash, &bookkeeping.inner);
         }
-        self.parser.seek_bookkeeping_done(bookkeeping)
+        self.parser.seek_bookkeeping_done(bookkeeping.inner)
     }
 
-    fn len_next(&self) -> encode::LenNext {
-        self.parser.len_next()
+    fn len_next(&self) -> LenNext {
+        match self.parser.len_next() {
+            encode::LenNext::Seek(bookkeeping) => {
+                LenNext::Seek(Box::new(self.skip_cached_parents(bookkeeping)))
+            }
+            encode::LenNext::Len(len) => LenNext::Len(len),
+        }
     }
 
     fn feed_header(&mut self, header: &[u8; HEADER_SIZE]) {
@@ -134,18 +825,33 @@ impl VerifyState {
         let left_child: Hash = (*array_ref!(parent, 0, 32)).into();
         let right_child: Hash = (*array_ref!(parent, 32, 32)).into();
         let computed_hash: Hash =
-            blake3::guts::parent_cv(&left_child, &right_child, finalization.is_root());
+            self.mode
+                .parent_cv(&left_child, &right_child, finalization.is_root());
         // Hash implements constant time equality.
         if expected_hash != &computed_hash {
             return Err(Error::HashMismatch);
         }
+        // Only verified CVs ever go into the cache.
+        if let Some(cache) = &mut self.cv_cache {
+            let (left_position, right_position) = self.parser.next_parent_children();
+            cache.insert(left_position, left_child);
+            cache.insert(right_position, right_child);
+        }
         self.stack.pop();
-        self.stack.push(right_child.into());
-        self.stack.push(left_child.into());
+        self.stack.push(right_child);
+        self.stack.push(left_child);
         self.parser.advance_parent();
         Ok(())
     }
 
+    // Hash a chunk group, which is a single chunk by default. The index is in units of groups, as
+    // in NextRead::Chunk.
+    fn chunk_cv(&self, index: u64, chunk: &[u8], finalization: Finalization) -> Hash {
+        let chunk_index = index << self.parser.group_log();
+        self.mode
+            .subtree_cv(chunk_index, chunk, finalization.is_root())
+    }
+
     fn feed_chunk(&mut self, chunk_hash: &Hash) -> Result<(), Error> {
         let expected_hash = self.stack.last().expect("unexpectedly empty stack");
         // Hash implements constant time equality.
@@ -164,23 +870,128 @@ impl fmt::Debug for VerifyState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "VerifyState {{ stack_size: {}, parser: {:?} }}",
+            "VerifyState {{ stack_size: {}, parser: {:?}, cv_cache_size: {:?} }}",
             self.stack.len(), // *Only* the stack size, not the hashes themselves.
             self.parser,      // The parser state only reveals the content length.
+            self.cv_cache.as_ref().map(|cache| cache.cvs.len()),
         )
     }
 }
 
+// VerifyState's version of encode::SeekBookkeeping. If the CV cache let us
+// skip reading some parent nodes, this also carries the resulting stack.
+struct SeekBookkeeping {
+    inner: encode::SeekBookkeeping,
+    cached_stack: Option<ArrayVec<[Hash; MAX_DEPTH]>>,
+}
+
+enum LenNext {
+    Seek(Box<SeekBookkeeping>),
+    Len(u64),
+}
+
+// A bounded cache of verified subtree CVs, keyed by tree position (see
+// ParseState::next_parent_children). The keys are ordered by height first, so
+// that when the cache is full we evict the smallest subtrees. The subtrees near
+// the root, which every leftward seek passes through, stay cached.
+#[derive(Clone)]
+struct CvCache {
+    capacity: usize,
+    cvs: BTreeMap<(u8, u64), Hash>,
+}
+
+impl CvCache {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            cvs: BTreeMap::new(),
+        }
+    }
+
+    fn get(&self, (start_chunk, height): (u64, u8)) -> Option<&Hash> {
+        self.cvs.get(&(height, start_chunk))
+    }
+
+    fn insert(&mut self, (start_chunk, height): (u64, u8), cv: Hash) {
+        self.cvs.insert((height, start_chunk), cv);
+        while self.cvs.len() > self.capacity {
+            self.cvs.pop_first();
+        }
+    }
+}
+
+// The most shards in a SharedCvCache, and the least capacity of each one.
+const CV_CACHE_SHARDS: usize = 16;
+const CV_CACHE_MIN_SHARD_CAPACITY: usize = 1024;
+
+// A CvCache for decoders that are shared between threads, split into shards with a lock each, so
+// that threads working in different parts of the tree don't wait for each other. Neighboring
+// subtrees of the same height go to different shards. The total capacity is split between the
+// shards, and each one evicts its own smallest subtrees first.
+struct SharedCvCache {
+    shards: Vec<Mutex<CvCache>>,
+}
+
+impl SharedCvCache {
+    fn new(capacity: usize) -> Self {
+        let num_shards = (capacity / CV_CACHE_MIN_SHARD_CAPACITY).clamp(1, CV_CACHE_SHARDS);
+        let shards = (0..num_shards)
+            .map(|i| {
+                let extra = (i < capacity % num_shards) as usize;
+                Mutex::new(CvCache::new(capacity / num_shards + extra))
+            })
+            .collect();
+        Self { shards }
+    }
+
+    fn shard(&self, (start_chunk, height): (u64, u8)) -> std::sync::MutexGuard<'_, CvCache> {
+        let index = (start_chunk >> height) as usize % self.shards.len();
+        // The cache only ever holds verified CVs, so it's still safe to use if another thread
+        // panicked while holding the lock.
+        self.shards[index]
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Return the cached CVs of the children of a subtree, only if both are cached.
+    fn children(&self, subtree: Subtree) -> Option<(Hash, Hash)> {
+        let (left, right) = subtree.children();
+        let left_cv = *self.shard(cache_key(left)).get(cache_key(left))?;
+        let right_cv = *self.shard(cache_key(right)).get(cache_key(right))?;
+        Some((left_cv, right_cv))
+    }
+
+    // Cache the CVs of the children of a subtree, from its verified parent node.
+    fn insert_children(&self, subtree: Subtree, parent: &[u8; PARENT_SIZE]) {
+        let (left, right) = subtree.children();
+        let (left_cv, right_cv) = crate::split_parent(parent);
+        self.shard(cache_key(left)).insert(cache_key(left), left_cv);
+        self.shard(cache_key(right))
+            .insert(cache_key(right), right_cv);
+    }
+}
+
+fn cache_key(subtree: Subtree) -> (u64, u8) {
+    (
+        subtree.start_chunk,
+        encode::subtree_height(subtree.num_chunks),
+    )
+}
+
 /// Errors that can happen during decoding.
 ///
 /// Two errors are possible when decoding, apart from the usual IO issues: the content bytes might
 /// not have the right hash, or the encoding might not be as long as it's supposed to be. In
 /// `std::io::Read` interfaces where we have to return `std::io::Error`, these variants are
-/// converted to `ErrorKind::InvalidData` and `ErrorKind::UnexpectedEof` respectively.
+/// converted to `ErrorKind::InvalidData` and `ErrorKind::UnexpectedEof` respectively. Decoders
+/// built with a [`DecoderBuilder`](struct.DecoderBuilder.html) can also fail with
+/// `LimitExceeded`, which is converted to `ErrorKind::InvalidData`.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum Error {
     HashMismatch,
     Truncated,
+    LimitExceeded,
 }
 
 impl fmt::Display for Error {
@@ -188,6 +999,7 @@ impl fmt::Display for Error {
         match *self {
             Error::HashMismatch => write!(f, "hash mismatch"),
             Error::Truncated => write!(f, "truncated encoding"),
+            Error::LimitExceeded => write!(f, "encoding exceeds size limit"),
         }
     }
 }
@@ -199,6 +1011,9 @@ impl From<Error> for io::Error {
         match e {
             Error::HashMismatch => io::Error::new(io::ErrorKind::InvalidData, "hash mismatch"),
             Error::Truncated => io::Error::new(io::ErrorKind::UnexpectedEof, "truncated encoding"),
+            Error::LimitExceeded => {
+                io::Error::new(io::ErrorKind::InvalidData, "encoding exceeds size limit")
+            }
         }
     }
 }
@@ -209,9 +1024,14 @@ struct DecoderShared<T: Read, O: Read> {
     input: T,
     outboard: Option<O>,
     state: VerifyState,
-    buf: [u8; CHUNK_SIZE],
+    // Big enough for a chunk group, which is a single chunk by default.
+    buf: Vec<u8>,
     buf_start: usize,
     buf_end: usize,
+    limits: Limits,
+    // The slice start and length, if this is a SliceDecoder. This is only used to compute the
+    // encoded size for the limits check.
+    slice: Option<(u64, u64)>,
 }
 
 impl<T: Read, O: Read> DecoderShared<T, O> {
@@ -220,10 +1040,63 @@ impl<T: Read, O: Read> DecoderShared<T, O> {
             input,
             outboard,
             state: VerifyState::new(hash),
-            buf: [0; CHUNK_SIZE],
+            buf: vec![0; CHUNK_SIZE],
             buf_start: 0,
             buf_end: 0,
+            limits: Limits::default(),
+            slice: None,
+        }
+    }
+
+    // This is only valid before anything has been read.
+    fn set_group_log(&mut self, group_log: u8) {
+        assert!(
+            group_log <= encode::MAX_CHUNK_GROUP_LOG,
+            "chunk_group_log is too large",
+        );
+        self.state.parser = encode::ParseState::new_grouped(group_log);
+        self.buf = vec![0; encode::group_size(group_log) as usize];
+    }
+
+    fn checkpoint(&self) -> DecoderCheckpoint {
+        debug_assert_eq!(self.state.stack.len(), self.state.parser.stack_depth());
+        DecoderCheckpoint {
+            outboard: self.outboard.is_some(),
+            mode: self.state.mode,
+            root_hash: self.state.root_hash,
+            parser: self.state.parser.clone(),
+            stack: self.state.stack.clone(),
+            buffered: self.buf[self.buf_start..self.buf_end].to_vec(),
+        }
+    }
+
+    fn resume(
+        input: T,
+        outboard: Option<O>,
+        hash: &Hash,
+        checkpoint: &DecoderCheckpoint,
+    ) -> io::Result<Self> {
+        if checkpoint.outboard != outboard.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "checkpoint is for a different encoding mode",
+            ));
+        }
+        // Hash implements constant time equality.
+        if hash != &checkpoint.root_hash {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "checkpoint is for a different hash",
+            ));
         }
+        let mut shared = Self::new(input, outboard, hash);
+        shared.state.mode = checkpoint.mode;
+        shared.set_group_log(checkpoint.parser.group_log());
+        shared.state.parser = checkpoint.parser.clone();
+        shared.state.stack = checkpoint.stack.clone();
+        shared.buf[..checkpoint.buffered.len()].copy_from_slice(&checkpoint.buffered);
+        shared.buf_end = checkpoint.buffered.len();
+        Ok(shared)
     }
 
     fn adjusted_content_position(&self) -> u64 {
@@ -257,6 +1130,14 @@ impl<T: Read, O: Read> DecoderShared<T, O> {
         } else {
             self.input.read_exact(&mut header)?;
         }
+        let content_len = crate::decode_len(&header);
+        let group_log = self.state.parser.group_log();
+        let encoded_size = if let Some((slice_start, slice_len)) = self.slice {
+            encode::slice_size_grouped(content_len, slice_start, slice_len, group_log)
+        } else {
+            encode::encoded_size_grouped(content_len, group_log)
+        };
+        self.limits.check(content_len, encoded_size)?;
         self.state.feed_header(&header);
         Ok(())
     }
@@ -297,9 +1178,7 @@ impl<T: Read, O: Read> DecoderShared<T, O> {
         }
         let buf_slice = &mut self.buf[..size];
         self.input.read_exact(buf_slice)?;
-        let hash = blake3::guts::ChunkState::new(index)
-            .update(buf_slice)
-            .finalize(finalization.is_root());
+        let hash = self.state.chunk_cv(index, buf_slice, finalization);
         self.state.feed_chunk(&hash)?;
         self.buf_start = skip;
         self.buf_end = size;
@@ -359,9 +1238,7 @@ impl<T: Read, O: Read> DecoderShared<T, O> {
                     // Hash it and push its hash into the VerifyState. This
                     // returns an error if the hash is bad. Otherwise, the
                     // chunk is verifiied.
-                    let chunk_hash = blake3::guts::ChunkState::new(index)
-                        .update(read_buf)
-                        .finalize(finalization.is_root());
+                    let chunk_hash = self.state.chunk_cv(index, read_buf, finalization);
                     self.state.feed_chunk(&chunk_hash)?;
 
                     // If the output buffer was large enough for direct output,
@@ -379,6 +1256,35 @@ impl<T: Read, O: Read> DecoderShared<T, O> {
         }
     }
 
+    // Unlike read(), this always goes through the internal buffer, and only
+    // returns once the buffer has some verified bytes in it or we're at EOF.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.buf_len() == 0 {
+            match self.state.read_next() {
+                NextRead::Done => break,
+                NextRead::Header => self.get_and_feed_header()?,
+                NextRead::Parent => self.get_and_feed_parent()?,
+                NextRead::Chunk {
+                    size,
+                    finalization,
+                    skip,
+                    index,
+                } => self.buffer_verified_chunk(
+                    size,
+                    finalization,
+                    skip,
+                    index,
+                    0, /* parents_to_read */
+                )?,
+            }
+        }
+        Ok(&self.buf[self.buf_start..self.buf_end])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.buf_start = cmp::min(self.buf_start + amt, self.buf_end);
+    }
+
     // Returns Ok(true) to indicate the seek is finished. Note that both the
     // Decoder and the SliceDecoder will use this method (which doesn't depend on
     // io::Seek), but only the Decoder will call handle_seek_bookkeeping first.
@@ -415,15 +1321,13 @@ impl<T: Read + Seek, O: Read + Seek> DecoderShared<T, O> {
     // The Decoder will call this as part of seeking, but note that the
     // SliceDecoder won't, because all the seek bookkeeping has already been
     // taken care of during slice extraction.
-    fn handle_seek_bookkeeping(
-        &mut self,
-        bookkeeping: encode::SeekBookkeeping,
-    ) -> io::Result<NextRead> {
+    fn handle_seek_bookkeeping(&mut self, bookkeeping: SeekBookkeeping) -> io::Result<NextRead> {
         // The VerifyState handles all the subtree stack management. We just
         // need to handle the underlying seek. This is done differently
         // depending on whether the encoding is combined or outboard.
         if let Some(outboard) = &mut self.outboard {
-            if let Some((content_pos, outboard_pos)) = bookkeeping.underlying_seek_outboard() {
+            if let Some((content_pos, outboard_pos)) = bookkeeping.inner.underlying_seek_outboard()
+            {
                 // As with Decoder in the outboard case, the outboard extractor has to seek both of
                 // its inner readers. The content position of the state goes into the content
                 // reader, and the rest of the reported seek offset goes into the outboard reader.
@@ -431,7 +1335,7 @@ impl<T: Read + Seek, O: Read + Seek> DecoderShared<T, O> {
                 outboard.seek(SeekFrom::Start(outboard_pos))?;
             }
         } else {
-            if let Some(encoding_position) = bookkeeping.underlying_seek() {
+            if let Some(encoding_position) = bookkeeping.inner.underlying_seek() {
                 let position_u64: u64 = encode::cast_offset(encoding_position)?;
                 self.input.seek(SeekFrom::Start(position_u64))?;
             }
@@ -464,6 +1368,11 @@ impl<T: Read, O: Read> fmt::Debug for DecoderShared<T, O> {
 /// [`std::io::Seek`](https://doc.rust-lang.org/std/io/trait.Seek.html) if the
 /// underlying reader does, but it's also compatible with non-seekable readers.
 ///
+/// `Decoder` also implements
+/// [`std::io::BufRead`](https://doc.rust-lang.org/std/io/trait.BufRead.html),
+/// returning verified bytes directly from its internal chunk buffer, so
+/// there's no need to wrap it in a `BufReader`.
+///
 /// # Example
 ///
 /// ```
@@ -501,6 +1410,48 @@ impl<T: Read> Decoder<T, T> {
             shared: DecoderShared::new(inner, None, hash),
         }
     }
+
+    /// Resume decoding the combined mode from a checkpoint, as returned by
+    /// [`checkpoint`](#method.checkpoint). `inner` must be positioned at
+    /// [`DecoderCheckpoint::input_position`](struct.DecoderCheckpoint.html#method.input_position).
+    /// This returns an error of kind `InvalidInput` if the checkpoint is for the outboard mode or
+    /// a different hash.
+    pub fn resume(inner: T, hash: &Hash, checkpoint: &DecoderCheckpoint) -> io::Result<Self> {
+        Ok(Self {
+            shared: DecoderShared::resume(inner, None, hash, checkpoint)?,
+        })
+    }
+
+    /// Create a new `Decoder` for the combined mode, for an encoding made with
+    /// [`Encoder::new_grouped`](../encode/struct.Encoder.html#method.new_grouped) and the same
+    /// `chunk_group_log`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_group_log` is greater than
+    /// [`MAX_CHUNK_GROUP_LOG`](../encode/constant.MAX_CHUNK_GROUP_LOG.html).
+    pub fn new_grouped(inner: T, hash: &Hash, chunk_group_log: u8) -> Self {
+        let mut decoder = Self::new(inner, hash);
+        decoder.shared.set_group_log(chunk_group_log);
+        decoder
+    }
+
+    /// Create a new `Decoder` for the combined mode, for an encoding made with
+    /// [`Encoder::new_keyed`](../encode/struct.Encoder.html#method.new_keyed) and the same `key`.
+    pub fn new_keyed(inner: T, hash: &Hash, key: &[u8; blake3::KEY_LEN]) -> Self {
+        let mut decoder = Self::new(inner, hash);
+        decoder.shared.state.mode = Mode::KeyedHash(*key);
+        decoder
+    }
+
+    /// Create a new `Decoder` for the combined mode, for an encoding made with
+    /// [`Encoder::new_derive_key`](../encode/struct.Encoder.html#method.new_derive_key) and the
+    /// same `context`.
+    pub fn new_derive_key(inner: T, hash: &Hash, context: &str) -> Self {
+        let mut decoder = Self::new(inner, hash);
+        decoder.shared.state.mode = Mode::derive_key(context);
+        decoder
+    }
 }
 
 impl<T: Read, O: Read> Decoder<T, O> {
@@ -509,6 +1460,74 @@ impl<T: Read, O: Read> Decoder<T, O> {
             shared: DecoderShared::new(inner, Some(outboard), hash),
         }
     }
+
+    /// Resume decoding the outboard mode from a checkpoint, as returned by
+    /// [`checkpoint`](#method.checkpoint). `inner` and `outboard` must be positioned at
+    /// [`DecoderCheckpoint::input_position`](struct.DecoderCheckpoint.html#method.input_position)
+    /// and [`DecoderCheckpoint::outboard_position`](struct.DecoderCheckpoint.html#method.outboard_position).
+    /// This returns an error of kind `InvalidInput` if the checkpoint is for the combined mode or
+    /// a different hash.
+    pub fn resume_outboard(
+        inner: T,
+        outboard: O,
+        hash: &Hash,
+        checkpoint: &DecoderCheckpoint,
+    ) -> io::Result<Self> {
+        Ok(Self {
+            shared: DecoderShared::resume(inner, Some(outboard), hash, checkpoint)?,
+        })
+    }
+
+    /// Create a new `Decoder` for the outboard mode with chunk groups, like
+    /// [`new_grouped`](#method.new_grouped).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_group_log` is greater than
+    /// [`MAX_CHUNK_GROUP_LOG`](../encode/constant.MAX_CHUNK_GROUP_LOG.html).
+    pub fn new_outboard_grouped(inner: T, outboard: O, hash: &Hash, chunk_group_log: u8) -> Self {
+        let mut decoder = Self::new_outboard(inner, outboard, hash);
+        decoder.shared.set_group_log(chunk_group_log);
+        decoder
+    }
+
+    /// Create a new `Decoder` for the outboard mode with a key, like
+    /// [`new_keyed`](#method.new_keyed).
+    pub fn new_outboard_keyed(
+        inner: T,
+        outboard: O,
+        hash: &Hash,
+        key: &[u8; blake3::KEY_LEN],
+    ) -> Self {
+        let mut decoder = Self::new_outboard(inner, outboard, hash);
+        decoder.shared.state.mode = Mode::KeyedHash(*key);
+        decoder
+    }
+
+    /// Create a new `Decoder` for the outboard mode with a context string, like
+    /// [`new_derive_key`](#method.new_derive_key).
+    pub fn new_outboard_derive_key(inner: T, outboard: O, hash: &Hash, context: &str) -> Self {
+        let mut decoder = Self::new_outboard(inner, outboard, hash);
+        decoder.shared.state.mode = Mode::derive_key(context);
+        decoder
+    }
+
+    /// Capture how far the decoder has gotten, so that decoding can pick up there later, for
+    /// example after a transfer is interrupted. This is valid even after a read has failed, as
+    /// long as it wasn't a hash mismatch, since the decoder only makes progress on verified
+    /// input. The decoder's limits and CV cache aren't part of the checkpoint. Its hash mode is,
+    /// including the key for a keyed decoder, so a resumed decoder verifies the same way.
+    pub fn checkpoint(&self) -> DecoderCheckpoint {
+        self.shared.checkpoint()
+    }
+}
+
+impl<T: Read, S: OutboardStore> Decoder<T, OutboardReader<S>> {
+    /// Create a new `Decoder` for the outboard mode, which reads parent nodes from an
+    /// [`OutboardStore`](../outboard/trait.OutboardStore.html) instead of an outboard encoding.
+    pub fn new_outboard_store(inner: T, store: S, hash: &Hash) -> Self {
+        Self::new_outboard(inner, OutboardReader::new(store), hash)
+    }
 }
 
 impl<T: Read, O: Read> Read for Decoder<T, O> {
@@ -517,6 +1536,16 @@ impl<T: Read, O: Read> Read for Decoder<T, O> {
     }
 }
 
+impl<T: Read, O: Read> BufRead for Decoder<T, O> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.shared.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.shared.consume(amt);
+    }
+}
+
 impl<T: Read + Seek, O: Read + Seek> Seek for Decoder<T, O> {
     fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
         // Clear the internal buffer when seeking. The buffered bytes won't be
@@ -533,12 +1562,12 @@ impl<T: Read + Seek, O: Read + Seek> Seek for Decoder<T, O> {
                 // require as a seek loop of its own to verify the length.
                 let content_len = loop {
                     match self.shared.state.len_next() {
-                        encode::LenNext::Seek(bookkeeping) => {
-                            let next_read = self.shared.handle_seek_bookkeeping(bookkeeping)?;
+                        LenNext::Seek(bookkeeping) => {
+                            let next_read = self.shared.handle_seek_bookkeeping(*bookkeeping)?;
                             let done = self.shared.handle_seek_read(next_read)?;
                             debug_assert!(!done);
                         }
-                        encode::LenNext::Len(len) => break len,
+                        LenNext::Len(len) => break len,
                     }
                 };
                 add_offset(content_len, offset)?
@@ -568,7 +1597,7 @@ fn add_offset(position: u64, offset: i64) -> io::Result<u64> {
             io::ErrorKind::InvalidInput,
             "seek before beginning",
         ))
-    } else if sum > u64::max_value() as i128 {
+    } else if sum > u64::MAX as i128 {
         Err(io::Error::new(
             io::ErrorKind::InvalidInput,
             "seek target overflowed u64",
@@ -578,6 +1607,168 @@ fn add_offset(position: u64, offset: i64) -> io::Result<u64> {
     }
 }
 
+// Serialized decoder checkpoints start with these, like encoder checkpoints.
+const DECODER_CHECKPOINT_MAGIC: [u8; 4] = *b"baoD";
+const DECODER_CHECKPOINT_VERSION: u8 = 1;
+
+/// A snapshot of a [`Decoder`](struct.Decoder.html), as returned by
+/// [`Decoder::checkpoint`](struct.Decoder.html#method.checkpoint).
+///
+/// A checkpoint holds the decoder's position, its parser state, the stack of subtree hashes it
+/// has already verified, and any verified bytes it has buffered but not yet returned. Resuming
+/// from a checkpoint with [`Decoder::resume`](struct.Decoder.html#method.resume) or
+/// [`Decoder::resume_outboard`](struct.Decoder.html#method.resume_outboard) verifies everything
+/// that comes after it against those hashes, so a resumed transfer doesn't have to trust any new
+/// input. It does have to trust the checkpoint itself, though. Anyone who can modify a checkpoint
+/// can make the resumed decoder accept anything, so keep it somewhere at least as safe as the
+/// output it describes. A checkpoint from a decoder created with a key or a context string also
+/// holds the key, or the key derived from the context string, so keep that one secret too.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use std::io::prelude::*;
+///
+/// let input = vec![0; 1_000_000];
+/// let (encoded, hash) = bao::encode::encode(&input);
+///
+/// // Decode part of the encoding, and take a checkpoint.
+/// let mut decoder = bao::decode::Decoder::new(&encoded[..100_000], &hash);
+/// let mut output = vec![0; 50_000];
+/// decoder.read_exact(&mut output)?;
+/// let checkpoint = decoder.checkpoint().serialize();
+///
+/// // Later, resume with a reader that starts where the last one stopped.
+/// let checkpoint = bao::decode::DecoderCheckpoint::deserialize(&checkpoint)?;
+/// let position = checkpoint.input_position() as usize;
+/// let mut decoder = bao::decode::Decoder::resume(&encoded[position..], &hash, &checkpoint)?;
+/// decoder.read_to_end(&mut output)?;
+/// assert_eq!(input, output);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct DecoderCheckpoint {
+    outboard: bool,
+    mode: Mode,
+    root_hash: Hash,
+    parser: encode::ParseState,
+    stack: ArrayVec<[Hash; MAX_DEPTH]>,
+    buffered: Vec<u8>,
+}
+
+impl DecoderCheckpoint {
+    /// The content position of the decoder, which is where reading resumes.
+    pub fn content_position(&self) -> u64 {
+        self.parser.content_position() - self.buffered.len() as u64
+    }
+
+    /// The position the underlying reader must be at to resume. This is an offset in the combined
+    /// encoding in the combined mode, or an offset in the content in the outboard mode.
+    pub fn input_position(&self) -> u64 {
+        if self.outboard {
+            self.parser.underlying_position_outboard().0
+        } else {
+            // Only a checkpoint from a position beyond u64::MAX in the encoding could truncate
+            // here, and the decoder would've failed to seek there.
+            self.parser.underlying_position() as u64
+        }
+    }
+
+    /// The position the outboard reader must be at to resume, or `None` in the combined mode.
+    pub fn outboard_position(&self) -> Option<u64> {
+        if self.outboard {
+            Some(self.parser.underlying_position_outboard().1 as u64)
+        } else {
+            None
+        }
+    }
+
+    /// Serialize the checkpoint. The format is the 4 magic bytes `baoD` and a version byte,
+    /// currently 1, then a flag byte for the outboard mode, a byte for the hash mode (0 for the
+    /// regular hash, 1 for keyed_hash, and 2 for derive_key, followed by the 32-byte key or
+    /// derived context key for the last two), the 32-byte root hash, the parser state, a count
+    /// byte and that many 32-byte subtree hashes, and a 4-byte little-endian length followed by
+    /// the buffered bytes.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = DECODER_CHECKPOINT_MAGIC.to_vec();
+        bytes.push(DECODER_CHECKPOINT_VERSION);
+        bytes.push(self.outboard as u8);
+        self.mode.serialize(&mut bytes);
+        bytes.extend_from_slice(self.root_hash.as_bytes());
+        bytes.extend_from_slice(&self.parser.serialize());
+        bytes.push(self.stack.len() as u8);
+        for hash in &self.stack {
+            bytes.extend_from_slice(hash.as_bytes());
+        }
+        bytes.extend_from_slice(&(self.buffered.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.buffered);
+        bytes
+    }
+
+    /// Deserialize the output of [`serialize`](#method.serialize). This returns an error of kind
+    /// `InvalidData` if the bytes aren't a well-formed checkpoint, including one from a different
+    /// format version.
+    pub fn deserialize(bytes: &[u8]) -> io::Result<Self> {
+        let mut bytes = crate::ByteReader::new(bytes, "invalid decoder checkpoint");
+        if bytes.take(DECODER_CHECKPOINT_MAGIC.len())? != DECODER_CHECKPOINT_MAGIC
+            || bytes.byte()? != DECODER_CHECKPOINT_VERSION
+        {
+            return Err(bytes.invalid());
+        }
+        let outboard = match bytes.byte()? {
+            0 => false,
+            1 => true,
+            _ => return Err(bytes.invalid()),
+        };
+        let mode = bytes.mode()?;
+        let root_hash = bytes.hash()?;
+        const PARSER_SIZE: usize = encode::ParseState::SERIALIZED_SIZE;
+        let parser =
+            encode::ParseState::deserialize(array_ref!(bytes.take(PARSER_SIZE)?, 0, PARSER_SIZE))
+                .ok_or_else(|| bytes.invalid())?;
+        // The verified stack always has one hash per level of the parser's stack.
+        let stack_len = bytes.byte()? as usize;
+        if stack_len != parser.stack_depth() {
+            return Err(bytes.invalid());
+        }
+        let mut stack = ArrayVec::new();
+        for _ in 0..stack_len {
+            stack.push(bytes.hash()?);
+        }
+        // At most one chunk group is buffered.
+        let buffered_len = u32::from_le_bytes(*array_ref!(bytes.take(4)?, 0, 4)) as u64;
+        if buffered_len > encode::group_size(parser.group_log())
+            || buffered_len > parser.content_position()
+        {
+            return Err(bytes.invalid());
+        }
+        let buffered = bytes.take(buffered_len as usize)?.to_vec();
+        bytes.finish()?;
+        Ok(Self {
+            outboard,
+            mode,
+            root_hash,
+            parser,
+            stack,
+            buffered,
+        })
+    }
+}
+
+impl fmt::Debug for DecoderCheckpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing hashes or content, they might be secret.
+        write!(
+            f,
+            "DecoderCheckpoint {{ outboard: {}, content_position: {}, ... }}",
+            self.outboard,
+            self.content_position(),
+        )
+    }
+}
+
 /// An incremental slice decoder. This reads and verifies the output of the
 /// [`SliceExtractor`](../encode/struct.SliceExtractor.html).
 ///
@@ -585,6 +1776,9 @@ fn add_offset(position: u64, offset: i64) -> io::Result<u64> {
 /// the content bytes and tree nodes intermixed, as in the combined encoding
 /// mode.
 ///
+/// Like `Decoder`, `SliceDecoder` implements
+/// [`std::io::BufRead`](https://doc.rust-lang.org/std/io/trait.BufRead.html).
+///
 /// # Example
 ///
 /// ```
@@ -635,21 +1829,79 @@ pub struct SliceDecoder<T: Read> {
 
 impl<T: Read> SliceDecoder<T> {
     pub fn new(inner: T, hash: &Hash, slice_start: u64, slice_len: u64) -> Self {
+        let mut shared = DecoderShared::new(inner, None, hash);
+        shared.slice = Some((slice_start, slice_len));
         Self {
-            shared: DecoderShared::new(inner, None, hash),
+            shared,
             slice_start,
             slice_remaining: slice_len,
             need_fake_read: slice_len == 0,
         }
     }
+
+    /// Create a new `SliceDecoder` for a slice of an encoding made with
+    /// [`Encoder::new_grouped`](../encode/struct.Encoder.html#method.new_grouped), extracted with
+    /// [`SliceExtractor::new_grouped`](../encode/struct.SliceExtractor.html#method.new_grouped)
+    /// and the same `chunk_group_log`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_group_log` is greater than
+    /// [`MAX_CHUNK_GROUP_LOG`](../encode/constant.MAX_CHUNK_GROUP_LOG.html).
+    pub fn new_grouped(
+        inner: T,
+        hash: &Hash,
+        slice_start: u64,
+        slice_len: u64,
+        chunk_group_log: u8,
+    ) -> Self {
+        let mut decoder = Self::new(inner, hash, slice_start, slice_len);
+        decoder.shared.set_group_log(chunk_group_log);
+        decoder
+    }
+
+    /// Create a new `SliceDecoder` for a slice of an encoding made with
+    /// [`Encoder::new_keyed`](../encode/struct.Encoder.html#method.new_keyed) and the same `key`.
+    pub fn new_keyed(
+        inner: T,
+        hash: &Hash,
+        key: &[u8; blake3::KEY_LEN],
+        slice_start: u64,
+        slice_len: u64,
+    ) -> Self {
+        let mut decoder = Self::new(inner, hash, slice_start, slice_len);
+        decoder.shared.state.mode = Mode::KeyedHash(*key);
+        decoder
+    }
+
+    /// Create a new `SliceDecoder` for a slice of an encoding made with
+    /// [`Encoder::new_derive_key`](../encode/struct.Encoder.html#method.new_derive_key) and the
+    /// same `context`.
+    pub fn new_derive_key(
+        inner: T,
+        hash: &Hash,
+        context: &str,
+        slice_start: u64,
+        slice_len: u64,
+    ) -> Self {
+        let mut decoder = Self::new(inner, hash, slice_start, slice_len);
+        decoder.shared.state.mode = Mode::derive_key(context);
+        decoder
+    }
 }
 
-impl<T: Read> Read for SliceDecoder<T> {
-    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
-        // If we haven't done the initial seek yet, do the full seek loop
-        // first. Note that this will never leave any buffered output. The only
-        // scenario where handle_seek_read reads a chunk is if it needs to
-        // validate the final chunk, and then it skips the whole thing.
+impl<T: Read> SliceDecoder<T> {
+    // The AsyncClient in the protocol module refills its input from a stream between reads.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn inner_mut(&mut self) -> &mut T {
+        &mut self.shared.input
+    }
+
+    // If we haven't done the initial seek yet, do the full seek loop. Note
+    // that this will never leave any buffered output. The only scenario where
+    // handle_seek_read reads a chunk is if it needs to validate the final
+    // chunk, and then it skips the whole thing.
+    fn finish_initial_seek(&mut self) -> io::Result<()> {
         if self.shared.state.content_position() < self.slice_start {
             loop {
                 let bookkeeping = self.shared.state.seek_next(self.slice_start);
@@ -665,70 +1917,1207 @@ impl<T: Read> Read for SliceDecoder<T> {
             }
             debug_assert_eq!(0, self.shared.buf_len());
         }
+        Ok(())
+    }
+
+    fn do_fake_read(&mut self) -> io::Result<()> {
+        // Read one byte and throw it away, just to verify a chunk.
+        self.shared.read(&mut [0])?;
+        self.need_fake_read = false;
+        Ok(())
+    }
+}
+
+impl<T: Read> Read for SliceDecoder<T> {
+    fn read(&mut self, output: &mut [u8]) -> io::Result<usize> {
+        self.finish_initial_seek()?;
+
+        // We either just finished the seek (if any), or already did it during
+        // a previous call. Continue the read. Cap the output buffer to be at
+        // most the slice bytes remaining.
+        if self.need_fake_read {
+            self.do_fake_read()?;
+            Ok(0)
+        } else {
+            let cap = cmp::min(self.slice_remaining, output.len() as u64) as usize;
+            let capped_output = &mut output[..cap];
+            let n = self.shared.read(capped_output)?;
+            self.slice_remaining -= n as u64;
+            Ok(n)
+        }
+    }
+}
+
+impl<T: Read> BufRead for SliceDecoder<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.finish_initial_seek()?;
+        if self.need_fake_read {
+            self.do_fake_read()?;
+        }
+        // As with read(), don't go past the end of the slice. The extractor
+        // doesn't include any chunks after that.
+        if self.slice_remaining == 0 {
+            return Ok(&[]);
+        }
+        let buf = self.shared.fill_buf()?;
+        let cap = cmp::min(self.slice_remaining, buf.len() as u64) as usize;
+        Ok(&buf[..cap])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let available = cmp::min(self.slice_remaining, self.shared.buf_len() as u64);
+        let amt = cmp::min(available, amt as u64) as usize;
+        self.shared.consume(amt);
+        self.slice_remaining -= amt as u64;
+    }
+}
+
+/// Iterate over the verified chunks of a combined encoding that's entirely in memory, without
+/// copying them.
+///
+/// Each item is the content offset of a chunk together with a slice of `encoded` containing its
+/// bytes. Every chunk is verified before it's returned. If verification fails, the iterator yields
+/// the error and then stops.
+///
+/// Only the regular hash mode is supported. Encodings made with a key or a context string don't
+/// verify here, and they need a [`Decoder`](struct.Decoder.html) from
+/// [`Decoder::new_keyed`](struct.Decoder.html#method.new_keyed) or
+/// [`DecoderBuilder`](struct.DecoderBuilder.html) instead.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let input = vec![0; 10_000];
+/// let (encoded, hash) = bao::encode::encode(&input);
+///
+/// let mut total = 0;
+/// for item in bao::decode::chunks(&encoded, &hash) {
+///     let (offset, chunk) = item?;
+///     assert_eq!(total, offset);
+///     total += chunk.len() as u64;
+/// }
+/// assert_eq!(input.len() as u64, total);
+/// # Ok(())
+/// # }
+/// ```
+pub fn chunks<'a>(encoded: &'a [u8], hash: &Hash) -> Chunks<'a> {
+    Chunks {
+        tree: encoded,
+        input: None,
+        tree_position: 0,
+        state: VerifyState::new(hash),
+        finished: false,
+    }
+}
+
+/// Iterate over the verified chunks of an input and its outboard encoding, both entirely in
+/// memory, without copying them. The yielded slices borrow from `input`. See
+/// [`chunks`](fn.chunks.html).
+pub fn chunks_outboard<'a>(input: &'a [u8], outboard: &'a [u8], hash: &Hash) -> Chunks<'a> {
+    Chunks {
+        tree: outboard,
+        input: Some(input),
+        tree_position: 0,
+        state: VerifyState::new(hash),
+        finished: false,
+    }
+}
+
+/// An iterator over verified chunks, returned by [`chunks`](fn.chunks.html) and
+/// [`chunks_outboard`](fn.chunks_outboard.html).
+#[derive(Clone)]
+pub struct Chunks<'a> {
+    // The combined encoding, or the outboard encoding if `input` is present.
+    tree: &'a [u8],
+    input: Option<&'a [u8]>,
+    tree_position: usize,
+    state: VerifyState,
+    finished: bool,
+}
+
+impl<'a> Chunks<'a> {
+    fn take_tree_bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        let tree: &'a [u8] = self.tree;
+        if tree.len() - self.tree_position < len {
+            return Err(Error::Truncated);
+        }
+        let bytes = &tree[self.tree_position..][..len];
+        self.tree_position += len;
+        Ok(bytes)
+    }
+
+    fn next_inner(&mut self) -> Result<Option<(u64, &'a [u8])>, Error> {
+        loop {
+            match self.state.read_next() {
+                NextRead::Done => return Ok(None),
+                NextRead::Header => {
+                    let header = self.take_tree_bytes(HEADER_SIZE)?;
+                    let header = array_ref!(header, 0, HEADER_SIZE);
+                    if let Some(input) = self.input {
+                        if (input.len() as u64) < crate::decode_len(header) {
+                            return Err(Error::Truncated);
+                        }
+                    }
+                    self.state.feed_header(header);
+                }
+                NextRead::Parent => {
+                    let parent = self.take_tree_bytes(PARENT_SIZE)?;
+                    self.state.feed_parent(array_ref!(parent, 0, PARENT_SIZE))?;
+                }
+                NextRead::Chunk {
+                    size,
+                    finalization,
+                    skip,
+                    index,
+                } => {
+                    debug_assert_eq!(0, skip, "we never seek");
+                    let offset = index * CHUNK_SIZE as u64;
+                    let chunk = if let Some(input) = self.input {
+                        // The length was checked against the header above.
+                        &input[offset as usize..][..size]
+                    } else {
+                        self.take_tree_bytes(size)?
+                    };
+                    let chunk_hash = self
+                        .state
+                        .mode
+                        .chunk_cv(index, chunk, finalization.is_root());
+                    self.state.feed_chunk(&chunk_hash)?;
+                    // The only empty chunk is the whole empty input. It still needs to be
+                    // verified, but there's nothing to return.
+                    if size > 0 {
+                        return Ok(Some((offset, chunk)));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = io::Result<(u64, &'a [u8])>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        match self.next_inner() {
+            Ok(Some(item)) => Some(Ok(item)),
+            Ok(None) => {
+                self.finished = true;
+                None
+            }
+            Err(e) => {
+                self.finished = true;
+                Some(Err(e.into()))
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for Chunks<'_> {}
+
+impl fmt::Debug for Chunks<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Chunks {{ is_outboard: {}, tree_position: {}, state: {:?}, finished: {} }}",
+            self.input.is_some(),
+            self.tree_position,
+            self.state,
+            self.finished,
+        )
+    }
+}
+
+/// A random-access view of an input and its outboard encoding, both entirely in memory, which
+/// verifies content on demand.
+///
+/// This is intended for memory-mapped files, though any `AsRef<[u8]>` will do. Each call to
+/// [`get`](#method.get) verifies only the chunks that the requested range covers, and only if
+/// they haven't been verified already. Verified chunks are remembered in a bitmap, and verified
+/// subtree hashes are cached, so that verifying a new chunk only reads the parent nodes below the
+/// nearest verified subtree. Reading a range that's already been verified doesn't do any hashing
+/// at all. The cache is bounded like the one in [`ReadAtDecoder`](struct.ReadAtDecoder.html), and
+/// when it's full, the smallest subtrees are evicted first.
+///
+/// `VerifiedMmap` is `Sync` if its buffers are, and it can be shared between threads.
+///
+/// Only the regular hash mode is supported. For encodings made with a key or a context string,
+/// use a [`ReadAtDecoder`](struct.ReadAtDecoder.html) from
+/// [`DecoderBuilder::build_read_at_outboard`](struct.DecoderBuilder.html#method.build_read_at_outboard)
+/// instead.
+///
+/// Note that verification can't protect against memory that changes after it's verified, for
+/// example a memory-mapped file that another process modifies.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let input = vec![0xab; 1_000_000];
+/// let (outboard, hash) = bao::encode::outboard(&input);
+///
+/// let verified = bao::decode::VerifiedMmap::new(&input, &outboard, &hash)?;
+/// assert_eq!(&input[500_000..500_100], verified.get(500_000..500_100)?);
+/// assert_eq!(input.len() as u64, verified.len()?);
+/// # Ok(())
+/// # }
+/// ```
+pub struct VerifiedMmap<I: AsRef<[u8]>, O: AsRef<[u8]>> {
+    input: I,
+    outboard: O,
+    content_len: u64,
+    root_hash: Hash,
+    // One bit per chunk. These are only ever set, after the chunk is verified.
+    verified_chunks: Vec<AtomicU64>,
+    verified_cvs: SharedCvCache,
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> VerifiedMmap<I, O> {
+    /// Create a new `VerifiedMmap`. This reads the length header from the outboard encoding and
+    /// checks that both buffers are long enough, but it doesn't verify anything yet.
+    pub fn new(input: I, outboard: O, hash: &Hash) -> io::Result<Self> {
+        let outboard_bytes = outboard.as_ref();
+        if outboard_bytes.len() < HEADER_SIZE {
+            return Err(Error::Truncated.into());
+        }
+        let content_len = crate::decode_len(array_ref!(outboard_bytes, 0, HEADER_SIZE));
+        if (outboard_bytes.len() as u128) < encode::outboard_size(content_len)
+            || (input.as_ref().len() as u64) < content_len
+        {
+            return Err(Error::Truncated.into());
+        }
+        let words = encode::count_chunks(content_len).div_ceil(64);
+        Ok(Self {
+            input,
+            outboard,
+            content_len,
+            root_hash: *hash,
+            verified_chunks: (0..words).map(|_| AtomicU64::new(0)).collect(),
+            verified_cvs: SharedCvCache::new(DEFAULT_SHARED_CV_CACHE_CAPACITY),
+        })
+    }
+
+    /// Return the verified length of the content. This verifies the final chunk, if it hasn't
+    /// been verified already.
+    pub fn len(&self) -> io::Result<u64> {
+        let final_chunk = encode::count_chunks(self.content_len) - 1;
+        self.verify_chunks(final_chunk, final_chunk)?;
+        Ok(self.content_len)
+    }
+
+    /// Return `true` if the verified length of the content is zero.
+    pub fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Return the content bytes in `range`, verifying them first if necessary.
+    ///
+    /// If the range extends past the end of the content, this verifies the final chunk (so that
+    /// the length is known to be correct) and then returns an error of kind `InvalidInput`.
+    pub fn get(&self, range: Range<u64>) -> io::Result<&[u8]> {
+        if range.start > range.end || range.end > self.content_len {
+            // Don't trust the length header until we've verified the final chunk.
+            self.len()?;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "range out of bounds",
+            ));
+        }
+        if range.start < range.end {
+            let first_chunk = range.start / CHUNK_SIZE as u64;
+            let last_chunk = (range.end - 1) / CHUNK_SIZE as u64;
+            self.verify_chunks(first_chunk, last_chunk)?;
+        }
+        Ok(&self.input.as_ref()[range.start as usize..range.end as usize])
+    }
+
+    /// Return the input and outboard buffers.
+    pub fn into_inner(self) -> (I, O) {
+        (self.input, self.outboard)
+    }
+
+    fn chunk_is_verified(&self, chunk_index: u64) -> bool {
+        let word = self.verified_chunks[(chunk_index / 64) as usize].load(Ordering::Acquire);
+        word & (1 << (chunk_index % 64)) != 0
+    }
+
+    fn set_chunk_verified(&self, chunk_index: u64) {
+        let word = &self.verified_chunks[(chunk_index / 64) as usize];
+        word.fetch_or(1 << (chunk_index % 64), Ordering::Release);
+    }
+
+    fn verify_chunks(&self, first_chunk: u64, last_chunk: u64) -> io::Result<()> {
+        // The fast path: everything has already been verified.
+        if (first_chunk..=last_chunk).all(|chunk| self.chunk_is_verified(chunk)) {
+            return Ok(());
+        }
+        verify_subtrees(
+            &mut &*self,
+            &Mode::Hash,
+            self.content_len,
+            Subtree::root(self.content_len),
+            &self.root_hash,
+            Finalization::Root,
+            &(first_chunk..last_chunk + 1),
+        )
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> VerifiedTree for &VerifiedMmap<I, O> {
+    fn cached_children(&self, subtree: Subtree) -> Option<(Hash, Hash)> {
+        self.verified_cvs.children(subtree)
+    }
+
+    fn read_parent(&mut self, subtree: Subtree) -> io::Result<[u8; PARENT_SIZE]> {
+        let offset = HEADER_SIZE + subtree.parent_index as usize * PARENT_SIZE;
+        Ok(*array_ref!(self.outboard.as_ref(), offset, PARENT_SIZE))
+    }
+
+    fn verified_parent(&mut self, subtree: Subtree, parent: &[u8; PARENT_SIZE]) {
+        self.verified_cvs.insert_children(subtree, parent);
+    }
+
+    fn chunk_is_verified(&self, chunk_index: u64) -> bool {
+        VerifiedMmap::chunk_is_verified(self, chunk_index)
+    }
+
+    fn read_chunk(&mut self, subtree: Subtree, buf: &mut [u8]) -> io::Result<()> {
+        let offset = subtree.start_chunk as usize * CHUNK_SIZE;
+        buf.copy_from_slice(&self.input.as_ref()[offset..][..buf.len()]);
+        Ok(())
+    }
+
+    fn verified_chunk(&mut self, subtree: Subtree, _chunk: &[u8]) -> io::Result<()> {
+        self.set_chunk_verified(subtree.start_chunk);
+        Ok(())
+    }
+}
+
+impl<I: AsRef<[u8]>, O: AsRef<[u8]>> fmt::Debug for VerifiedMmap<I, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing hashes, they might be secret.
+        write!(
+            f,
+            "VerifiedMmap {{ content_len: {}, ... }}",
+            self.content_len
+        )
+    }
+}
+
+/// Positional reads, which don't take `&mut self` or move a cursor, so that many threads can read
+/// from the same source at once.
+///
+/// This is like `std::os::unix::fs::FileExt::read_at`. It's implemented for `File` on Unix and
+/// Windows, for byte slices and vectors, and for [`ReadAtDecoder`](struct.ReadAtDecoder.html)
+/// itself.
+pub trait ReadAt {
+    /// Read some bytes starting at `offset` into `buf`, returning the number of bytes read. A
+    /// return value of 0 means EOF, unless `buf` is empty.
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+
+    /// Read exactly `buf.len()` bytes starting at `offset`, or return an error of kind
+    /// `UnexpectedEof`.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    ))
+                }
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ReadAt for [u8] {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.len() as u64 {
+            return Ok(0);
+        }
+        let available = &self[offset as usize..];
+        let n = cmp::min(buf.len(), available.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        Ok(n)
+    }
+}
+
+impl ReadAt for Vec<u8> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        self.as_slice().read_at(buf, offset)
+    }
+}
+
+impl<T: ReadAt + ?Sized> ReadAt for &T {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        (**self).read_at(buf, offset)
+    }
+}
+
+#[cfg(unix)]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::unix::fs::FileExt::read_at(self, buf, offset)
+    }
+}
+
+// Note that seek_read moves the file cursor, unlike pread on Unix. That doesn't matter to anyone
+// using positional reads.
+#[cfg(windows)]
+impl ReadAt for std::fs::File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        std::os::windows::fs::FileExt::seek_read(self, buf, offset)
+    }
+}
+
+// The default capacity of the CV caches in ReadAtDecoder and VerifiedMmap, about 3 MB.
+const DEFAULT_SHARED_CV_CACHE_CAPACITY: usize = 1 << 16;
+
+/// A decoder that verifies positional reads from `&self`, so that many threads can read the same
+/// encoding at once without a mutex around the decoder or a file handle each.
+///
+/// This supports both the combined and outboard modes, with any [`ReadAt`](trait.ReadAt.html)
+/// source, typically a `File`. Each call to `read_at` reads and verifies every chunk it overlaps,
+/// so content is always verified as it's returned, even if the underlying file changes. The CVs
+/// of verified subtrees are kept in a cache shared by all threads, so that a read only has to
+/// fetch the parent nodes below the deepest cached subtree. The cache is split into shards with a
+/// lock each, so that threads reading different parts of the content rarely wait for each other.
+/// When the cache is full, the smallest subtrees are evicted first. The capacity can be set with
+/// [`DecoderBuilder::cv_cache_capacity`](struct.DecoderBuilder.html#method.cv_cache_capacity).
+///
+/// A read that starts at or past the end of the content verifies the final chunk, so that the
+/// length is known to be correct, and then returns EOF.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use bao::decode::{ReadAt, ReadAtDecoder};
+///
+/// let input = vec![0xab; 1_000_000];
+/// let (encoded, hash) = bao::encode::encode(&input);
+///
+/// let decoder = ReadAtDecoder::new(&*encoded, &hash)?;
+/// std::thread::scope(|scope| {
+///     for i in 0..4 {
+///         let decoder = &decoder;
+///         scope.spawn(move || {
+///             let mut buf = [0; 100];
+///             decoder.read_exact_at(&mut buf, i * 200_000).unwrap();
+///             assert_eq!(&[0xab; 100][..], &buf[..]);
+///         });
+///     }
+/// });
+/// assert_eq!(input.len() as u64, decoder.len()?);
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReadAtDecoder<T: ReadAt, O: ReadAt> {
+    input: T,
+    outboard: Option<O>,
+    content_len: u64,
+    root_hash: Hash,
+    mode: Mode,
+    cv_cache: SharedCvCache,
+}
+
+impl<T: ReadAt> ReadAtDecoder<T, T> {
+    /// Create a new `ReadAtDecoder` for the combined mode. This reads the length header, but it
+    /// doesn't verify anything yet.
+    pub fn new(inner: T, hash: &Hash) -> io::Result<Self> {
+        Self::from_parts(
+            inner,
+            None,
+            hash,
+            Mode::Hash,
+            Limits::default(),
+            DEFAULT_SHARED_CV_CACHE_CAPACITY,
+        )
+    }
+}
+
+impl<T: ReadAt, O: ReadAt> ReadAtDecoder<T, O> {
+    /// Create a new `ReadAtDecoder` for the outboard mode. This reads the length header from the
+    /// outboard encoding, but it doesn't verify anything yet.
+    pub fn new_outboard(inner: T, outboard: O, hash: &Hash) -> io::Result<Self> {
+        Self::from_parts(
+            inner,
+            Some(outboard),
+            hash,
+            Mode::Hash,
+            Limits::default(),
+            DEFAULT_SHARED_CV_CACHE_CAPACITY,
+        )
+    }
+
+    fn from_parts(
+        input: T,
+        outboard: Option<O>,
+        hash: &Hash,
+        mode: Mode,
+        limits: Limits,
+        cv_cache_capacity: usize,
+    ) -> io::Result<Self> {
+        let mut header = [0; HEADER_SIZE];
+        if let Some(outboard) = &outboard {
+            outboard.read_exact_at(&mut header, 0)?;
+        } else {
+            input.read_exact_at(&mut header, 0)?;
+        }
+        let content_len = crate::decode_len(&header);
+        limits.check(content_len, encode::encoded_size(content_len))?;
+        Ok(Self {
+            input,
+            outboard,
+            content_len,
+            root_hash: *hash,
+            mode,
+            cv_cache: SharedCvCache::new(cv_cache_capacity),
+        })
+    }
+
+    /// Return the verified length of the content. This reads and verifies the final chunk.
+    pub fn len(&self) -> io::Result<u64> {
+        let final_chunk = encode::count_chunks(self.content_len) - 1;
+        self.read_chunks(final_chunk, final_chunk, &mut [], 0)?;
+        Ok(self.content_len)
+    }
+
+    /// Return `true` if the verified length of the content is zero.
+    pub fn is_empty(&self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    // Verify the chunks from first_chunk to last_chunk inclusive, and copy the content bytes
+    // that overlap buf, which starts at content offset buf_offset.
+    fn read_chunks(
+        &self,
+        first_chunk: u64,
+        last_chunk: u64,
+        buf: &mut [u8],
+        buf_offset: u64,
+    ) -> io::Result<()> {
+        let mut tree = ReadAtTree {
+            decoder: self,
+            buf,
+            buf_offset,
+        };
+        verify_subtrees(
+            &mut tree,
+            &self.mode,
+            self.content_len,
+            Subtree::root(self.content_len),
+            &self.root_hash,
+            Finalization::Root,
+            &(first_chunk..last_chunk + 1),
+        )
+    }
+}
+
+// A ReadAtDecoder, and the caller's buffer for the verified content of one read.
+struct ReadAtTree<'a, T: ReadAt, O: ReadAt> {
+    decoder: &'a ReadAtDecoder<T, O>,
+    buf: &'a mut [u8],
+    buf_offset: u64,
+}
+
+impl<T: ReadAt, O: ReadAt> VerifiedTree for ReadAtTree<'_, T, O> {
+    fn cached_children(&self, subtree: Subtree) -> Option<(Hash, Hash)> {
+        self.decoder.cv_cache.children(subtree)
+    }
+
+    fn read_parent(&mut self, subtree: Subtree) -> io::Result<[u8; PARENT_SIZE]> {
+        let mut parent = [0; PARENT_SIZE];
+        if let Some(outboard) = &self.decoder.outboard {
+            let offset = HEADER_SIZE as u64 + subtree.parent_index * PARENT_SIZE as u64;
+            outboard.read_exact_at(&mut parent, offset)?;
+        } else {
+            let offset = HEADER_SIZE as u64 + combined_offset(subtree);
+            self.decoder.input.read_exact_at(&mut parent, offset)?;
+        }
+        Ok(parent)
+    }
+
+    fn verified_parent(&mut self, subtree: Subtree, parent: &[u8; PARENT_SIZE]) {
+        self.decoder.cv_cache.insert_children(subtree, parent);
+    }
+
+    fn read_chunk(&mut self, subtree: Subtree, buf: &mut [u8]) -> io::Result<()> {
+        let offset = if self.decoder.outboard.is_some() {
+            subtree.start_chunk * CHUNK_SIZE as u64
+        } else {
+            HEADER_SIZE as u64 + combined_offset(subtree)
+        };
+        self.decoder.input.read_exact_at(buf, offset)
+    }
+
+    fn verified_chunk(&mut self, subtree: Subtree, chunk: &[u8]) -> io::Result<()> {
+        // Copy the part of the chunk that overlaps the caller's buffer.
+        let chunk_start = subtree.start_chunk * CHUNK_SIZE as u64;
+        let buf_end = self.buf_offset + self.buf.len() as u64;
+        let copy_start = cmp::max(chunk_start, self.buf_offset);
+        let copy_end = cmp::min(chunk_start + chunk.len() as u64, buf_end);
+        if copy_start < copy_end {
+            self.buf
+                [(copy_start - self.buf_offset) as usize..(copy_end - self.buf_offset) as usize]
+                .copy_from_slice(
+                    &chunk[(copy_start - chunk_start) as usize..(copy_end - chunk_start) as usize],
+                );
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadAt, O: ReadAt> ReadAt for ReadAtDecoder<T, O> {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if offset >= self.content_len {
+            // Don't trust the length header until we've verified the final chunk.
+            self.len()?;
+            return Ok(0);
+        }
+        let end = cmp::min(offset.saturating_add(buf.len() as u64), self.content_len);
+        if offset == end {
+            return Ok(0);
+        }
+        let first_chunk = offset / CHUNK_SIZE as u64;
+        let last_chunk = (end - 1) / CHUNK_SIZE as u64;
+        let n = (end - offset) as usize;
+        self.read_chunks(first_chunk, last_chunk, &mut buf[..n], offset)?;
+        Ok(n)
+    }
+}
+
+impl<T: ReadAt, O: ReadAt> fmt::Debug for ReadAtDecoder<T, O> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing hashes, they might be secret.
+        write!(
+            f,
+            "ReadAtDecoder {{ is_outboard: {}, content_len: {}, ... }}",
+            self.outboard.is_some(),
+            self.content_len,
+        )
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn make_test_input(len: usize) -> Vec<u8> {
+    // Fill the input with incrementing bytes, so that reads from different sections are very
+    // unlikely to accidentally match.
+    let mut ret = Vec::new();
+    let mut counter = 0u64;
+    while ret.len() < len {
+        if counter < u8::MAX as u64 {
+            ret.push(counter as u8);
+        } else if counter < u16::MAX as u64 {
+            ret.extend_from_slice(&(counter as u16).to_be_bytes());
+        } else if counter < u32::MAX as u64 {
+            ret.extend_from_slice(&(counter as u32).to_be_bytes());
+        } else {
+            ret.extend_from_slice(&counter.to_be_bytes());
+        }
+        counter += 1;
+    }
+    ret.truncate(len);
+    ret
+}
+
+#[cfg(test)]
+mod test {
+    use rand::prelude::*;
+    use rand_chacha::ChaChaRng;
+    use std::io;
+    use std::io::prelude::*;
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::encode;
+    use crate::HASH_SIZE;
+
+    #[test]
+    fn test_decode() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (encoded, hash) = { encode::encode(&input) };
+            let output = decode(&encoded, &hash).unwrap();
+            assert_eq!(input, output);
+            assert_eq!(output.len(), output.capacity());
+        }
+    }
+
+    #[test]
+    fn test_decode_into() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (encoded, hash) = encode::encode(&input);
+            // Leave some extra room at the end, which should be untouched.
+            let mut output = vec![0xff; case + 1];
+            let n = decode_into(&encoded, &hash, &mut output).unwrap();
+            assert_eq!(case, n);
+            assert_eq!(input, &output[..n]);
+            assert_eq!(0xff, output[n]);
+            if case > 0 {
+                let err = decode_into(&encoded, &hash, &mut output[..case - 1]).unwrap_err();
+                assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+            }
+            let err = decode_into(&encoded[..encoded.len() - 1], &hash, &mut output).unwrap_err();
+            assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        }
+
+        // The builder checks its limits, and it doesn't support chunk groups here.
+        let (encoded, hash) = encode::encode(make_test_input(CHUNK_SIZE + 1));
+        let mut output = vec![0; CHUNK_SIZE + 1];
+        let mut builder = DecoderBuilder::new();
+        builder.max_content_len(CHUNK_SIZE as u64);
+        let err = builder
+            .decode_into(&encoded, &hash, &mut output)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let mut builder = DecoderBuilder::new();
+        builder.chunk_group_log(1);
+        let err = builder
+            .decode_into(&encoded, &hash, &mut output)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn test_decode_into_corrupted() {
+        // When a chunk is corrupt, every chunk of the output should either be verified content or
+        // left as it was. Nothing unverified should be written.
+        let case = 16 * CHUNK_SIZE + 1;
+        let input = make_test_input(case);
+        let (encoded, hash) = encode::encode(&input);
+        let mut tweak_positions = vec![HEADER_SIZE, encoded.len() - 1];
+        let mut position = HEADER_SIZE;
+        for chunk in 0..encode::count_chunks(case as u64) {
+            position += encode::pre_order_parent_nodes(chunk, case as u64) as usize * PARENT_SIZE;
+            tweak_positions.push(position);
+            position += encode::chunk_size(chunk, case as u64);
+        }
+        for tweak in tweak_positions {
+            let mut bad_encoded = encoded.clone();
+            bad_encoded[tweak] ^= 1;
+            let mut output = vec![0; case];
+            let err = decode_into(&bad_encoded, &hash, &mut output).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            let mut corrupted_chunks = 0;
+            for (input_chunk, output_chunk) in
+                input.chunks(CHUNK_SIZE).zip(output.chunks(CHUNK_SIZE))
+            {
+                if input_chunk != output_chunk {
+                    assert!(output_chunk.iter().all(|&b| b == 0));
+                    corrupted_chunks += 1;
+                }
+            }
+            assert!(corrupted_chunks > 0);
+        }
+    }
+
+    #[test]
+    fn test_decode_to_file() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (encoded, hash) = encode::encode(&input);
+            let mut file = tempfile::tempfile().unwrap();
+            // Start with a file that's too long, to check that it gets truncated.
+            file.write_all(&[0xff; 2 * CHUNK_SIZE]).unwrap();
+            let n = decode_to_file(&encoded, &hash, &file).unwrap();
+            assert_eq!(case as u64, n);
+            let mut output = Vec::new();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.read_to_end(&mut output).unwrap();
+            assert_eq!(input, output);
+
+            // Don't tweak the header in the empty case.
+            if case > 0 {
+                let mut bad_encoded = encoded.clone();
+                *bad_encoded.last_mut().unwrap() ^= 1;
+                let err = decode_to_file(&bad_encoded, &hash, &file).unwrap_err();
+                assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_to_file_corrupted() {
+        // Corrupt the middle chunk, and decode over a file that's already full of other bytes.
+        // Everything but verified chunks should end up zero.
+        let case = 5 * CHUNK_SIZE;
+        let input = make_test_input(case);
+        let (mut encoded, hash) = encode::encode(&input);
+        let middle = encoded.len() / 2;
+        encoded[middle] ^= 1;
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&vec![0xff; case]).unwrap();
+        let err = decode_to_file(&encoded, &hash, &file).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let mut output = Vec::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_end(&mut output).unwrap();
+        assert_eq!(case, output.len());
+        let mut corrupted_chunks = 0;
+        for (input_chunk, output_chunk) in input.chunks(CHUNK_SIZE).zip(output.chunks(CHUNK_SIZE)) {
+            if input_chunk != output_chunk {
+                assert!(output_chunk.iter().all(|&b| b == 0));
+                corrupted_chunks += 1;
+            }
+        }
+        assert!(corrupted_chunks > 0);
+    }
+
+    // Read everything through the BufRead interface, checking that fill_buf never returns more
+    // than a chunk at a time.
+    fn read_all_buffered(reader: &mut impl BufRead) -> io::Result<Vec<u8>> {
+        let mut output = Vec::new();
+        loop {
+            let buf = reader.fill_buf()?;
+            assert!(buf.len() <= CHUNK_SIZE);
+            if buf.is_empty() {
+                return Ok(output);
+            }
+            // Consume bytes in small pieces, to exercise partial consumption.
+            let n = cmp::min(buf.len(), 100);
+            output.extend_from_slice(&buf[..n]);
+            reader.consume(n);
+        }
+    }
+
+    #[test]
+    fn test_bufread() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (encoded, hash) = encode::encode(&input);
+            let (outboard, _) = encode::outboard(&input);
+
+            let mut decoder = Decoder::new(&*encoded, &hash);
+            assert_eq!(input, read_all_buffered(&mut decoder).unwrap());
+            let mut decoder = Decoder::new_outboard(&*input, &*outboard, &hash);
+            assert_eq!(input, read_all_buffered(&mut decoder).unwrap());
+
+            // Lines should work directly on the decoder, without a BufReader.
+            let decoder = Decoder::new(&*encoded, &hash);
+            let lines: Vec<Vec<u8>> = decoder.split(b'\n').map(|line| line.unwrap()).collect();
+            let expected: Vec<Vec<u8>> = input.split(|&b| b == b'\n').map(|l| l.to_vec()).collect();
+            if case > 0 {
+                assert_eq!(expected[..expected.len() - 1], lines[..expected.len() - 1]);
+            }
+
+            // Mixing seeks, reads, and fill_buf should stay consistent.
+            if case > 10 {
+                let mut decoder = Decoder::new(Cursor::new(&encoded), &hash);
+                decoder.seek(SeekFrom::Start(5)).unwrap();
+                let mut first = [0; 5];
+                decoder.read_exact(&mut first).unwrap();
+                assert_eq!(&input[5..10], &first);
+                assert_eq!(&input[10..], &*read_all_buffered(&mut decoder).unwrap());
+            }
+
+            // Corruption should be reported by fill_buf, never returned.
+            if case > 0 {
+                let mut bad_encoded = encoded.clone();
+                *bad_encoded.last_mut().unwrap() ^= 1;
+                let mut decoder = Decoder::new(&*bad_encoded, &hash);
+                let err = read_all_buffered(&mut decoder).unwrap_err();
+                assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            }
+
+            for &slice_start in crate::test::TEST_CASES {
+                for &slice_len in &[0, 1, CHUNK_SIZE, 3 * CHUNK_SIZE] {
+                    let mut slice = Vec::new();
+                    encode::SliceExtractor::new(
+                        Cursor::new(&encoded),
+                        slice_start as u64,
+                        slice_len as u64,
+                    )
+                    .read_to_end(&mut slice)
+                    .unwrap();
+                    let expected_start = cmp::min(input.len(), slice_start);
+                    let expected_end = cmp::min(input.len(), slice_start + slice_len);
+                    let mut decoder =
+                        SliceDecoder::new(&*slice, &hash, slice_start as u64, slice_len as u64);
+                    assert_eq!(
+                        &input[expected_start..expected_end],
+                        &*read_all_buffered(&mut decoder).unwrap()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunks() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (encoded, hash) = encode::encode(&input);
+            let (outboard, _) = encode::outboard(&input);
+            for (buf, iter) in [
+                (&encoded, chunks(&encoded, &hash)),
+                (&input, chunks_outboard(&input, &outboard, &hash)),
+            ] {
+                let mut output = Vec::new();
+                for item in iter {
+                    let (offset, chunk) = item.unwrap();
+                    assert_eq!(output.len() as u64, offset);
+                    assert!(chunk.len() <= CHUNK_SIZE);
+                    // The chunk should borrow from the original buffer, not a copy.
+                    let buf_range = buf.as_ptr_range();
+                    assert!(buf_range.start <= chunk.as_ptr() && chunk.as_ptr() < buf_range.end);
+                    output.extend_from_slice(chunk);
+                }
+                assert_eq!(input, output);
+            }
+
+            // A wrong hash should fail on the first item, even for the empty input.
+            let mut bad_hash_bytes = *hash.as_bytes();
+            bad_hash_bytes[0] ^= 1;
+            let bad_hash = bad_hash_bytes.into();
+            let mut iter = chunks(&encoded, &bad_hash);
+            let err = iter.next().unwrap().unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            assert!(iter.next().is_none());
+
+            // Truncation should be detected, and short input in the outboard case too.
+            let mut iter = chunks(&encoded[..encoded.len() - 1], &hash);
+            let err = iter.find_map(Result::err).unwrap();
+            assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+            if case > 0 {
+                let mut iter = chunks_outboard(&input[..case - 1], &outboard, &hash);
+                let err = iter.next().unwrap().unwrap_err();
+                assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+            }
+
+            // Corrupting the final chunk should still yield all the chunks before it.
+            if case > 0 {
+                let mut bad_encoded = encoded.clone();
+                *bad_encoded.last_mut().unwrap() ^= 1;
+                let mut output = Vec::new();
+                let mut error = None;
+                for item in chunks(&bad_encoded, &hash) {
+                    match item {
+                        Ok((_, chunk)) => output.extend_from_slice(chunk),
+                        Err(e) => error = Some(e),
+                    }
+                }
+                assert_eq!(io::ErrorKind::InvalidData, error.unwrap().kind());
+                let last_chunk_start = (case - 1) / CHUNK_SIZE * CHUNK_SIZE;
+                assert_eq!(&input[..last_chunk_start], &*output);
+            }
+        }
+    }
+
+    #[test]
+    fn test_verified_mmap() {
+        let mut prng = ChaChaRng::from_seed([0; 32]);
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (outboard, hash) = encode::outboard(&input);
+            let verified = VerifiedMmap::new(&input, &outboard, &hash).unwrap();
+            for _ in 0..20 {
+                let start = prng.gen_range(0, case + 1);
+                let end = prng.gen_range(start, case + 1);
+                let range = start as u64..end as u64;
+                assert_eq!(&input[start..end], verified.get(range.clone()).unwrap());
+                // The second read of the same range should be cached.
+                assert_eq!(&input[start..end], verified.get(range).unwrap());
+            }
+            assert_eq!(case as u64, verified.len().unwrap());
+            let err = verified.get(0..case as u64 + 1).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+            // A wrong hash should fail every chunk, including for the length.
+            let mut bad_hash_bytes = *hash.as_bytes();
+            bad_hash_bytes[0] ^= 1;
+            let bad_hash = bad_hash_bytes.into();
+            let verified = VerifiedMmap::new(&input, &outboard, &bad_hash).unwrap();
+            let err = verified.len().unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            if case > 0 {
+                let err = verified.get(0..1).unwrap_err();
+                assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            }
 
-        // We either just finished the seek (if any), or already did it during
-        // a previous call. Continue the read. Cap the output buffer to be at
-        // most the slice bytes remaining.
-        if self.need_fake_read {
-            // Read one byte and throw it away, just to verify a chunk.
-            self.shared.read(&mut [0])?;
-            self.need_fake_read = false;
-            Ok(0)
-        } else {
-            let cap = cmp::min(self.slice_remaining, output.len() as u64) as usize;
-            let capped_output = &mut output[..cap];
-            let n = self.shared.read(capped_output)?;
-            self.slice_remaining -= n as u64;
-            Ok(n)
+            // Short buffers should be rejected up front.
+            let err =
+                VerifiedMmap::new(&input, &outboard[..outboard.len() - 1], &hash).unwrap_err();
+            assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+            if case > 0 {
+                let err = VerifiedMmap::new(&input[..case - 1], &outboard, &hash).unwrap_err();
+                assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+            }
         }
     }
-}
 
-#[cfg(test)]
-pub(crate) fn make_test_input(len: usize) -> Vec<u8> {
-    // Fill the input with incrementing bytes, so that reads from different sections are very
-    // unlikely to accidentally match.
-    let mut ret = Vec::new();
-    let mut counter = 0u64;
-    while ret.len() < len {
-        if counter < u8::max_value() as u64 {
-            ret.push(counter as u8);
-        } else if counter < u16::max_value() as u64 {
-            ret.extend_from_slice(&(counter as u16).to_be_bytes());
-        } else if counter < u32::max_value() as u64 {
-            ret.extend_from_slice(&(counter as u32).to_be_bytes());
-        } else {
-            ret.extend_from_slice(&(counter as u64).to_be_bytes());
+    #[test]
+    fn test_verified_mmap_corrupted() {
+        let case = 37 * CHUNK_SIZE;
+        let input = make_test_input(case);
+        let (outboard, hash) = encode::outboard(&input);
+        for bad_chunk in 0..37 {
+            let mut bad_input = input.clone();
+            bad_input[bad_chunk * CHUNK_SIZE] ^= 1;
+            let verified = VerifiedMmap::new(&bad_input, &outboard, &hash).unwrap();
+            // Verify the bad chunk's neighbors first, and then the bad chunk itself, twice.
+            for chunk in (0..37)
+                .filter(|&c| c != bad_chunk)
+                .chain(vec![bad_chunk; 2])
+            {
+                let start = chunk as u64 * CHUNK_SIZE as u64;
+                let result = verified.get(start..start + CHUNK_SIZE as u64);
+                if chunk == bad_chunk {
+                    assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+                } else {
+                    assert_eq!(&input[start as usize..][..CHUNK_SIZE], result.unwrap());
+                }
+            }
+            // Ranges spanning the bad chunk should fail too.
+            let err = verified.get(0..case as u64).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
         }
-        counter += 1;
     }
-    ret.truncate(len);
-    ret
-}
-
-#[cfg(test)]
-mod test {
-    use rand::prelude::*;
-    use rand_chacha::ChaChaRng;
-    use std::io;
-    use std::io::prelude::*;
-    use std::io::Cursor;
 
-    use super::*;
-    use crate::encode;
+    #[test]
+    fn test_verified_mmap_threads() {
+        let case = 100 * CHUNK_SIZE + 1;
+        let input = make_test_input(case);
+        let (outboard, hash) = encode::outboard(&input);
+        let verified = VerifiedMmap::new(&input, &outboard, &hash).unwrap();
+        let verified = &verified;
+        let input = &input;
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                scope.spawn(move || {
+                    for i in 0..case / 100 {
+                        let start = (thread * 997 + i * 101) % case;
+                        let end = cmp::min(case, start + 3000);
+                        let range = start as u64..end as u64;
+                        assert_eq!(&input[start..end], verified.get(range).unwrap());
+                    }
+                });
+            }
+        });
+    }
 
     #[test]
-    fn test_decode() {
+    fn test_read_at_decoder() {
+        let mut prng = ChaChaRng::from_seed([0; 32]);
         for &case in crate::test::TEST_CASES {
             println!("case {}", case);
             let input = make_test_input(case);
-            let (encoded, hash) = { encode::encode(&input) };
-            let output = decode(&encoded, &hash).unwrap();
-            assert_eq!(input, output);
-            assert_eq!(output.len(), output.capacity());
+            let (encoded, hash) = encode::encode(&input);
+            let (outboard, outboard_hash) = encode::outboard(&input);
+            assert_eq!(hash, outboard_hash);
+            let combined = ReadAtDecoder::new(&*encoded, &hash).unwrap();
+            let outboard = ReadAtDecoder::new_outboard(&*input, &*outboard, &hash).unwrap();
+            for _ in 0..20 {
+                let start = prng.gen_range(0, case + 1);
+                let end = prng.gen_range(start, case + 1);
+                for decoder in &[&combined as &dyn ReadAt, &outboard] {
+                    let mut output = vec![0; end - start];
+                    decoder.read_exact_at(&mut output, start as u64).unwrap();
+                    assert_eq!(&input[start..end], &*output);
+                }
+            }
+            assert_eq!(case as u64, combined.len().unwrap());
+            assert_eq!(case as u64, outboard.len().unwrap());
+
+            // Reads past the end return a short read and then EOF.
+            let mut output = vec![0; 2 * CHUNK_SIZE];
+            let start = case.saturating_sub(CHUNK_SIZE);
+            let n = combined.read_at(&mut output, start as u64).unwrap();
+            assert_eq!(&input[start..], &output[..n]);
+            assert_eq!(0, combined.read_at(&mut output, case as u64).unwrap());
+            assert_eq!(0, outboard.read_at(&mut output, u64::MAX).unwrap());
+
+            // A wrong hash should fail every read, including EOF.
+            let mut bad_hash_bytes = *hash.as_bytes();
+            bad_hash_bytes[0] ^= 1;
+            let bad_hash = bad_hash_bytes.into();
+            let decoder = ReadAtDecoder::new(&*encoded, &bad_hash).unwrap();
+            let err = decoder.read_at(&mut [0], 0).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            let err = decoder.read_at(&mut [0], case as u64).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_read_at_decoder_corrupted() {
+        let case = 37 * CHUNK_SIZE;
+        let input = make_test_input(case);
+        let (encoded, hash) = encode::encode(&input);
+        // Parent nodes and chunks are all multiples of 64 bytes, so corrupting every 64-byte
+        // block after the header covers all of them.
+        for bad_position in (HEADER_SIZE..encoded.len()).step_by(PARENT_SIZE) {
+            let mut bad_encoded = encoded.clone();
+            bad_encoded[bad_position + 10] ^= 1;
+            let decoder = ReadAtDecoder::new(&*bad_encoded, &hash).unwrap();
+            let mut failures = 0;
+            // Read every chunk twice, so that the second pass uses the CV cache.
+            for chunk in (0..37).chain(0..37) {
+                let mut output = [0; CHUNK_SIZE];
+                let start = chunk as u64 * CHUNK_SIZE as u64;
+                match decoder.read_exact_at(&mut output, start) {
+                    Ok(()) => assert_eq!(&input[start as usize..][..CHUNK_SIZE], &output[..]),
+                    Err(e) => {
+                        assert_eq!(io::ErrorKind::InvalidData, e.kind());
+                        failures += 1;
+                    }
+                }
+            }
+            // Some chunk must be affected, and every affected chunk fails both times.
+            assert!(failures > 0 && failures % 2 == 0);
+            let err = decoder.read_exact_at(&mut vec![0; case], 0).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
         }
     }
 
+    #[test]
+    fn test_read_at_decoder_threads() {
+        let case = 100 * CHUNK_SIZE + 1;
+        let input = make_test_input(case);
+        let (outboard, hash) = encode::outboard(&input);
+        let mut input_file = tempfile::tempfile().unwrap();
+        input_file.write_all(&input).unwrap();
+        let mut outboard_file = tempfile::tempfile().unwrap();
+        outboard_file.write_all(&outboard).unwrap();
+        let decoder = DecoderBuilder::new()
+            .cv_cache_capacity(10)
+            .build_read_at_outboard(input_file, outboard_file, &hash)
+            .unwrap();
+        let decoder = &decoder;
+        let input = &input;
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                scope.spawn(move || {
+                    for i in 0..case / 100 {
+                        let start = (thread * 997 + i * 101) % case;
+                        let end = cmp::min(case, start + 3000);
+                        let mut output = vec![0; end - start];
+                        decoder.read_exact_at(&mut output, start as u64).unwrap();
+                        assert_eq!(&input[start..end], &*output);
+                    }
+                });
+            }
+        });
+    }
+
     #[test]
     fn test_decode_outboard() {
         for &case in crate::test::TEST_CASES {
@@ -835,6 +3224,175 @@ mod test {
         }
     }
 
+    // Counts the bytes read from the inner reader.
+    struct CountingReader<R> {
+        inner: R,
+        count: std::rc::Rc<std::cell::Cell<u64>>,
+    }
+
+    impl<R: Read> Read for CountingReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = self.inner.read(buf)?;
+            self.count.set(self.count.get() + n as u64);
+            Ok(n)
+        }
+    }
+
+    impl<R: Seek> Seek for CountingReader<R> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn test_cv_cache_random_seeks() {
+        let input_len = 0b100101 * CHUNK_SIZE + 7;
+        let input = make_test_input(input_len);
+        let (encoded, hash) = encode::encode(&input);
+        let (outboard, _) = encode::outboard(&input);
+        for &capacity in &[0, 1, 4, 12, 1000] {
+            println!("capacity {}", capacity);
+            let mut prng = ChaChaRng::from_seed([0; 32]);
+            let mut builder = DecoderBuilder::new();
+            builder.cv_cache_capacity(capacity);
+            let mut combined = builder.build(Cursor::new(&encoded), &hash);
+            let mut outboard =
+                builder.build_outboard(Cursor::new(&input), Cursor::new(&outboard), &hash);
+            for _ in 0..300 {
+                let seek = prng.gen_range(0, input_len + 1);
+                let input_start = cmp::min(seek, input_len);
+                let input_end = cmp::min(input_start + CHUNK_SIZE, input_len);
+                for decoder in [&mut combined as &mut dyn ReadSeek, &mut outboard] {
+                    decoder.seek(SeekFrom::Start(seek as u64)).unwrap();
+                    let mut output = vec![0; input_end - input_start];
+                    decoder.read_exact(&mut output).unwrap();
+                    assert_eq!(&input[input_start..input_end], &*output);
+                }
+            }
+            let mut output = Vec::new();
+            combined.seek(SeekFrom::End(-100)).unwrap();
+            combined.read_to_end(&mut output).unwrap();
+            assert_eq!(&input[input_len - 100..], &*output);
+        }
+    }
+
+    trait ReadSeek: Read + Seek {}
+    impl<T: Read + Seek> ReadSeek for T {}
+
+    #[test]
+    fn test_cv_cache_reads_less() {
+        // Reading one byte from the start of each chunk, going backwards, has to re-read the path
+        // from the root every time without a cache, but not with one.
+        let input_len = 64 * CHUNK_SIZE;
+        let input = make_test_input(input_len);
+        let (encoded, hash) = encode::encode(&input);
+        let mut counts = Vec::new();
+        for &capacity in &[None, Some(1000)] {
+            let count = std::rc::Rc::new(std::cell::Cell::new(0));
+            let reader = CountingReader {
+                inner: Cursor::new(&encoded),
+                count: count.clone(),
+            };
+            let mut builder = DecoderBuilder::new();
+            if let Some(capacity) = capacity {
+                builder.cv_cache_capacity(capacity);
+            }
+            let mut decoder = builder.build(reader, &hash);
+            for chunk in (0..64).rev() {
+                let position = chunk * CHUNK_SIZE;
+                decoder.seek(SeekFrom::Start(position as u64)).unwrap();
+                let mut byte = [0];
+                decoder.read_exact(&mut byte).unwrap();
+                assert_eq!(input[position], byte[0]);
+            }
+            counts.push(count.get());
+        }
+        // Without the cache we read the header, the 6 parents above each chunk, and each chunk.
+        // With the cache we read each parent only once.
+        assert_eq!(
+            HEADER_SIZE as u64 + 64 * (6 * PARENT_SIZE + CHUNK_SIZE) as u64,
+            counts[0]
+        );
+        assert_eq!(
+            HEADER_SIZE as u64 + 63 * PARENT_SIZE as u64 + 64 * CHUNK_SIZE as u64,
+            counts[1]
+        );
+    }
+
+    #[test]
+    fn test_cv_cache_corrupted_parent() {
+        // A corrupt parent node must fail every time, and never poison the cache.
+        let input_len = 8 * CHUNK_SIZE;
+        let input = make_test_input(input_len);
+        let (mut encoded, hash) = encode::encode(&input);
+        // The parent of chunks 4-7 comes after the first half of the tree.
+        let right_parent = HEADER_SIZE
+            + PARENT_SIZE
+            + encode::encoded_subtree_size(4 * CHUNK_SIZE as u64) as usize;
+        encoded[right_parent] ^= 1;
+        let mut builder = DecoderBuilder::new();
+        builder.cv_cache_capacity(1000);
+        let mut decoder = builder.build(Cursor::new(&encoded), &hash);
+        for _ in 0..3 {
+            for chunk in 0..8 {
+                let position = chunk * CHUNK_SIZE;
+                let mut output = [0; 10];
+                // Depending on the chunk, the bad parent is read either during the seek or
+                // during the read.
+                let result = decoder
+                    .seek(SeekFrom::Start(position as u64))
+                    .and_then(|_| decoder.read_exact(&mut output));
+                if chunk < 4 {
+                    result.unwrap();
+                    assert_eq!(&input[position..][..10], &output);
+                } else {
+                    assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_shared_cv_cache() {
+        for &capacity in &[0, 1, 10, 5000, DEFAULT_SHARED_CV_CACHE_CAPACITY] {
+            println!("capacity {}", capacity);
+            let cache = SharedCvCache::new(capacity);
+            assert!(cache.shards.len() <= CV_CACHE_SHARDS);
+            let len = |cache: &SharedCvCache| -> usize {
+                cache
+                    .shards
+                    .iter()
+                    .map(|s| s.lock().unwrap().cvs.len())
+                    .sum()
+            };
+            // Fill the cache with every parent node of a big tree, twice over.
+            let num_chunks = 2 * capacity as u64 + 2;
+            let mut subtrees = vec![Subtree::root(num_chunks * CHUNK_SIZE as u64)];
+            while let Some(subtree) = subtrees.pop() {
+                if subtree.num_chunks > 1 {
+                    cache.insert_children(subtree, &[0; PARENT_SIZE]);
+                    assert!(len(&cache) <= capacity);
+                    let (left, right) = subtree.children();
+                    subtrees.push(left);
+                    subtrees.push(right);
+                }
+            }
+            assert_eq!(capacity, len(&cache));
+        }
+        // The subtrees near the root are the last to go.
+        let cache = SharedCvCache::new(2);
+        let root = Subtree::root(100 * CHUNK_SIZE as u64);
+        cache.insert_children(root, &[1; PARENT_SIZE]);
+        let (left, right) = root.children();
+        cache.insert_children(left, &[2; PARENT_SIZE]);
+        cache.insert_children(right, &[3; PARENT_SIZE]);
+        assert_eq!(
+            Some(([1; HASH_SIZE].into(), [1; HASH_SIZE].into())),
+            cache.children(root)
+        );
+        assert_eq!(None, cache.children(left));
+    }
+
     #[test]
     fn test_invalid_zero_length() {
         // There are different ways of structuring a decoder, and many of them are vulnerable to a
@@ -894,7 +3452,7 @@ mod test {
             // Read all the bits up to that tweak. Because it's right after a chunk boundary, the
             // read should succeed.
             let mut decoder = Decoder::new(Cursor::new(&encoded), &hash);
-            let mut output = vec![0; tweak_position as usize];
+            let mut output = vec![0; tweak_position];
             decoder.read_exact(&mut output).unwrap();
             assert_eq!(&input[..tweak_position], &*output);
 
@@ -992,6 +3550,34 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_slice_size() {
+        for &case in crate::test::TEST_CASES {
+            let input = make_test_input(case);
+            let (encoded, _) = encode::encode(&input);
+            for &slice_start in crate::test::TEST_CASES {
+                let slice_lens = [0, 1, 2, CHUNK_SIZE - 1, CHUNK_SIZE, CHUNK_SIZE + 1];
+                for &slice_len in slice_lens.iter() {
+                    let mut slice = Vec::new();
+                    let mut extractor = encode::SliceExtractor::new(
+                        Cursor::new(&encoded),
+                        slice_start as u64,
+                        slice_len as u64,
+                    );
+                    extractor.read_to_end(&mut slice).unwrap();
+                    assert_eq!(
+                        slice.len() as u128,
+                        encode::slice_size(case as u64, slice_start as u64, slice_len as u64),
+                        "case {} start {} len {}",
+                        case,
+                        slice_start,
+                        slice_len,
+                    );
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_corrupted_slice() {
         let input = make_test_input(20_000);
@@ -1045,6 +3631,460 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_limits() {
+        let input = make_test_input(3 * CHUNK_SIZE);
+        let (encoded, hash) = encode::encode(&input);
+        let (outboard, _) = encode::outboard(&input);
+        let encoded_size = encoded.len() as u64;
+        let content_len = input.len() as u64;
+
+        // Limits that are exactly large enough should succeed.
+        let mut builder = DecoderBuilder::new();
+        builder.max_content_len(content_len);
+        builder.max_encoded_size(encoded_size);
+        assert_eq!(input, builder.decode(&encoded, &hash).unwrap());
+        let mut output = Vec::new();
+        let mut decoder = builder.build(&*encoded, &hash);
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(input, output);
+        let mut output = Vec::new();
+        let mut decoder = builder.build_outboard(&*input, &*outboard, &hash);
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(input, output);
+
+        // One byte less should fail, for both limits.
+        let too_small = [
+            DecoderBuilder::new()
+                .max_content_len(content_len - 1)
+                .clone(),
+            DecoderBuilder::new()
+                .max_encoded_size(encoded_size - 1)
+                .clone(),
+        ];
+        for builder in &too_small {
+            let err = builder.decode(&encoded, &hash).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            let err = builder
+                .build(&*encoded, &hash)
+                .read_to_end(&mut Vec::new())
+                .unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            let err = builder
+                .build_outboard(Cursor::new(&input), Cursor::new(&outboard), &hash)
+                .seek(SeekFrom::End(0))
+                .unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+
+        // A forged header claiming an enormous length should be rejected right after the header
+        // is read, without reading any further.
+        let mut forged = encoded.clone();
+        forged[..HEADER_SIZE].copy_from_slice(&crate::encode_len(u64::MAX / 2));
+        let mut builder = DecoderBuilder::new();
+        builder.max_content_len(1 << 30);
+        let err = builder.decode(&forged, &hash).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let mut reader = Cursor::new(&forged);
+        let err = builder
+            .build(&mut reader, &hash)
+            .read(&mut [0; CHUNK_SIZE])
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(HEADER_SIZE as u64, reader.position());
+
+        // Slice limits apply to the size of the slice, not the whole encoding.
+        let slice_start = CHUNK_SIZE as u64;
+        let slice_len = CHUNK_SIZE as u64;
+        let mut slice = Vec::new();
+        encode::SliceExtractor::new(Cursor::new(&encoded), slice_start, slice_len)
+            .read_to_end(&mut slice)
+            .unwrap();
+        let mut builder = DecoderBuilder::new();
+        builder.max_encoded_size(slice.len() as u64);
+        let mut output = Vec::new();
+        let mut decoder = builder.build_slice(&*slice, &hash, slice_start, slice_len);
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(&input[CHUNK_SIZE..2 * CHUNK_SIZE], &*output);
+        builder.max_encoded_size(slice.len() as u64 - 1);
+        let mut decoder = builder.build_slice(&*slice, &hash, slice_start, slice_len);
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_decoder_checkpoint() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (encoded, hash) = encode::encode(&input);
+            let (outboard, _) = encode::outboard(&input);
+            // Interrupt the transfer at various points in the encoding. The decoder reads as far
+            // as it can verify, and the rest comes from a resumed decoder.
+            let mut cuts = vec![0, 1, HEADER_SIZE, encoded.len() / 2, encoded.len()];
+            cuts.retain(|&cut| cut <= encoded.len());
+            for &cut in &cuts {
+                let mut output = Vec::new();
+                let mut decoder = Decoder::new(&encoded[..cut], &hash);
+                let result = decoder.read_to_end(&mut output);
+                assert_eq!(cut == encoded.len(), result.is_ok());
+                let checkpoint = decoder.checkpoint();
+                assert_eq!(output.len() as u64, checkpoint.content_position());
+                assert!(checkpoint.input_position() as usize <= cut);
+                assert_eq!(None, checkpoint.outboard_position());
+                let checkpoint = DecoderCheckpoint::deserialize(&checkpoint.serialize()).unwrap();
+                let position = checkpoint.input_position() as usize;
+                let mut decoder =
+                    Decoder::resume(&encoded[position..], &hash, &checkpoint).unwrap();
+                decoder.read_to_end(&mut output).unwrap();
+                assert_eq!(input, output);
+            }
+            // Stop partway through a chunk in the outboard mode, with bytes still buffered.
+            for &stop in &[0, case / 3, case] {
+                let mut decoder = Decoder::new_outboard(&input[..], &outboard[..], &hash);
+                let mut output = vec![0; stop];
+                decoder.read_exact(&mut output).unwrap();
+                let checkpoint = decoder.checkpoint();
+                assert_eq!(stop as u64, checkpoint.content_position());
+                let input_position = checkpoint.input_position() as usize;
+                let outboard_position = checkpoint.outboard_position().unwrap() as usize;
+                let mut decoder = Decoder::resume_outboard(
+                    &input[input_position..],
+                    &outboard[outboard_position..],
+                    &hash,
+                    &checkpoint,
+                )
+                .unwrap();
+                decoder.read_to_end(&mut output).unwrap();
+                assert_eq!(input, output);
+            }
+            // Checkpoints after a seek resume from the seek target.
+            let seek = case / 2;
+            let mut decoder = Decoder::new(Cursor::new(&encoded), &hash);
+            decoder.seek(SeekFrom::Start(seek as u64)).unwrap();
+            let checkpoint = decoder.checkpoint();
+            assert_eq!(seek as u64, checkpoint.content_position());
+            let position = checkpoint.input_position() as usize;
+            let mut decoder = Decoder::resume(&encoded[position..], &hash, &checkpoint).unwrap();
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(&input[seek..], &*output);
+        }
+    }
+
+    #[test]
+    fn test_decoder_checkpoint_errors() {
+        let input = make_test_input(10 * CHUNK_SIZE);
+        let (mut encoded, hash) = encode::encode(&input);
+        let mut decoder = Decoder::new(&encoded[..encoded.len() / 2], &hash);
+        decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        let checkpoint = decoder.checkpoint();
+        let position = checkpoint.input_position() as usize;
+
+        // The hash and the mode have to match.
+        let other_hash = blake3::hash(b"foo");
+        let err = Decoder::resume(&encoded[position..], &other_hash, &checkpoint).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        let err =
+            Decoder::resume_outboard(&input[..], &encoded[..], &hash, &checkpoint).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        // Input after the checkpoint is still verified.
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+        let mut decoder = Decoder::resume(&encoded[position..], &hash, &checkpoint).unwrap();
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // Malformed checkpoints are rejected.
+        let bytes = checkpoint.serialize();
+        for len in 0..bytes.len() {
+            let err = DecoderCheckpoint::deserialize(&bytes[..len]).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+        let mut extra = bytes.clone();
+        extra.push(0);
+        let err = DecoderCheckpoint::deserialize(&extra).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        // The magic bytes and the version come first, then the outboard flag and the hash mode.
+        for (index, value) in [(0, b'x'), (4, 2), (5, 2), (6, 3)] {
+            let mut bad = bytes.clone();
+            bad[index] = value;
+            let err = DecoderCheckpoint::deserialize(&bad).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+        // The stack count comes right after those, the root hash, and the parser state.
+        let mut bad_stack = bytes.clone();
+        bad_stack[7 + HASH_SIZE + encode::ParseState::SERIALIZED_SIZE] += 1;
+        let err = DecoderCheckpoint::deserialize(&bad_stack).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_keyed_decoder_checkpoint() {
+        let input = make_test_input(10 * CHUNK_SIZE);
+        let key = [42; blake3::KEY_LEN];
+        let context = "bao test context";
+        for keyed in [true, false] {
+            let mut encoded = Cursor::new(Vec::new());
+            let mut encoder = if keyed {
+                encode::Encoder::new_keyed(&mut encoded, &key)
+            } else {
+                encode::Encoder::new_derive_key(&mut encoded, context)
+            };
+            encoder.write_all(&input).unwrap();
+            let hash = encoder.finalize().unwrap();
+            let encoded = encoded.into_inner();
+            let half = &encoded[..encoded.len() / 2];
+            let mut decoder = if keyed {
+                Decoder::new_keyed(half, &hash, &key)
+            } else {
+                Decoder::new_derive_key(half, &hash, context)
+            };
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap_err();
+
+            // The resumed decoder uses the same mode, without being told.
+            let checkpoint = decoder.checkpoint().serialize();
+            let checkpoint = DecoderCheckpoint::deserialize(&checkpoint).unwrap();
+            assert_eq!(output.len() as u64, checkpoint.content_position());
+            let position = checkpoint.input_position() as usize;
+            let mut decoder = Decoder::resume(&encoded[position..], &hash, &checkpoint).unwrap();
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(input, output);
+        }
+    }
+
+    #[test]
+    fn test_grouped_decoders() {
+        for &case in crate::test::TEST_CASES {
+            let input = make_test_input(case);
+            let hash = blake3::hash(&input);
+            for &group_log in &[1, 3] {
+                println!("case {} group_log {}", case, group_log);
+                let mut encoded = Cursor::new(Vec::new());
+                let mut encoder = encode::Encoder::new_grouped(&mut encoded, group_log);
+                encoder.write_all(&input).unwrap();
+                assert_eq!(hash, encoder.finalize().unwrap());
+                let encoded = encoded.into_inner();
+                let mut outboard = Cursor::new(Vec::new());
+                let mut encoder = encode::Encoder::new_outboard_grouped(&mut outboard, group_log);
+                encoder.write_all(&input).unwrap();
+                encoder.finalize().unwrap();
+                let outboard = outboard.into_inner();
+
+                let mut output = Vec::new();
+                let mut decoder = Decoder::new_grouped(&*encoded, &hash, group_log);
+                decoder.read_to_end(&mut output).unwrap();
+                assert_eq!(input, output);
+                let mut output = Vec::new();
+                let mut decoder =
+                    Decoder::new_outboard_grouped(&*input, &*outboard, &hash, group_log);
+                decoder.read_to_end(&mut output).unwrap();
+                assert_eq!(input, output);
+                let mut builder = DecoderBuilder::new();
+                builder
+                    .chunk_group_log(group_log)
+                    .max_encoded_size(encoded.len() as u64);
+                assert_eq!(input, builder.decode(&encoded, &hash).unwrap());
+                let err = builder.build_read_at(&*encoded, &hash).unwrap_err();
+                assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+                // Seeking lands in the middle of a group.
+                let seek = case / 3;
+                let mut decoder = Decoder::new_grouped(Cursor::new(&encoded), &hash, group_log);
+                decoder.seek(SeekFrom::Start(seek as u64)).unwrap();
+                let mut output = Vec::new();
+                decoder.read_to_end(&mut output).unwrap();
+                assert_eq!(&input[seek..], &*output);
+
+                // Decoding with the wrong group log fails.
+                if case > CHUNK_SIZE {
+                    let mut decoder = Decoder::new(&*encoded, &hash);
+                    assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+                }
+
+                // Slices include whole groups.
+                let group_size = encode::group_size(group_log);
+                for &(slice_start, slice_len) in &[
+                    (0, 0),
+                    (case as u64 / 3, case as u64 / 2),
+                    (group_size - 1, 2),
+                    (case as u64, 1),
+                ] {
+                    let mut slice = Vec::new();
+                    let mut extractor = encode::SliceExtractor::new_outboard_grouped(
+                        Cursor::new(&input),
+                        Cursor::new(&outboard),
+                        slice_start,
+                        slice_len,
+                        group_log,
+                    );
+                    extractor.read_to_end(&mut slice).unwrap();
+                    assert_eq!(
+                        encode::slice_size_grouped(case as u64, slice_start, slice_len, group_log),
+                        slice.len() as u128
+                    );
+                    let mut combined_slice = Vec::new();
+                    let mut extractor = encode::SliceExtractor::new_grouped(
+                        Cursor::new(&encoded),
+                        slice_start,
+                        slice_len,
+                        group_log,
+                    );
+                    extractor.read_to_end(&mut combined_slice).unwrap();
+                    assert_eq!(slice, combined_slice);
+                    let mut output = Vec::new();
+                    let mut decoder = SliceDecoder::new_grouped(
+                        &*slice,
+                        &hash,
+                        slice_start,
+                        slice_len,
+                        group_log,
+                    );
+                    decoder.read_to_end(&mut output).unwrap();
+                    let start = cmp::min(slice_start as usize, case);
+                    let end = cmp::min((slice_start + slice_len) as usize, case);
+                    assert_eq!(&input[start..end], &*output);
+                }
+
+                // Checkpoints keep the group log and the buffered part of a group.
+                let stop = case / 2;
+                let mut decoder = Decoder::new_grouped(&*encoded, &hash, group_log);
+                let mut output = vec![0; stop];
+                decoder.read_exact(&mut output).unwrap();
+                let checkpoint = decoder.checkpoint();
+                let checkpoint = DecoderCheckpoint::deserialize(&checkpoint.serialize()).unwrap();
+                let position = checkpoint.input_position() as usize;
+                let mut decoder =
+                    Decoder::resume(&encoded[position..], &hash, &checkpoint).unwrap();
+                decoder.read_to_end(&mut output).unwrap();
+                assert_eq!(input, output);
+            }
+        }
+    }
+
+    #[test]
+    fn test_grouped_corruption() {
+        let group_log = 2;
+        let input = make_test_input(9 * CHUNK_SIZE + 1);
+        let hash = blake3::hash(&input);
+        let mut encoded = Cursor::new(Vec::new());
+        let mut encoder = encode::Encoder::new_grouped(&mut encoded, group_log);
+        encoder.write_all(&input).unwrap();
+        encoder.finalize().unwrap();
+        let encoded = encoded.into_inner();
+        for &tweak in &[
+            HEADER_SIZE,
+            HEADER_SIZE + 2 * PARENT_SIZE + 3000,
+            encoded.len() - 1,
+        ] {
+            let mut bad_encoded = encoded.clone();
+            bad_encoded[tweak] ^= 1;
+            let mut decoder = Decoder::new_grouped(&*bad_encoded, &hash, group_log);
+            let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_keyed_decoders() {
+        let key = [42; blake3::KEY_LEN];
+        let context = "bao 2020-01-01 test context";
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let mut encoded = Cursor::new(Vec::new());
+            let mut encoder = encode::Encoder::new_keyed(&mut encoded, &key);
+            encoder.write_all(&input).unwrap();
+            let keyed_hash = encoder.finalize().unwrap();
+            let encoded = encoded.into_inner();
+            let mut outboard = Cursor::new(Vec::new());
+            let mut encoder = encode::Encoder::new_outboard_derive_key(&mut outboard, context);
+            encoder.write_all(&input).unwrap();
+            let derived_hash = encoder.finalize().unwrap();
+            let outboard = outboard.into_inner();
+
+            let mut output = Vec::new();
+            let mut decoder = Decoder::new_keyed(&*encoded, &keyed_hash, &key);
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(input, output);
+            let mut output = Vec::new();
+            let mut decoder =
+                Decoder::new_outboard_derive_key(&*input, &*outboard, &derived_hash, context);
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(input, output);
+            let mut builder = DecoderBuilder::new();
+            builder.keyed(&key);
+            assert_eq!(input, builder.decode(&encoded, &keyed_hash).unwrap());
+            let decoder = builder.build_read_at(&*encoded, &keyed_hash).unwrap();
+            let mut output = vec![0; case];
+            decoder.read_exact_at(&mut output, 0).unwrap();
+            assert_eq!(input, output);
+            let mut output = vec![0; case];
+            assert_eq!(
+                case,
+                builder
+                    .decode_into(&encoded, &keyed_hash, &mut output)
+                    .unwrap()
+            );
+            assert_eq!(input, output);
+            // The regular hash mode doesn't verify a keyed encoding.
+            let err = decode_into(&encoded, &keyed_hash, &mut output).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+            // Slices of keyed encodings are the same as any other slice.
+            let slice_start = case as u64 / 3;
+            let slice_len = case as u64 / 2;
+            let mut slice = Vec::new();
+            let mut extractor = encode::SliceExtractor::new_outboard(
+                Cursor::new(&input),
+                Cursor::new(&outboard),
+                slice_start,
+                slice_len,
+            );
+            extractor.read_to_end(&mut slice).unwrap();
+            let mut output = Vec::new();
+            let mut decoder = SliceDecoder::new_derive_key(
+                &*slice,
+                &derived_hash,
+                context,
+                slice_start,
+                slice_len,
+            );
+            decoder.read_to_end(&mut output).unwrap();
+            let start = cmp::min(slice_start as usize, case);
+            let end = cmp::min((slice_start + slice_len) as usize, case);
+            assert_eq!(&input[start..end], &*output);
+
+            // Decoding with the wrong mode or key fails, even when given the right hash.
+            let other_key = [43; blake3::KEY_LEN];
+            let mut decoders: Vec<Box<dyn Read>> = vec![
+                Box::new(Decoder::new(&*encoded, &keyed_hash)),
+                Box::new(Decoder::new_keyed(&*encoded, &keyed_hash, &other_key)),
+                Box::new(Decoder::new_derive_key(&*encoded, &keyed_hash, context)),
+                Box::new(Decoder::new_outboard(&*input, &*outboard, &derived_hash)),
+                Box::new(Decoder::new_outboard_derive_key(
+                    &*input,
+                    &*outboard,
+                    &derived_hash,
+                    "other context",
+                )),
+                Box::new(SliceDecoder::new_keyed(
+                    &*slice,
+                    &derived_hash,
+                    &key,
+                    slice_start,
+                    slice_len,
+                )),
+            ];
+            for decoder in &mut decoders {
+                let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+                assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            }
+        }
+    }
+
     #[test]
     fn test_slice_entire() {
         // If a slice starts at the beginning (actually anywere in the first chunk) and includes
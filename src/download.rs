@@ -0,0 +1,643 @@
+//! Downloading content from several sources at once, and dropping the ones that misbehave.
+//!
+//! A [`Source`](trait.Source.html) is anything that can produce a slice of the content with a
+//! given root hash, as [`SliceExtractor`](../encode/struct.SliceExtractor.html) makes it, like a
+//! peer on the network. The downloader trusts none of them. It first asks for a slice past the
+//! end of the content, which includes the final chunk and verifies the length. Then it splits the
+//! content into ranges, and fetches them concurrently, one thread per source, giving the next
+//! range to whichever source is free. Each slice is verified with a
+//! [`SliceDecoder`](../decode/struct.SliceDecoder.html) before any of it is written to the
+//! output.
+//!
+//! A source that returns an error, a slice that doesn't match the hash, or a slice that's
+//! truncated or otherwise the wrong length, gets the [`Blame`](struct.Blame.html). It's dropped
+//! for the rest of the download, and its range goes back to the front of the queue for another
+//! source. The download fails only if every source gets dropped.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use bao::download::Source;
+//! use std::io::{self, Cursor, Write};
+//!
+//! // A source with the whole encoding in memory. If `corrupt` is set, it flips a bit in every
+//! // slice it serves.
+//! struct MemorySource {
+//!     encoded: Vec<u8>,
+//!     corrupt: bool,
+//! }
+//!
+//! impl Source for MemorySource {
+//!     fn fetch_slice(
+//!         &mut self,
+//!         _hash: &bao::Hash,
+//!         start: u64,
+//!         len: u64,
+//!         output: &mut dyn Write,
+//!     ) -> io::Result<()> {
+//!         let mut slice = Vec::new();
+//!         let encoded = Cursor::new(&self.encoded);
+//!         io::copy(&mut bao::encode::SliceExtractor::new(encoded, start, len), &mut slice)?;
+//!         if self.corrupt {
+//!             *slice.last_mut().unwrap() ^= 1;
+//!         }
+//!         output.write_all(&slice)
+//!     }
+//! }
+//!
+//! let input = vec![0xab; 1_000_000];
+//! let (encoded, hash) = bao::encode::encode(&input);
+//! let mut sources = vec![
+//!     MemorySource { encoded: encoded.clone(), corrupt: false },
+//!     MemorySource { encoded: encoded.clone(), corrupt: true },
+//!     MemorySource { encoded: encoded.clone(), corrupt: false },
+//! ];
+//!
+//! let mut output = Cursor::new(Vec::new());
+//! let stats = bao::download::download(&hash, &mut sources, &mut output)?;
+//! assert_eq!(input, output.into_inner());
+//! assert_eq!(1, stats.blamed.len());
+//! assert_eq!(1, stats.blamed[0].source);
+//! assert_eq!(0, stats.fetched[1]);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::decode::SliceDecoder;
+use crate::encode;
+use crate::{Hash, CHUNK_SIZE, HEADER_SIZE, MAX_DEPTH, PARENT_SIZE};
+use arrayref::array_ref;
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use std::ops::Range;
+use std::sync::mpsc;
+use std::thread;
+
+/// The length of the ranges a [`Downloader`](struct.Downloader.html) fetches, unless it's set
+/// with [`range_len`](struct.Downloader.html#method.range_len).
+pub const DEFAULT_RANGE_LEN: u64 = 64 * CHUNK_SIZE as u64;
+
+/// Somewhere to fetch slices of content from.
+pub trait Source {
+    /// Write the slice of the content with root hash `hash` that starts at `start` and is `len`
+    /// bytes long to `output`, as [`SliceExtractor`](../encode/struct.SliceExtractor.html) makes
+    /// it. Nothing here needs to be verified, and the source is blamed for any error. Writing more
+    /// than the slice to `output` fails with an error of kind `InvalidData`.
+    fn fetch_slice(
+        &mut self,
+        hash: &Hash,
+        start: u64,
+        len: u64,
+        output: &mut dyn Write,
+    ) -> io::Result<()>;
+}
+
+impl<S: Source + ?Sized> Source for &mut S {
+    fn fetch_slice(
+        &mut self,
+        hash: &Hash,
+        start: u64,
+        len: u64,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        (**self).fetch_slice(hash, start, len, output)
+    }
+}
+
+impl<S: Source + ?Sized> Source for Box<S> {
+    fn fetch_slice(
+        &mut self,
+        hash: &Hash,
+        start: u64,
+        len: u64,
+        output: &mut dyn Write,
+    ) -> io::Result<()> {
+        (**self).fetch_slice(hash, start, len, output)
+    }
+}
+
+/// A source that was dropped from a download, and why.
+#[derive(Debug)]
+pub struct Blame {
+    /// The index of the source.
+    pub source: usize,
+    /// The range it was fetching. For the first request, which finds the length of the content,
+    /// this is empty and starts at `u64::MAX`.
+    pub range: Range<u64>,
+    /// What went wrong. A slice that doesn't match the hash, or that's the wrong length, is an
+    /// error of kind `InvalidData`, and a truncated slice is one of kind `UnexpectedEof`. Any
+    /// other error comes from the source.
+    pub error: io::Error,
+}
+
+/// What a download did.
+#[derive(Debug)]
+pub struct DownloadStats {
+    /// The length of the content, which is the number of bytes written to the output.
+    pub content_len: u64,
+    /// The bytes of content that came from each source, by index.
+    pub fetched: Vec<u64>,
+    /// The sources that were dropped, in the order they were dropped.
+    pub blamed: Vec<Blame>,
+}
+
+/// Download the content with root hash `hash` from `sources`, with the default settings. See
+/// [`Downloader::download`](struct.Downloader.html#method.download).
+pub fn download<S: Source + Send>(
+    hash: &Hash,
+    sources: &mut [S],
+    output: impl Write + Seek,
+) -> io::Result<DownloadStats> {
+    Downloader::new().download(hash, sources, output)
+}
+
+/// Settings for downloading content from several sources. See the
+/// [module documentation](index.html).
+#[derive(Clone, Debug)]
+pub struct Downloader {
+    range_len: u64,
+}
+
+impl Downloader {
+    /// Create a new `Downloader` with the default settings.
+    pub fn new() -> Self {
+        Self {
+            range_len: DEFAULT_RANGE_LEN,
+        }
+    }
+
+    /// Fetch ranges of `len` bytes, rounded up to a whole number of chunks. Smaller ranges spread
+    /// the work more evenly, and less of it is lost when a source gets dropped, but each slice
+    /// repeats the parent nodes above it. The default is
+    /// [`DEFAULT_RANGE_LEN`](constant.DEFAULT_RANGE_LEN.html).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `len` is zero.
+    pub fn range_len(&mut self, len: u64) -> &mut Self {
+        assert!(len > 0, "range_len can't be zero");
+        let chunk_size = CHUNK_SIZE as u64;
+        self.range_len = len.saturating_add(chunk_size - 1) / chunk_size * chunk_size;
+        self
+    }
+
+    /// Download the content with root hash `hash` from `sources`, and write it to `output`. The
+    /// ranges finish in any order, and each one is written at its offset. Only verified content
+    /// is written, but if the download fails, the output might be missing some of it.
+    ///
+    /// If every source gets dropped before the download is done, this returns the error from the
+    /// last one. An empty list of sources is an error of kind `InvalidInput`. Errors from the
+    /// output are returned as-is.
+    pub fn download<S: Source + Send>(
+        &self,
+        hash: &Hash,
+        sources: &mut [S],
+        mut output: impl Write + Seek,
+    ) -> io::Result<DownloadStats> {
+        if sources.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no sources"));
+        }
+        let mut stats = DownloadStats {
+            content_len: 0,
+            fetched: vec![0; sources.len()],
+            blamed: Vec::new(),
+        };
+        let mut live = vec![true; sources.len()];
+        let mut content_len = None;
+        for (i, source) in sources.iter_mut().enumerate() {
+            match fetch_len(source, hash) {
+                Ok(len) => {
+                    content_len = Some(len);
+                    break;
+                }
+                Err(e) => {
+                    live[i] = false;
+                    stats.blame(i, u64::MAX..u64::MAX, e);
+                }
+            }
+        }
+        let content_len = match content_len {
+            Some(len) => len,
+            None => return Err(stats.blamed.pop().unwrap().error),
+        };
+        stats.content_len = content_len;
+        let mut pending = VecDeque::new();
+        let mut start = 0;
+        while start < content_len {
+            let end = cmp::min(start.saturating_add(self.range_len), content_len);
+            pending.push_back(start..end);
+            start = end;
+        }
+        thread::scope(|scope| {
+            let (results_sender, results) = mpsc::channel();
+            // A sender of ranges to each live source. Dropping one stops its thread.
+            let mut jobs: Vec<Option<mpsc::Sender<Range<u64>>>> = Vec::new();
+            let mut idle = VecDeque::new();
+            for (i, source) in sources.iter_mut().enumerate() {
+                if !live[i] {
+                    jobs.push(None);
+                    continue;
+                }
+                let (job_sender, job_receiver) = mpsc::channel::<Range<u64>>();
+                let results_sender = results_sender.clone();
+                scope.spawn(move || {
+                    for range in job_receiver {
+                        let result = fetch_range(source, hash, content_len, range.clone());
+                        if results_sender.send((i, range, result)).is_err() {
+                            return;
+                        }
+                    }
+                });
+                jobs.push(Some(job_sender));
+                idle.push_back(i);
+            }
+            drop(results_sender);
+            let mut in_flight = 0;
+            loop {
+                while !pending.is_empty() && !idle.is_empty() {
+                    let i = idle.pop_front().unwrap();
+                    let range = pending.pop_front().unwrap();
+                    jobs[i].as_ref().unwrap().send(range).unwrap();
+                    in_flight += 1;
+                }
+                if in_flight == 0 {
+                    if pending.is_empty() {
+                        break;
+                    }
+                    return Err(stats.blamed.pop().unwrap().error);
+                }
+                // Every source with a range in flight still has a thread that will answer.
+                let (i, range, result) = results.recv().unwrap();
+                in_flight -= 1;
+                match result {
+                    Ok(content) => {
+                        output.seek(SeekFrom::Start(range.start))?;
+                        output.write_all(&content)?;
+                        stats.fetched[i] += content.len() as u64;
+                        idle.push_back(i);
+                    }
+                    Err(e) => {
+                        jobs[i] = None;
+                        pending.push_front(range.clone());
+                        stats.blame(i, range, e);
+                    }
+                }
+            }
+            output.flush()?;
+            Ok(())
+        })?;
+        Ok(stats)
+    }
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DownloadStats {
+    fn blame(&mut self, source: usize, range: Range<u64>, error: io::Error) {
+        self.blamed.push(Blame {
+            source,
+            range,
+            error,
+        });
+    }
+}
+
+// Fetch the slice past the end of the content, which includes the final chunk, and return the
+// verified length. We don't know the length yet, but the slice can't be longer than the parent
+// nodes of the deepest tree and one chunk.
+fn fetch_len(source: &mut impl Source, hash: &Hash) -> io::Result<u64> {
+    let max_size = HEADER_SIZE + MAX_DEPTH * PARENT_SIZE + CHUNK_SIZE;
+    let slice = fetch_limited(source, hash, u64::MAX, 0, max_size as u128)?;
+    let mut input = Cursor::new(&slice[..]);
+    let mut decoder = SliceDecoder::new(&mut input, hash, u64::MAX, 0);
+    let copied = io::copy(&mut decoder, &mut io::sink())?;
+    if copied != 0 || input.position() != slice.len() as u64 {
+        return Err(wrong_length());
+    }
+    Ok(crate::decode_len(array_ref!(slice, 0, HEADER_SIZE)))
+}
+
+// Fetch a range of the content and return it, once it's verified.
+fn fetch_range(
+    source: &mut impl Source,
+    hash: &Hash,
+    content_len: u64,
+    range: Range<u64>,
+) -> io::Result<Vec<u8>> {
+    let len = range.end - range.start;
+    let max_size = encode::slice_size(content_len, range.start, len);
+    let slice = fetch_limited(source, hash, range.start, len, max_size)?;
+    // The decoder verifies the content, but this slice could still claim a different length than
+    // the one we verified at the start.
+    if slice.len() < HEADER_SIZE {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    if crate::decode_len(array_ref!(slice, 0, HEADER_SIZE)) != content_len {
+        return Err(wrong_length());
+    }
+    let mut input = Cursor::new(&slice[..]);
+    let mut decoder = SliceDecoder::new(&mut input, hash, range.start, len);
+    let mut content = Vec::with_capacity(len as usize);
+    decoder.read_to_end(&mut content)?;
+    if content.len() as u64 != len || input.position() != slice.len() as u64 {
+        return Err(wrong_length());
+    }
+    Ok(content)
+}
+
+// Fetch a slice, failing as soon as the source writes more than `max_size` bytes of it.
+fn fetch_limited(
+    source: &mut impl Source,
+    hash: &Hash,
+    start: u64,
+    len: u64,
+    max_size: u128,
+) -> io::Result<Vec<u8>> {
+    let mut output = LimitedWriter {
+        buf: Vec::new(),
+        max_size,
+        overflowed: false,
+    };
+    let result = source.fetch_slice(hash, start, len, &mut output);
+    // The source might have ignored the error from the writer.
+    if output.overflowed {
+        return Err(wrong_length());
+    }
+    result?;
+    Ok(output.buf)
+}
+
+// A Vec that a source can't write past the end of the slice it was asked for.
+struct LimitedWriter {
+    buf: Vec<u8>,
+    max_size: u128,
+    overflowed: bool,
+}
+
+impl Write for LimitedWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.buf.len() as u128 + data.len() as u128 > self.max_size {
+            self.overflowed = true;
+            return Err(wrong_length());
+        }
+        self.buf.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn wrong_length() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "slice is the wrong length")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decode::make_test_input;
+    use crate::encode::{self, SliceExtractor};
+    use std::collections::HashSet;
+
+    #[derive(Clone, Copy, Debug)]
+    enum Fault {
+        Honest,
+        // Flip a bit in the last byte of every slice.
+        Corrupt,
+        // Leave off the last byte of every slice.
+        Truncate,
+        // Return NotFound.
+        Missing,
+        // Change the length header of every slice.
+        WrongLength,
+        // Keep writing zeros after every slice, until the output fails.
+        Endless,
+        // Serve this many slices, and then corrupt the rest.
+        HonestFor(usize),
+    }
+
+    struct MockSource {
+        encoded: Vec<u8>,
+        fault: Fault,
+        served: usize,
+    }
+
+    impl MockSource {
+        fn new(encoded: &[u8], fault: Fault) -> Self {
+            Self {
+                encoded: encoded.to_vec(),
+                fault,
+                served: 0,
+            }
+        }
+    }
+
+    impl Source for MockSource {
+        fn fetch_slice(
+            &mut self,
+            _hash: &Hash,
+            start: u64,
+            len: u64,
+            output: &mut dyn Write,
+        ) -> io::Result<()> {
+            let mut slice = Vec::new();
+            SliceExtractor::new(Cursor::new(&self.encoded), start, len).read_to_end(&mut slice)?;
+            self.served += 1;
+            let fault = match self.fault {
+                Fault::HonestFor(n) if self.served <= n => Fault::Honest,
+                Fault::HonestFor(_) => Fault::Corrupt,
+                fault => fault,
+            };
+            match fault {
+                Fault::Honest | Fault::HonestFor(_) => {}
+                Fault::Corrupt => *slice.last_mut().unwrap() ^= 1,
+                Fault::Truncate => {
+                    slice.pop();
+                }
+                Fault::Missing => return Err(io::ErrorKind::NotFound.into()),
+                Fault::Endless => {
+                    output.write_all(&slice)?;
+                    loop {
+                        output.write_all(&[0; CHUNK_SIZE])?;
+                    }
+                }
+                Fault::WrongLength => {
+                    let len = crate::decode_len(array_ref!(slice, 0, HEADER_SIZE));
+                    slice[..HEADER_SIZE].copy_from_slice(&crate::encode_len(len + 1));
+                }
+            }
+            output.write_all(&slice)
+        }
+    }
+
+    fn blamed_sources(stats: &DownloadStats) -> HashSet<usize> {
+        stats.blamed.iter().map(|blame| blame.source).collect()
+    }
+
+    #[test]
+    fn test_download() {
+        let faults = [
+            Fault::Honest,
+            Fault::Corrupt,
+            Fault::Truncate,
+            Fault::Missing,
+            Fault::WrongLength,
+            Fault::Honest,
+        ];
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (encoded, hash) = encode::encode(&input);
+            let mut sources: Vec<MockSource> = faults
+                .iter()
+                .map(|&fault| MockSource::new(&encoded, fault))
+                .collect();
+            let mut output = Cursor::new(Vec::new());
+            let stats = Downloader::new()
+                .range_len(2 * CHUNK_SIZE as u64)
+                .download(&hash, &mut sources, &mut output)
+                .unwrap();
+            assert_eq!(input, output.into_inner());
+            assert_eq!(case as u64, stats.content_len);
+            assert_eq!(case as u64, stats.fetched.iter().sum::<u64>());
+            // Each bad source gets one of the first ranges, if there are enough of them.
+            let bad: HashSet<usize> = (1..5).collect();
+            let blamed = blamed_sources(&stats);
+            if case > 8 * CHUNK_SIZE {
+                assert_eq!(bad, blamed);
+            } else {
+                assert!(blamed.is_subset(&bad));
+            }
+            for blame in &stats.blamed {
+                assert_eq!(0, stats.fetched[blame.source]);
+                let expected_kind = match faults[blame.source] {
+                    Fault::Truncate => io::ErrorKind::UnexpectedEof,
+                    Fault::Missing => io::ErrorKind::NotFound,
+                    _ => io::ErrorKind::InvalidData,
+                };
+                assert_eq!(expected_kind, blame.error.kind());
+            }
+        }
+    }
+
+    #[test]
+    fn test_download_retries() {
+        let range_len = 4 * CHUNK_SIZE as u64;
+        let input = make_test_input(100 * range_len as usize);
+        let (encoded, hash) = encode::encode(&input);
+
+        // Bad sources ahead of a good one get blamed for the length.
+        let mut sources = vec![
+            MockSource::new(&encoded, Fault::Corrupt),
+            MockSource::new(&encoded, Fault::Truncate),
+            MockSource::new(&encoded, Fault::Honest),
+        ];
+        let mut output = Cursor::new(Vec::new());
+        let stats = download(&hash, &mut sources, &mut output).unwrap();
+        assert_eq!(input, output.into_inner());
+        assert_eq!(2, stats.blamed.len());
+        for (i, blame) in stats.blamed.iter().enumerate() {
+            assert_eq!(i, blame.source);
+            assert_eq!(u64::MAX..u64::MAX, blame.range);
+        }
+        assert_eq!(vec![0, 0, input.len() as u64], stats.fetched);
+
+        // A source that goes bad partway through has its range fetched again by another. The
+        // length is the first slice it serves.
+        let mut sources: Vec<Box<dyn Source + Send>> = vec![
+            Box::new(MockSource::new(&encoded, Fault::HonestFor(11))),
+            Box::new(MockSource::new(&encoded, Fault::Honest)),
+        ];
+        let mut output = Cursor::new(Vec::new());
+        let stats = Downloader::new()
+            .range_len(range_len)
+            .download(&hash, &mut sources, &mut output)
+            .unwrap();
+        assert_eq!(input, output.into_inner());
+        assert_eq!(1, stats.blamed.len());
+        assert_eq!(0, stats.blamed[0].source);
+        assert_eq!(
+            range_len,
+            stats.blamed[0].range.end - stats.blamed[0].range.start
+        );
+        assert_eq!(io::ErrorKind::InvalidData, stats.blamed[0].error.kind());
+        assert_eq!(10 * range_len, stats.fetched[0]);
+        assert_eq!(90 * range_len, stats.fetched[1]);
+    }
+
+    #[test]
+    fn test_download_errors() {
+        let input = make_test_input(10 * CHUNK_SIZE);
+        let (encoded, hash) = encode::encode(&input);
+
+        let err = download::<MockSource>(&hash, &mut [], Cursor::new(Vec::new())).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        // When every source is dropped, the error is the last one's.
+        let faults = [
+            (Fault::Corrupt, io::ErrorKind::InvalidData),
+            (Fault::Truncate, io::ErrorKind::UnexpectedEof),
+            (Fault::Missing, io::ErrorKind::NotFound),
+            (Fault::WrongLength, io::ErrorKind::InvalidData),
+            (Fault::Endless, io::ErrorKind::InvalidData),
+            (Fault::HonestFor(3), io::ErrorKind::InvalidData),
+        ];
+        for &(fault, kind) in &faults {
+            println!("fault {:?}", fault);
+            let mut sources = vec![
+                MockSource::new(&encoded, Fault::Missing),
+                MockSource::new(&encoded, fault),
+            ];
+            let mut output = Cursor::new(Vec::new());
+            let err = Downloader::new()
+                .range_len(1)
+                .download(&hash, &mut sources, &mut output)
+                .unwrap_err();
+            assert_eq!(kind, err.kind());
+            // Only verified content gets written.
+            let output = output.into_inner();
+            assert_eq!(&input[..output.len()], &*output);
+        }
+
+        // A source that keeps writing past the end of a slice gets cut off, for a range as well
+        // as for the length above.
+        let mut sources = vec![
+            MockSource::new(&encoded, Fault::Honest),
+            MockSource::new(&encoded, Fault::Endless),
+        ];
+        let mut output = Cursor::new(Vec::new());
+        let stats = Downloader::new()
+            .range_len(CHUNK_SIZE as u64)
+            .download(&hash, &mut sources, &mut output)
+            .unwrap();
+        assert_eq!(input, output.into_inner());
+        assert_eq!(1, stats.blamed.len());
+        assert_eq!(1, stats.blamed[0].source);
+        assert_eq!(
+            CHUNK_SIZE as u64,
+            stats.blamed[0].range.end - stats.blamed[0].range.start
+        );
+        assert_eq!(io::ErrorKind::InvalidData, stats.blamed[0].error.kind());
+
+        // A source for other content doesn't match the hash.
+        let (other_encoded, _) = encode::encode(make_test_input(CHUNK_SIZE));
+        let mut sources = vec![MockSource::new(&other_encoded, Fault::Honest)];
+        let err = download(&hash, &mut sources, Cursor::new(Vec::new())).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_zero_range_len() {
+        Downloader::new().range_len(0);
+    }
+}
@@ -12,6 +12,16 @@
 //! copying any input bytes. The outboard encoding is much smaller, but it can
 //! only be used together with the original input file.
 //!
+//! Outboard encodings can also be left in post-order, which is how the `Encoder` writes them
+//! before `finalize` flips them. See
+//! [`Encoder::new_outboard_post_order`](struct.Encoder.html#method.new_outboard_post_order).
+//!
+//! Either mode can also group chunks together. With a `chunk_group_log` of N, the lowest N levels
+//! of the tree are left out, and each leaf of the encoded tree is a group of 2^N chunks. That
+//! makes an outboard encoding 2^N times smaller, at the cost of slices being aligned to whole
+//! chunk groups. The root hash is still the regular BLAKE3 hash. See
+//! [`Encoder::new_grouped`](struct.Encoder.html#method.new_grouped).
+//!
 //! # Example
 //!
 //! ```
@@ -36,9 +46,12 @@
 //! # }
 //! ```
 
+use crate::outboard::{OutboardReader, OutboardStore};
 use crate::Finalization::{self, NotRoot, Root};
-use crate::{Hash, ParentNode, CHUNK_SIZE, HASH_SIZE, HEADER_SIZE, MAX_DEPTH, PARENT_SIZE};
-use arrayref::array_mut_ref;
+use crate::{
+    ChunkState, Hash, Mode, ParentNode, CHUNK_SIZE, HASH_SIZE, HEADER_SIZE, MAX_DEPTH, PARENT_SIZE,
+};
+use arrayref::{array_mut_ref, array_ref};
 use arrayvec::ArrayVec;
 use std::cmp;
 use std::fmt;
@@ -68,43 +81,148 @@ pub fn outboard(input: impl AsRef<[u8]>) -> (Vec<u8>, Hash) {
     (vec, hash)
 }
 
+/// The largest supported `chunk_group_log`. Groups of 2^16 chunks are 64 MiB, and encoders and
+/// decoders buffer up to one group in memory.
+pub const MAX_CHUNK_GROUP_LOG: u8 = 16;
+
 /// Compute the size of a combined encoding, given the size of the input. Note that for input sizes
 /// close to `u64::MAX`, the result can overflow a `u64`.
 pub fn encoded_size(content_len: u64) -> u128 {
-    content_len as u128 + outboard_size(content_len)
+    encoded_size_grouped(content_len, 0)
 }
 
 /// Compute the size of an outboard encoding, given the size of the input.
 pub fn outboard_size(content_len: u64) -> u128 {
+    outboard_size_grouped(content_len, 0)
+}
+
+/// Compute the size of a slice, given the size of the input and the slice parameters. This is the
+/// number of bytes that `SliceExtractor` will produce and that `SliceDecoder` will consume.
+pub fn slice_size(content_len: u64, slice_start: u64, slice_len: u64) -> u128 {
+    slice_size_grouped(content_len, slice_start, slice_len, 0)
+}
+
+/// Like [`encoded_size`](fn.encoded_size.html), for an encoding with chunk groups of
+/// 2^`chunk_group_log` chunks.
+pub fn encoded_size_grouped(content_len: u64, chunk_group_log: u8) -> u128 {
+    content_len as u128 + outboard_size_grouped(content_len, chunk_group_log)
+}
+
+/// Like [`outboard_size`](fn.outboard_size.html), for an encoding with chunk groups of
+/// 2^`chunk_group_log` chunks.
+pub fn outboard_size_grouped(content_len: u64, chunk_group_log: u8) -> u128 {
     // Should the return type here really by u128? Two reasons: 1) It's convenient to use the same
     // type as encoded_size(), and 2) if we're ever experimenting with very small chunk sizes, we
     // could indeed overflow u64.
-    outboard_subtree_size(content_len) + HEADER_SIZE as u128
+    outboard_subtree_size_grouped(content_len, chunk_group_log) + HEADER_SIZE as u128
+}
+
+/// Like [`slice_size`](fn.slice_size.html), for an encoding with chunk groups of
+/// 2^`chunk_group_log` chunks. Slices of these encodings include whole chunk groups.
+pub fn slice_size_grouped(
+    content_len: u64,
+    slice_start: u64,
+    slice_len: u64,
+    chunk_group_log: u8,
+) -> u128 {
+    let group_size = group_size(chunk_group_log);
+    if content_len == 0 {
+        // The empty encoding is just the header.
+        return HEADER_SIZE as u128;
+    }
+    // These match the SliceExtractor: the slice always includes at least one byte, and a slice
+    // starting at or past EOF includes the final chunk.
+    let start = cmp::min(slice_start, content_len - 1);
+    let end = cmp::min(start.saturating_add(cmp::max(slice_len, 1)), content_len);
+    let first_group = start / group_size;
+    let last_group = (end - 1) / group_size;
+    // The end of the last group can be 2^64 for the longest content, so saturate.
+    let content_bytes = cmp::min(content_len, (last_group + 1).saturating_mul(group_size))
+        - first_group * group_size;
+    let total_groups = count_groups(content_len, chunk_group_log);
+    let parents = count_parents_in_range(0, total_groups, first_group, last_group);
+    HEADER_SIZE as u128 + parents as u128 * PARENT_SIZE as u128 + content_bytes as u128
+}
+
+// Count the parent nodes in the subtree of `size` chunks starting at chunk index `start`, which
+// cover any of the chunks from `first` to `last` inclusive. These are exactly the parents that a
+// slice of those chunks includes. With chunk groups, this works the same way in units of groups.
+// Subtrees entirely inside or outside the range return right away, so this only recurses along
+// the two edges of the range.
+fn count_parents_in_range(start: u64, size: u64, first: u64, last: u64) -> u64 {
+    let end = start + size - 1;
+    if size == 1 || end < first || start > last {
+        0
+    } else if first <= start && end <= last {
+        size - 1
+    } else {
+        let left_size = left_subtree_chunks(size);
+        1 + count_parents_in_range(start, left_size, first, last)
+            + count_parents_in_range(start + left_size, size - left_size, first, last)
+    }
+}
+
+// The left subtree of a BLAKE3 tree always has the largest power-of-two number of chunks that
+// leaves at least one chunk for the right subtree.
+pub(crate) fn left_subtree_chunks(total_chunks: u64) -> u64 {
+    debug_assert!(total_chunks > 1);
+    1 << (63 - (total_chunks - 1).leading_zeros())
+}
+
+// The number of levels of parent nodes in a subtree with this many chunks. Together with the
+// subtree's first chunk index, this identifies its position in the tree. Height zero is a single
+// chunk.
+pub(crate) fn subtree_height(num_chunks: u64) -> u8 {
+    debug_assert!(num_chunks > 0);
+    (64 - (num_chunks - 1).leading_zeros()) as u8
 }
 
 pub(crate) fn encoded_subtree_size(content_len: u64) -> u128 {
-    content_len as u128 + outboard_subtree_size(content_len)
+    encoded_subtree_size_grouped(content_len, 0)
+}
+
+pub(crate) fn count_chunks(content_len: u64) -> u64 {
+    count_groups(content_len, 0)
+}
+
+pub(crate) fn chunk_size(chunk_index: u64, content_len: u64) -> usize {
+    group_len(chunk_index, content_len, 0)
+}
+
+// The rest of the tree works the same whether its leaves are chunks or groups of chunks. These
+// variants of the functions above take a group_log, and with a group_log of zero, each group is a
+// single chunk.
+
+pub(crate) fn group_size(group_log: u8) -> u64 {
+    debug_assert!(group_log <= MAX_CHUNK_GROUP_LOG);
+    (CHUNK_SIZE as u64) << group_log
+}
+
+pub(crate) fn encoded_subtree_size_grouped(content_len: u64, group_log: u8) -> u128 {
+    content_len as u128 + outboard_subtree_size_grouped(content_len, group_log)
 }
 
-pub(crate) fn outboard_subtree_size(content_len: u64) -> u128 {
+pub(crate) fn outboard_subtree_size_grouped(content_len: u64, group_log: u8) -> u128 {
     // The number of parent nodes is always the number of chunks minus one. To see why this is true,
     // start with a single chunk and incrementally add chunks to the tree. Each new chunk always
     // brings one parent node along with it.
-    let num_parents = count_chunks(content_len) - 1;
+    let num_parents = count_groups(content_len, group_log) - 1;
     num_parents as u128 * PARENT_SIZE as u128
 }
 
-pub(crate) fn count_chunks(content_len: u64) -> u64 {
+pub(crate) fn count_groups(content_len: u64, group_log: u8) -> u64 {
     // Two things to watch out for here: the 0-length input still counts as 1 chunk, and we don't
     // want to overflow when content_len is u64::MAX_VALUE.
-    let full_chunks: u64 = content_len / CHUNK_SIZE as u64;
-    let has_partial_chunk: bool = (content_len % CHUNK_SIZE as u64) != 0;
-    cmp::max(1, full_chunks + has_partial_chunk as u64)
+    let group_size = group_size(group_log);
+    let full_groups: u64 = content_len / group_size;
+    let has_partial_group: bool = (content_len % group_size) != 0;
+    cmp::max(1, full_groups + has_partial_group as u64)
 }
 
-pub(crate) fn chunk_size(chunk_index: u64, content_len: u64) -> usize {
-    let chunk_start = chunk_index * CHUNK_SIZE as u64;
-    cmp::min(CHUNK_SIZE, (content_len - chunk_start) as usize)
+pub(crate) fn group_len(group_index: u64, content_len: u64, group_log: u8) -> usize {
+    let group_size = group_size(group_log);
+    let group_start = group_index * group_size;
+    cmp::min(group_size, content_len - group_start) as usize
 }
 
 // ----------------------------------------------------------------------------
@@ -170,14 +288,23 @@ fn post_order_parent_nodes_final(chunk_index: u64) -> u8 {
 //
 // We then take the minimum of those two values, and that's the number of
 // parent nodes before each chunk.
+#[cfg(test)]
 pub(crate) fn pre_order_parent_nodes(chunk_index: u64, content_len: u64) -> u8 {
+    pre_order_parent_nodes_grouped(chunk_index, content_len, 0)
+}
+
+pub(crate) fn pre_order_parent_nodes_grouped(
+    chunk_index: u64,
+    content_len: u64,
+    group_log: u8,
+) -> u8 {
     fn bit_length(x: u64) -> u32 {
         // As mentioned above, note that this reports a bit length of 64 for
         // x=0. That works for us, because cmp::min below will always choose
         // the other rule, but think about it before you copy/paste this.
         64 - x.leading_zeros()
     }
-    let total_chunks = count_chunks(content_len);
+    let total_chunks = count_groups(content_len, group_log);
     debug_assert!(chunk_index < total_chunks);
     let total_chunks_after_this = total_chunks - chunk_index;
     let bit_length_rule = bit_length(total_chunks_after_this - 1);
@@ -196,18 +323,21 @@ pub(crate) fn pre_order_parent_nodes(chunk_index: u64, content_len: u64) -> u8 {
 struct FlipperState {
     parents: ArrayVec<[crate::ParentNode; MAX_DEPTH]>,
     content_len: u64,
+    group_log: u8,
+    // In units of chunk groups, which are single chunks by default.
     last_chunk_moved: u64,
     parents_needed: u8,
     parents_available: u8,
 }
 
 impl FlipperState {
-    pub fn new(content_len: u64) -> Self {
-        let total_chunks = count_chunks(content_len);
+    pub fn new(content_len: u64, group_log: u8) -> Self {
+        let total_chunks = count_groups(content_len, group_log);
         Self {
             parents: ArrayVec::new(),
             content_len,
-            last_chunk_moved: count_chunks(content_len), // one greater than the final chunk index
+            group_log,
+            last_chunk_moved: total_chunks, // one greater than the final chunk index
             parents_needed: post_order_parent_nodes_final(total_chunks - 1),
             parents_available: 0,
         }
@@ -221,7 +351,11 @@ impl FlipperState {
         } else if self.parents_needed > 0 {
             FlipperNext::FeedParent
         } else if self.last_chunk_moved > 0 {
-            FlipperNext::Chunk(chunk_size(self.last_chunk_moved - 1, self.content_len))
+            FlipperNext::Chunk(group_len(
+                self.last_chunk_moved - 1,
+                self.content_len,
+                self.group_log,
+            ))
         } else {
             FlipperNext::Done
         }
@@ -234,7 +368,8 @@ impl FlipperState {
         debug_assert_eq!(self.parents_available, 0);
         debug_assert_eq!(self.parents_needed, 0);
         self.last_chunk_moved -= 1;
-        self.parents_available = pre_order_parent_nodes(self.last_chunk_moved, self.content_len);
+        self.parents_available =
+            pre_order_parent_nodes_grouped(self.last_chunk_moved, self.content_len, self.group_log);
         if self.last_chunk_moved > 0 {
             self.parents_needed = post_order_parent_nodes_nonfinal(self.last_chunk_moved - 1);
         }
@@ -257,8 +392,17 @@ impl FlipperState {
 
 impl fmt::Debug for FlipperState {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "FlipperState {{ parents: {}, content_len: {}, last_chunk_moved: {}, parents_needed: {}, parents_available: {} }}",
-               self.parents.len(), self.content_len, self.last_chunk_moved, self.parents_needed, self.parents_available)
+        write!(
+            f,
+            "FlipperState {{ parents: {}, content_len: {}, group_log: {}, last_chunk_moved: {}, \
+             parents_needed: {}, parents_available: {} }}",
+            self.parents.len(),
+            self.content_len,
+            self.group_log,
+            self.last_chunk_moved,
+            self.parents_needed,
+            self.parents_available,
+        )
     }
 }
 
@@ -278,14 +422,27 @@ pub(crate) enum StateFinish {
 #[derive(Clone)]
 pub(crate) struct State {
     subtrees: ArrayVec<[Hash; MAX_DEPTH]>,
+    // The length of each subtree, for telling which parents are inside a chunk group.
+    subtree_lens: ArrayVec<[u64; MAX_DEPTH]>,
     total_len: u64,
+    mode: Mode,
+    group_log: u8,
 }
 
 impl State {
     pub fn new() -> Self {
+        Self::with_mode(Mode::Hash, 0)
+    }
+
+    // With a non-zero group_log, parent nodes inside of a chunk group are merged as usual, but
+    // they aren't returned.
+    pub fn with_mode(mode: Mode, group_log: u8) -> Self {
         Self {
             subtrees: ArrayVec::new(),
+            subtree_lens: ArrayVec::new(),
             total_len: 0,
+            mode,
+            group_log,
         }
     }
 
@@ -293,15 +450,24 @@ impl State {
         self.total_len
     }
 
-    fn merge_inner(&mut self, finalization: Finalization) -> ParentNode {
+    // Returns None for a parent node inside of a chunk group.
+    fn merge_inner(&mut self, finalization: Finalization) -> Option<ParentNode> {
         let right_child = self.subtrees.pop().unwrap();
         let left_child = self.subtrees.pop().unwrap();
-        let parent_cv = blake3::guts::parent_cv(&left_child, &right_child, finalization.is_root());
+        let parent_cv = self
+            .mode
+            .parent_cv(&left_child, &right_child, finalization.is_root());
         self.subtrees.push(parent_cv);
+        let right_len = self.subtree_lens.pop().unwrap();
+        let left_len = self.subtree_lens.pop().unwrap();
+        self.subtree_lens.push(left_len + right_len);
+        if left_len + right_len <= group_size(self.group_log) {
+            return None;
+        }
         let mut parent_node = [0; PARENT_SIZE];
         parent_node[..HASH_SIZE].copy_from_slice(left_child.as_bytes());
         parent_node[HASH_SIZE..].copy_from_slice(right_child.as_bytes());
-        parent_node
+        Some(parent_node)
     }
 
     // We keep the subtree hashes in an array without storing their size, and we use this cute
@@ -335,6 +501,7 @@ impl State {
     pub fn push_subtree(&mut self, hash: &Hash, len: usize) {
         debug_assert!(!self.needs_merge());
         self.subtrees.push(*hash);
+        self.subtree_lens.push(len as u64);
         // Overflow in the length is practically impossible if we're actually hashing the input,
         // since it would take several hundred CPU years of work. But it could happen if we're
         // doing something fancy with a sparse tree. In general, the BLAKE3 hash of more than u64::MAX
@@ -357,10 +524,14 @@ impl State {
     /// After the final call to `push_subtree`, you must call `merge_finalize` in a loop instead of
     /// this function.
     pub fn merge_parent(&mut self) -> Option<ParentNode> {
-        if !self.needs_merge() {
-            return None;
+        // Subtrees only get bigger as they merge, so any parents inside of a chunk group come
+        // before the rest.
+        while self.needs_merge() {
+            if let Some(parent) = self.merge_inner(NotRoot) {
+                return Some(parent);
+            }
         }
-        Some(self.merge_inner(NotRoot))
+        None
     }
 
     /// Returns a tuple of `ParentNode` bytes and (in the last call only) the root hash. Callers
@@ -368,13 +539,17 @@ impl State {
     /// subtree, until the second return value is `Some`. Callers who don't need parent nodes
     /// should use the simpler `finalize` interface instead.
     pub fn merge_finalize(&mut self) -> StateFinish {
-        if self.subtrees.len() > 2 {
-            StateFinish::Parent(self.merge_inner(NotRoot))
-        } else if self.subtrees.len() == 2 {
-            StateFinish::Parent(self.merge_inner(Root))
-        } else {
-            StateFinish::Root(self.subtrees[0])
+        while self.subtrees.len() > 1 {
+            let finalization = if self.subtrees.len() > 2 {
+                NotRoot
+            } else {
+                Root
+            };
+            if let Some(parent) = self.merge_inner(finalization) {
+                return StateFinish::Parent(parent);
+            }
         }
+        StateFinish::Root(self.subtrees[0])
     }
 }
 
@@ -408,9 +583,18 @@ impl fmt::Debug for State {
 #[derive(Clone, Debug)]
 pub struct Encoder<T: Read + Write + Seek> {
     inner: T,
-    chunk_state: blake3::guts::ChunkState,
+    chunk_state: ChunkState,
+    // A copy of the bytes in chunk_state, which doesn't give them back, for checkpoints. Only the
+    // bytes of a chunk that's still partial get copied, and the bytes of the first chunk, which
+    // might be the root. A checkpoint stores the CV of any other full chunk instead.
+    chunk_buf: [u8; CHUNK_SIZE],
+    // The CV of a full chunk from a checkpoint, added to the tree state once we know whether
+    // there's more input. chunk_state is empty while this is set.
+    full_chunk_cv: Option<Hash>,
     tree_state: State,
     outboard: bool,
+    post_order: bool,
+    mode: Mode,
 }
 
 impl<T: Read + Write + Seek> Encoder<T> {
@@ -418,12 +602,7 @@ impl<T: Read + Write + Seek> Encoder<T> {
     /// the input bytes, so that it can be decoded without the original input file. This is what
     /// you get from `bao encode`.
     pub fn new(inner: T) -> Self {
-        Self {
-            inner,
-            chunk_state: blake3::guts::ChunkState::new(0),
-            tree_state: State::new(),
-            outboard: false,
-        }
+        Self::with_mode(inner, Mode::Hash)
     }
 
     /// Create a new `Encoder` for making an outboard encoding. That means that the encoding won't
@@ -436,6 +615,222 @@ impl<T: Read + Write + Seek> Encoder<T> {
         encoder
     }
 
+    /// Create a new `Encoder` for the combined mode, whose root hash is the BLAKE3
+    /// [`keyed_hash`](https://docs.rs/blake3/latest/blake3/fn.keyed_hash.html) of the input with
+    /// `key`. Decoding it requires the same key, for example with
+    /// [`Decoder::new_keyed`](../decode/struct.Decoder.html#method.new_keyed). Equal inputs
+    /// encoded with different keys have unrelated hashes.
+    pub fn new_keyed(inner: T, key: &[u8; blake3::KEY_LEN]) -> Self {
+        Self::with_mode(inner, Mode::KeyedHash(*key))
+    }
+
+    /// Create a new `Encoder` for an outboard encoding in post-order, which skips the expensive
+    /// flip at the end of [`finalize`](#method.finalize). The format is the parent nodes of the
+    /// tree in post-order, that is, each parent node comes right after its right subtree, followed
+    /// by the 8-byte little-endian length header at the very end. It's the same size as the usual
+    /// outboard encoding. Decoding it requires a
+    /// [`PostOrderOutboard`](../outboard/struct.PostOrderOutboard.html).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use bao::outboard::PostOrderOutboard;
+    /// use std::io::prelude::*;
+    /// use std::io::Cursor;
+    ///
+    /// let input = vec![0xab; 100_000];
+    /// let mut outboard = Vec::new();
+    /// let mut encoder = bao::encode::Encoder::new_outboard_post_order(Cursor::new(&mut outboard));
+    /// encoder.write_all(&input)?;
+    /// let hash = encoder.finalize()?;
+    ///
+    /// let store = PostOrderOutboard::new(Cursor::new(&outboard));
+    /// let mut decoder = bao::decode::Decoder::new_outboard_store(&*input, store, &hash);
+    /// let mut output = Vec::new();
+    /// decoder.read_to_end(&mut output)?;
+    /// assert_eq!(input, output);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn new_outboard_post_order(inner: T) -> Self {
+        let mut encoder = Self::new_outboard(inner);
+        encoder.post_order = true;
+        encoder
+    }
+
+    /// Create a new `Encoder` for the outboard mode with a key, like
+    /// [`new_keyed`](#method.new_keyed).
+    pub fn new_outboard_keyed(inner: T, key: &[u8; blake3::KEY_LEN]) -> Self {
+        let mut encoder = Self::new_keyed(inner, key);
+        encoder.outboard = true;
+        encoder
+    }
+
+    /// Create a new `Encoder` for the combined mode, whose root hash is the BLAKE3
+    /// [`derive_key`](https://docs.rs/blake3/latest/blake3/fn.derive_key.html) output for
+    /// `context`, with the input as the key material. Decoding it requires the same context
+    /// string, for example with
+    /// [`Decoder::new_derive_key`](../decode/struct.Decoder.html#method.new_derive_key).
+    pub fn new_derive_key(inner: T, context: &str) -> Self {
+        Self::with_mode(inner, Mode::derive_key(context))
+    }
+
+    /// Create a new `Encoder` for the outboard mode with a context string, like
+    /// [`new_derive_key`](#method.new_derive_key).
+    pub fn new_outboard_derive_key(inner: T, context: &str) -> Self {
+        let mut encoder = Self::new_derive_key(inner, context);
+        encoder.outboard = true;
+        encoder
+    }
+
+    /// Create a new `Encoder` for the combined mode, with chunk groups of 2^`chunk_group_log`
+    /// chunks as the leaves of the tree. The root hash is the same as with
+    /// [`new`](#method.new), but the encoding has fewer parent nodes, and it has to be decoded
+    /// with the same `chunk_group_log`, for example with
+    /// [`Decoder::new_grouped`](../decode/struct.Decoder.html#method.new_grouped). A
+    /// `chunk_group_log` of zero is the same as `new`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_group_log` is greater than
+    /// [`MAX_CHUNK_GROUP_LOG`](constant.MAX_CHUNK_GROUP_LOG.html).
+    pub fn new_grouped(inner: T, chunk_group_log: u8) -> Self {
+        assert!(
+            chunk_group_log <= MAX_CHUNK_GROUP_LOG,
+            "chunk_group_log is too large",
+        );
+        let mut encoder = Self::new(inner);
+        encoder.tree_state = State::with_mode(Mode::Hash, chunk_group_log);
+        encoder
+    }
+
+    /// Create a new `Encoder` for the outboard mode with chunk groups, like
+    /// [`new_grouped`](#method.new_grouped).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_group_log` is greater than
+    /// [`MAX_CHUNK_GROUP_LOG`](constant.MAX_CHUNK_GROUP_LOG.html).
+    pub fn new_outboard_grouped(inner: T, chunk_group_log: u8) -> Self {
+        let mut encoder = Self::new_grouped(inner, chunk_group_log);
+        encoder.outboard = true;
+        encoder
+    }
+
+    fn with_mode(inner: T, mode: Mode) -> Self {
+        Self {
+            inner,
+            chunk_state: mode.chunk_state(0),
+            chunk_buf: [0; CHUNK_SIZE],
+            full_chunk_cv: None,
+            tree_state: State::with_mode(mode, 0),
+            outboard: false,
+            post_order: false,
+            mode,
+        }
+    }
+
+    /// Save the state of the encoder, so that encoding can be resumed later with
+    /// [`resume`](#method.resume), for example after the process restarts. This flushes the
+    /// underlying writer, but callers writing to a file should also sync it before relying on the
+    /// checkpoint.
+    ///
+    /// For an encoder created with a key or a context string, the checkpoint holds the key, or
+    /// the key derived from the context string, so keep it secret.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use bao::encode::{Encoder, EncoderCheckpoint};
+    /// use std::io::prelude::*;
+    ///
+    /// let mut output = std::io::Cursor::new(Vec::new());
+    /// let mut encoder = Encoder::new(&mut output);
+    /// encoder.write_all(b"some ")?;
+    /// let saved = encoder.checkpoint()?.serialize();
+    ///
+    /// // Later, after a restart...
+    /// let checkpoint = EncoderCheckpoint::deserialize(&saved)?;
+    /// let mut encoder = Encoder::resume(&mut output, &checkpoint)?;
+    /// encoder.write_all(b"input")?;
+    /// let hash = encoder.finalize()?;
+    /// assert_eq!(bao::encode::encode(b"some input"), (output.into_inner(), hash));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn checkpoint(&mut self) -> io::Result<EncoderCheckpoint> {
+        self.inner.flush()?;
+        let current_chunk = if let Some(cv) = self.full_chunk_cv {
+            CurrentChunk::Full(cv)
+        } else if self.chunk_state.len() == CHUNK_SIZE && self.tree_state.count() > 0 {
+            CurrentChunk::Full(self.chunk_state.finalize(false))
+        } else {
+            CurrentChunk::Partial(self.chunk_buf[..self.chunk_state.len()].to_vec())
+        };
+        Ok(EncoderCheckpoint {
+            outboard: self.outboard,
+            post_order: self.post_order,
+            mode: self.mode,
+            tree_state: self.tree_state.clone(),
+            current_chunk,
+        })
+    }
+
+    /// Resume encoding from a checkpoint taken with [`checkpoint`](#method.checkpoint).
+    /// `inner` must be the same output the checkpointed encoder was writing to, containing at
+    /// least the [`position`](struct.EncoderCheckpoint.html#method.position) bytes it had written.
+    /// If more bytes were written after the checkpoint, truncate the output to that position
+    /// first. Continuing with the same input produces output identical to an encoding that was
+    /// never interrupted, with the same root hash.
+    ///
+    /// If the output is shorter than the checkpoint position, this returns an error of kind
+    /// `UnexpectedEof`.
+    pub fn resume(mut inner: T, checkpoint: &EncoderCheckpoint) -> io::Result<Self> {
+        let position = checkpoint.position();
+        if inner.seek(SeekFrom::End(0))? < position {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "output is shorter than the checkpoint",
+            ));
+        }
+        inner.seek(SeekFrom::Start(position))?;
+        let chunk_counter = checkpoint.tree_state.count() / CHUNK_SIZE as u64;
+        let mut chunk_buf = [0; CHUNK_SIZE];
+        let (chunk_state, full_chunk_cv) = match &checkpoint.current_chunk {
+            CurrentChunk::Partial(bytes) => {
+                let mut chunk_state = checkpoint.mode.chunk_state(chunk_counter);
+                chunk_state.update(bytes);
+                chunk_buf[..bytes.len()].copy_from_slice(bytes);
+                (chunk_state, None)
+            }
+            CurrentChunk::Full(cv) => (checkpoint.mode.chunk_state(chunk_counter + 1), Some(*cv)),
+        };
+        Ok(Self {
+            inner,
+            chunk_state,
+            chunk_buf,
+            full_chunk_cv,
+            tree_state: checkpoint.tree_state.clone(),
+            outboard: checkpoint.outboard,
+            post_order: checkpoint.post_order,
+            mode: checkpoint.mode,
+        })
+    }
+
+    // Add the CV of a full chunk to the tree state, and write out any completed parent nodes. The
+    // caller has to know there's more input coming.
+    fn push_full_chunk(&mut self, chunk_cv: &Hash) -> io::Result<()> {
+        self.tree_state.push_subtree(chunk_cv, CHUNK_SIZE);
+        let chunk_counter = self.tree_state.count() / CHUNK_SIZE as u64;
+        self.chunk_state = self.mode.chunk_state(chunk_counter);
+        while let Some(parent) = self.tree_state.merge_parent() {
+            self.inner.write_all(&parent)?;
+        }
+        Ok(())
+    }
+
     /// Finalize the encoding, after all the input has been written. You can't
     /// use this `Encoder` again after calling `finalize`.
     ///
@@ -443,7 +838,12 @@ impl<T: Read + Write + Seek> Encoder<T> {
     /// and then to go back and flip the entire thing into pre-order. That makes it possible to
     /// stream input without knowing its length in advance, which is a core requirement of the
     /// `std::io::Write` interface. The downside is that `finalize` is a relatively expensive step.
+    /// An `Encoder` from [`new_outboard_post_order`](#method.new_outboard_post_order) skips it.
     pub fn finalize(&mut self) -> io::Result<Hash> {
+        if let Some(cv) = self.full_chunk_cv.take() {
+            self.tree_state.push_subtree(&cv, CHUNK_SIZE);
+        }
+
         // Compute the total len before we merge the final chunk into the
         // tree_state.
         let total_len = self
@@ -480,7 +880,9 @@ impl<T: Read + Write + Seek> Encoder<T> {
 
         // Finally, flip the tree to be pre-order. This means rewriting the
         // entire output, so it's expensive.
-        self.flip_post_order_stream()?;
+        if !self.post_order {
+            self.flip_post_order_stream()?;
+        }
 
         Ok(root_hash)
     }
@@ -492,7 +894,7 @@ impl<T: Read + Write + Seek> Encoder<T> {
         self.inner.seek(SeekFrom::Start(read_cursor))?;
         self.inner.read_exact(&mut header)?;
         let content_len = crate::decode_len(&header);
-        let mut flipper = FlipperState::new(content_len);
+        let mut flipper = FlipperState::new(content_len, self.tree_state.group_log);
         loop {
             match flipper.next() {
                 FlipperNext::FeedParent => {
@@ -511,17 +913,22 @@ impl<T: Read + Write + Seek> Encoder<T> {
                     write_cursor -= PARENT_SIZE as u64;
                 }
                 FlipperNext::Chunk(size) => {
-                    // In outboard moded, we skip over chunks.
-                    if !self.outboard {
+                    // In outboard moded, we skip over chunks. A chunk group gets moved a chunk at
+                    // a time, from the back. Everything moves to the right, so that doesn't
+                    // overwrite anything we haven't read yet.
+                    let mut remaining = if self.outboard { 0 } else { size };
+                    while remaining > 0 {
+                        let piece = cmp::min(remaining, CHUNK_SIZE);
                         let mut chunk = [0; CHUNK_SIZE];
                         self.inner
-                            .seek(SeekFrom::Start(read_cursor - size as u64))?;
-                        self.inner.read_exact(&mut chunk[..size])?;
-                        read_cursor -= size as u64;
+                            .seek(SeekFrom::Start(read_cursor - piece as u64))?;
+                        self.inner.read_exact(&mut chunk[..piece])?;
+                        read_cursor -= piece as u64;
                         self.inner
-                            .seek(SeekFrom::Start(write_cursor - size as u64))?;
-                        self.inner.write_all(&chunk[..size])?;
-                        write_cursor -= size as u64;
+                            .seek(SeekFrom::Start(write_cursor - piece as u64))?;
+                        self.inner.write_all(&chunk[..piece])?;
+                        write_cursor -= piece as u64;
+                        remaining -= piece;
                     }
                     flipper.chunk_moved();
                 }
@@ -536,18 +943,177 @@ impl<T: Read + Write + Seek> Encoder<T> {
     }
 }
 
+// Serialized encoder checkpoints start with these magic bytes and a format version, so that other
+// bytes, or a checkpoint in some future format, are rejected rather than misread.
+const ENCODER_CHECKPOINT_MAGIC: [u8; 4] = *b"baoE";
+const ENCODER_CHECKPOINT_VERSION: u8 = 1;
+
+/// The saved state of an [`Encoder`](struct.Encoder.html), from
+/// [`Encoder::checkpoint`](struct.Encoder.html#method.checkpoint).
+///
+/// This holds the subtree hashes along the right edge of the tree so far and the bytes of the
+/// current partial chunk, up to a kilobyte, but not the rest of the output, which is still in the
+/// encoder's underlying writer. It also holds the hash mode, including the key for an encoder
+/// created with a key or a context string. It can be serialized to bytes and back.
+#[derive(Clone)]
+pub struct EncoderCheckpoint {
+    outboard: bool,
+    post_order: bool,
+    mode: Mode,
+    tree_state: State,
+    current_chunk: CurrentChunk,
+}
+
+// The chunk an encoder was in the middle of at a checkpoint, which isn't in the tree state yet.
+#[derive(Clone)]
+enum CurrentChunk {
+    // The bytes of a partial chunk, or of a full first chunk, which might turn out to be the root.
+    Partial(Vec<u8>),
+    // The non-root CV of a full chunk after the first.
+    Full(Hash),
+}
+
+impl EncoderCheckpoint {
+    /// The number of input bytes written to the encoder before the checkpoint.
+    pub fn content_len(&self) -> u64 {
+        let current_len = match &self.current_chunk {
+            CurrentChunk::Partial(bytes) => bytes.len(),
+            CurrentChunk::Full(_) => CHUNK_SIZE,
+        };
+        self.tree_state.count() + current_len as u64
+    }
+
+    /// The number of bytes the encoder had written to its underlying writer, which is where
+    /// writing resumes. The output is in post-order until the encoder is finalized.
+    pub fn position(&self) -> u64 {
+        // Parent nodes get written for the tree of completed chunk groups, with one subtree per
+        // bit of their count, as in State::needs_merge. The content bytes of the partial chunk
+        // have been written in the combined mode too.
+        let groups = self.tree_state.count() / group_size(self.tree_state.group_log);
+        let parents = groups - groups.count_ones() as u64;
+        let content = if self.outboard { 0 } else { self.content_len() };
+        content + parents * PARENT_SIZE as u64
+    }
+
+    /// Serialize the checkpoint. The format is the 4 magic bytes `baoE` and a version byte,
+    /// currently 1, then a byte for the mode (0 for combined, 1 for outboard, and 2 for outboard
+    /// in post-order), a byte for the hash mode (0 for the regular hash, 1 for keyed_hash, and 2
+    /// for derive_key, followed by the 32-byte key or derived context key for the last two), a
+    /// `chunk_group_log` byte, the 8-byte little-endian length of the completed
+    /// chunks, and a count byte and that many 32-byte subtree hashes. Then comes the current
+    /// chunk, which is either a 0 byte and a 2-byte little-endian length followed by the bytes of
+    /// a partial chunk, or a 1 byte followed by the 32-byte CV of a full chunk.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = ENCODER_CHECKPOINT_MAGIC.to_vec();
+        bytes.push(ENCODER_CHECKPOINT_VERSION);
+        bytes.push(self.outboard as u8 + self.post_order as u8);
+        self.mode.serialize(&mut bytes);
+        bytes.push(self.tree_state.group_log);
+        bytes.extend_from_slice(&crate::encode_len(self.tree_state.count()));
+        bytes.push(self.tree_state.subtrees.len() as u8);
+        for subtree in &self.tree_state.subtrees {
+            bytes.extend_from_slice(subtree.as_bytes());
+        }
+        match &self.current_chunk {
+            CurrentChunk::Partial(chunk) => {
+                bytes.push(0);
+                bytes.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+                bytes.extend_from_slice(chunk);
+            }
+            CurrentChunk::Full(cv) => {
+                bytes.push(1);
+                bytes.extend_from_slice(cv.as_bytes());
+            }
+        }
+        bytes
+    }
+
+    /// Deserialize the output of [`serialize`](#method.serialize). This returns an error of kind
+    /// `InvalidData` if the bytes aren't a well-formed checkpoint, including one from a different
+    /// format version.
+    pub fn deserialize(bytes: &[u8]) -> io::Result<Self> {
+        let mut bytes = crate::ByteReader::new(bytes, "invalid encoder checkpoint");
+        if bytes.take(ENCODER_CHECKPOINT_MAGIC.len())? != ENCODER_CHECKPOINT_MAGIC
+            || bytes.byte()? != ENCODER_CHECKPOINT_VERSION
+        {
+            return Err(bytes.invalid());
+        }
+        let (outboard, post_order) = match bytes.byte()? {
+            0 => (false, false),
+            1 => (true, false),
+            2 => (true, true),
+            _ => return Err(bytes.invalid()),
+        };
+        let mode = bytes.mode()?;
+        let group_log = bytes.byte()?;
+        if group_log > MAX_CHUNK_GROUP_LOG {
+            return Err(bytes.invalid());
+        }
+        let total_len = crate::decode_len(array_ref!(bytes.take(HEADER_SIZE)?, 0, HEADER_SIZE));
+        let num_subtrees = bytes.byte()? as usize;
+        // The completed chunks are whole, and the subtrees are one per bit of their count, as in
+        // State::needs_merge.
+        let chunks = total_len / CHUNK_SIZE as u64;
+        if total_len % CHUNK_SIZE as u64 != 0 || num_subtrees != chunks.count_ones() as usize {
+            return Err(bytes.invalid());
+        }
+        let mut tree_state = State::with_mode(mode, group_log);
+        tree_state.total_len = total_len;
+        for bit in (0..64).rev().filter(|bit| chunks & (1 << bit) != 0) {
+            tree_state.subtrees.push(bytes.hash()?);
+            tree_state
+                .subtree_lens
+                .push((CHUNK_SIZE as u64) << bit as u64);
+        }
+        let current_chunk = match bytes.byte()? {
+            0 => {
+                let len = u16::from_le_bytes(*array_ref!(bytes.take(2)?, 0, 2)) as usize;
+                if len > CHUNK_SIZE {
+                    return Err(bytes.invalid());
+                }
+                CurrentChunk::Partial(bytes.take(len)?.to_vec())
+            }
+            // Only a chunk after the first gets checkpointed by its CV.
+            1 if chunks > 0 => CurrentChunk::Full(bytes.hash()?),
+            _ => return Err(bytes.invalid()),
+        };
+        bytes.finish()?;
+        Ok(Self {
+            outboard,
+            post_order,
+            mode,
+            tree_state,
+            current_chunk,
+        })
+    }
+}
+
+impl fmt::Debug for EncoderCheckpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing hashes or content, they might be secret.
+        write!(
+            f,
+            "EncoderCheckpoint {{ outboard: {}, content_len: {}, ... }}",
+            self.outboard,
+            self.content_len(),
+        )
+    }
+}
+
 impl<T: Read + Write + Seek> Write for Encoder<T> {
     fn write(&mut self, input: &[u8]) -> io::Result<usize> {
+        // Nothing below can happen until we know there's more input.
+        if input.is_empty() {
+            return Ok(0);
+        }
+
         // If the current chunk is full, we need to finalize it, add it to
         // the tree state, and write out any completed parent nodes.
-        if self.chunk_state.len() == CHUNK_SIZE {
-            let chunk_hash = self.chunk_state.finalize(false);
-            self.tree_state.push_subtree(&chunk_hash, CHUNK_SIZE);
-            let chunk_counter = self.tree_state.count() / CHUNK_SIZE as u64;
-            self.chunk_state = blake3::guts::ChunkState::new(chunk_counter);
-            while let Some(parent) = self.tree_state.merge_parent() {
-                self.inner.write_all(&parent)?;
-            }
+        if let Some(cv) = self.full_chunk_cv.take() {
+            self.push_full_chunk(&cv)?;
+        } else if self.chunk_state.len() == CHUNK_SIZE {
+            let chunk_cv = self.chunk_state.finalize(false);
+            self.push_full_chunk(&chunk_cv)?;
         }
 
         // Add as many bytes as possible to the current chunk.
@@ -556,7 +1122,12 @@ impl<T: Read + Write + Seek> Write for Encoder<T> {
         if !self.outboard {
             self.inner.write_all(&input[..take])?;
         }
+        let len = self.chunk_state.len();
         self.chunk_state.update(&input[..take]);
+        // Keep a copy only if a checkpoint could need it, as described at chunk_buf.
+        if self.chunk_state.len() < CHUNK_SIZE || self.tree_state.count() == 0 {
+            self.chunk_buf[len..][..take].copy_from_slice(&input[..take]);
+        }
         Ok(take)
     }
 
@@ -581,10 +1152,18 @@ pub(crate) struct ParseState {
     // requirement" in the spec. This parser doesn't actually check hashes, but
     // it drives callers that do check.
     final_chunk_validated: bool,
+    // Chunk indices and sizes here are in units of chunk groups, which are
+    // single chunks by default.
+    group_log: u8,
 }
 
 impl ParseState {
     pub fn new() -> Self {
+        Self::new_grouped(0)
+    }
+
+    pub fn new_grouped(group_log: u8) -> Self {
+        debug_assert!(group_log <= MAX_CHUNK_GROUP_LOG);
         Self {
             content_len: None,
             content_position: 0,
@@ -592,15 +1171,28 @@ impl ParseState {
             stack_depth: 1,
             upcoming_parents: 0, // set later in feed_header
             final_chunk_validated: false,
+            group_log,
         }
     }
 
+    pub fn group_log(&self) -> u8 {
+        self.group_log
+    }
+
+    fn group_size(&self) -> u64 {
+        group_size(self.group_log)
+    }
+
     pub fn content_position(&self) -> u64 {
         self.content_position
     }
 
+    pub fn stack_depth(&self) -> usize {
+        self.stack_depth as usize
+    }
+
     fn at_root(&self) -> bool {
-        self.content_position < CHUNK_SIZE as u64 && self.stack_depth == 1
+        self.content_position < self.group_size() && self.stack_depth == 1
     }
 
     fn at_eof(&self) -> bool {
@@ -625,14 +1217,126 @@ impl ParseState {
         false
     }
 
+    // The offset in the combined encoding where the next read starts. Nothing
+    // is read at EOF, and this reports the end of the encoding.
+    pub fn underlying_position(&self) -> u128 {
+        match self.content_len {
+            Some(content_len) if self.at_eof() => encoded_size_grouped(content_len, self.group_log),
+            _ => self.encoding_position,
+        }
+    }
+
+    // A variant on the above for callers who keep the encoded tree separate
+    // from the content. This returns the content and outboard offsets.
+    pub fn underlying_position_outboard(&self) -> (u64, u128) {
+        match self.content_len {
+            None => (0, 0),
+            Some(content_len) if self.at_eof() => (
+                content_len,
+                outboard_size_grouped(content_len, self.group_log),
+            ),
+            Some(_) => {
+                let content = self.next_chunk_start();
+                (content, self.encoding_position - content as u128)
+            }
+        }
+    }
+
+    // The parser state is part of a decoder checkpoint. The format is a flag
+    // byte for whether the header has been parsed, then the content length,
+    // content position, and encoding position in little-endian, then the stack
+    // depth, the upcoming parents, the final chunk flag, and the chunk group
+    // log.
+    pub const SERIALIZED_SIZE: usize = 1 + 8 + 8 + 16 + 4;
+
+    pub fn serialize(&self) -> [u8; Self::SERIALIZED_SIZE] {
+        let mut bytes = [0; Self::SERIALIZED_SIZE];
+        bytes[0] = self.content_len.is_some() as u8;
+        *array_mut_ref!(bytes, 1, 8) = self.content_len.unwrap_or(0).to_le_bytes();
+        *array_mut_ref!(bytes, 9, 8) = self.content_position.to_le_bytes();
+        *array_mut_ref!(bytes, 17, 16) = self.encoding_position.to_le_bytes();
+        bytes[33] = self.stack_depth;
+        bytes[34] = self.upcoming_parents;
+        bytes[35] = self.final_chunk_validated as u8;
+        bytes[36] = self.group_log;
+        bytes
+    }
+
+    // This returns None for states the parser can't get into, at least as far
+    // as the other methods here would misbehave. It doesn't check that the
+    // encoding position matches the content position, because the caller is
+    // trusting the rest of the checkpoint anyway.
+    pub fn deserialize(bytes: &[u8; Self::SERIALIZED_SIZE]) -> Option<Self> {
+        let content_len = match bytes[0] {
+            0 => None,
+            1 => Some(u64::from_le_bytes(*array_ref!(bytes, 1, 8))),
+            _ => return None,
+        };
+        let final_chunk_validated = match bytes[35] {
+            0 => false,
+            1 => true,
+            _ => return None,
+        };
+        if bytes[36] > MAX_CHUNK_GROUP_LOG {
+            return None;
+        }
+        let state = Self {
+            content_len,
+            content_position: u64::from_le_bytes(*array_ref!(bytes, 9, 8)),
+            encoding_position: u128::from_le_bytes(*array_ref!(bytes, 17, 16)),
+            stack_depth: bytes[33],
+            upcoming_parents: bytes[34],
+            final_chunk_validated,
+            group_log: bytes[36],
+        };
+        let content_len = match state.content_len {
+            Some(content_len) => content_len,
+            // Before the header, the state is always the initial one.
+            None if bytes[1..] == Self::new_grouped(state.group_log).serialize()[1..] => {
+                return Some(state)
+            }
+            None => return None,
+        };
+        if state.stack_depth as usize > MAX_DEPTH || state.upcoming_parents as usize > MAX_DEPTH {
+            return None;
+        }
+        // Getting to the end of the content requires validating the final
+        // chunk, see at_eof.
+        if content_len > 0 && state.content_position >= content_len && !state.final_chunk_validated
+        {
+            return None;
+        }
+        if !state.at_eof()
+            && (state.stack_depth == 0
+                || state.encoding_position < HEADER_SIZE as u128 + state.next_chunk_start() as u128)
+        {
+            return None;
+        }
+        Some(state)
+    }
+
     fn next_chunk_start(&self) -> u64 {
         debug_assert!(!self.at_eof(), "not valid at EOF");
-        self.content_position - (self.content_position % CHUNK_SIZE as u64)
+        self.content_position - (self.content_position % self.group_size())
     }
 
     fn next_chunk_index(&self) -> u64 {
         debug_assert!(!self.at_eof(), "not valid at EOF");
-        self.content_position / CHUNK_SIZE as u64
+        self.content_position / self.group_size()
+    }
+
+    // When the next read is a parent node, this returns the tree positions of
+    // its left and right children. A tree position is the index of the first
+    // chunk in a subtree, together with the height of the subtree. Height
+    // zero is a single chunk.
+    pub fn next_parent_children(&self) -> ((u64, u8), (u64, u8)) {
+        debug_assert!(self.upcoming_parents > 0, "next read isn't a parent");
+        let content_len = self.content_len.expect("no parents before header");
+        let left_start = self.next_chunk_index();
+        let left_height = self.upcoming_parents - 1;
+        let right_start = left_start + (1 << left_height);
+        let right_height = pre_order_parent_nodes_grouped(right_start, content_len, self.group_log);
+        ((left_start, left_height), (right_start, right_height))
     }
 
     pub fn finalization(&self) -> Finalization {
@@ -648,7 +1352,7 @@ impl ParseState {
         self.content_position = 0;
         self.encoding_position = HEADER_SIZE as u128;
         self.stack_depth = 1;
-        self.upcoming_parents = pre_order_parent_nodes(0, content_len);
+        self.upcoming_parents = pre_order_parent_nodes_grouped(0, content_len, self.group_log);
         // The final_chunk_validated flag is left alone. If the caller has
         // already validated the final chunk, then they can do EOF-relative
         // seeks or read the length without paying that cost again.
@@ -676,10 +1380,10 @@ impl ParseState {
             NextRead::Parent
         } else {
             NextRead::Chunk {
-                size: chunk_size(self.next_chunk_index(), content_len),
+                size: group_len(self.next_chunk_index(), content_len, self.group_log),
                 finalization: self.finalization(),
-                skip: (self.content_position % CHUNK_SIZE as u64) as usize,
-                index: self.content_position / CHUNK_SIZE as u64,
+                skip: (self.content_position % self.group_size()) as usize,
+                index: self.content_position / self.group_size(),
             }
         }
     }
@@ -711,6 +1415,7 @@ impl ParseState {
             old_state: self.clone(),
             new_state,
             next_read,
+            seek_to,
         }
     }
 
@@ -767,14 +1472,14 @@ impl ParseState {
             // repointed EOF seek, where we instruct the caller to read the
             // final chunk and call seek_next again.
             let distance = seek_to - self.next_chunk_start();
-            if distance < CHUNK_SIZE as u64 {
+            if distance < self.group_size() {
                 if verifying_final_chunk {
                     let size = (content_len - self.next_chunk_start()) as usize;
                     return NextRead::Chunk {
                         size,
                         finalization: self.finalization(),
                         skip: size, // Skip the whole thing.
-                        index: self.content_position / CHUNK_SIZE as u64,
+                        index: self.content_position / self.group_size(),
                     };
                 } else {
                     self.content_position = seek_to;
@@ -788,7 +1493,7 @@ impl ParseState {
             let downshifted_distance = distance
                 .checked_shr(self.upcoming_parents as u32)
                 .unwrap_or(0);
-            if downshifted_distance < CHUNK_SIZE as u64 {
+            if downshifted_distance < self.group_size() {
                 debug_assert!(self.upcoming_parents > 0);
                 return NextRead::Parent;
             }
@@ -797,12 +1502,16 @@ impl ParseState {
             // we know the subtree size is maximal, and computing it won't
             // overflow. The caller will have to execute an underlying seek in
             // this case.
-            let subtree_size = (CHUNK_SIZE as u64) << self.upcoming_parents;
+            let subtree_size = self.group_size() << self.upcoming_parents;
             self.content_position = self.next_chunk_start() + subtree_size;
-            self.encoding_position += encoded_subtree_size(subtree_size);
+            self.encoding_position += encoded_subtree_size_grouped(subtree_size, self.group_log);
             self.stack_depth -= 1;
             // This depends on the update to content_position immediately above.
-            self.upcoming_parents = pre_order_parent_nodes(self.next_chunk_index(), content_len);
+            self.upcoming_parents = pre_order_parent_nodes_grouped(
+                self.next_chunk_index(),
+                content_len,
+                self.group_log,
+            );
         }
     }
 
@@ -854,8 +1563,8 @@ impl ParseState {
             "advance_chunk with non-zero upcoming parents"
         );
         let content_len = self.content_len.expect("advance_chunk before header");
-        let size = chunk_size(self.next_chunk_index(), content_len);
-        let skip = self.content_position % CHUNK_SIZE as u64;
+        let size = group_len(self.next_chunk_index(), content_len, self.group_log);
+        let skip = self.content_position % self.group_size();
         self.content_position += size as u64 - skip;
         self.encoding_position += size as u128;
         self.stack_depth -= 1;
@@ -867,7 +1576,11 @@ impl ParseState {
             self.final_chunk_validated = true;
         } else {
             // upcoming_parents is only meaningful if we're before EOF.
-            self.upcoming_parents = pre_order_parent_nodes(self.next_chunk_index(), content_len);
+            self.upcoming_parents = pre_order_parent_nodes_grouped(
+                self.next_chunk_index(),
+                content_len,
+                self.group_log,
+            );
         }
     }
 }
@@ -914,14 +1627,45 @@ pub(crate) enum NextRead {
 // After handling all of the above, the caller passes the SeekBookkeeping back
 // to seek_done(), which might returns a NextRead for the caller to carry
 // out, or None to indicate
+//
+// A caller that already knows the subtree hashes below the next parent node
+// (as a VerifyState with a CV cache might) can skip reading that parent by
+// calling advance_parent(). That continues the seek past the parent, and the
+// other instructions above then reflect the combined result.
 #[derive(Debug)]
 pub(crate) struct SeekBookkeeping {
     old_state: ParseState,
     new_state: ParseState,
     next_read: NextRead,
+    seek_to: u64,
 }
 
 impl SeekBookkeeping {
+    // Returns true if the next read is a parent node, either as part of the
+    // seek, or right after the seek is done. Seeking doesn't descend into the
+    // subtree that starts with the target chunk, and leaves those parents for
+    // the following read.
+    pub fn parent_next(&self) -> bool {
+        match self.next_read {
+            NextRead::Parent => true,
+            NextRead::Done => matches!(self.new_state.read_next(), NextRead::Parent),
+            _ => false,
+        }
+    }
+
+    // When parent_next() is true, the tree positions of the parent's children.
+    // See ParseState::next_parent_children.
+    pub fn next_parent_children(&self) -> ((u64, u8), (u64, u8)) {
+        self.new_state.next_parent_children()
+    }
+
+    // Skip over the next parent node, when parent_next() is true.
+    pub fn advance_parent(&mut self) {
+        debug_assert!(self.parent_next());
+        self.new_state.advance_parent();
+        self.next_read = self.new_state.new_state_seek_next(self.seek_to);
+    }
+
     pub fn reset_to_root(&self) -> bool {
         self.new_state.at_root() && !self.old_state.at_root()
     }
@@ -1001,7 +1745,8 @@ pub struct SliceExtractor<T: Read + Seek, O: Read + Seek> {
     slice_len: u64,
     slice_bytes_read: u64,
     parser: ParseState,
-    buf: [u8; CHUNK_SIZE],
+    // Big enough for a chunk group, which is a single chunk by default.
+    buf: Vec<u8>,
     buf_start: usize,
     buf_end: usize,
     seek_done: bool,
@@ -1012,7 +1757,19 @@ impl<T: Read + Seek> SliceExtractor<T, T> {
     /// `slice_len` are with respect to the *content* of the encoding, that is, the *original*
     /// input bytes. This corresponds to `bao slice slice_start slice_len`.
     pub fn new(input: T, slice_start: u64, slice_len: u64) -> Self {
-        Self::new_inner(input, None, slice_start, slice_len)
+        Self::new_inner(input, None, slice_start, slice_len, 0)
+    }
+
+    /// Create a new `SliceExtractor` to read from a combined encoding made with
+    /// [`Encoder::new_grouped`](struct.Encoder.html#method.new_grouped) and the same
+    /// `chunk_group_log`. The slice includes every chunk group that overlaps the requested range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_group_log` is greater than
+    /// [`MAX_CHUNK_GROUP_LOG`](constant.MAX_CHUNK_GROUP_LOG.html).
+    pub fn new_grouped(input: T, slice_start: u64, slice_len: u64, chunk_group_log: u8) -> Self {
+        Self::new_inner(input, None, slice_start, slice_len, chunk_group_log)
     }
 }
 
@@ -1023,10 +1780,44 @@ impl<T: Read + Seek, O: Read + Seek> SliceExtractor<T, O> {
     /// the *original* input bytes. This corresponds to `bao slice slice_start slice_len
     /// --outboard`.
     pub fn new_outboard(input: T, outboard: O, slice_start: u64, slice_len: u64) -> Self {
-        Self::new_inner(input, Some(outboard), slice_start, slice_len)
+        Self::new_inner(input, Some(outboard), slice_start, slice_len, 0)
     }
 
-    fn new_inner(input: T, outboard: Option<O>, slice_start: u64, slice_len: u64) -> Self {
+    /// Create a new `SliceExtractor` to read from an unmodified input file and an outboard
+    /// encoding made with [`Encoder::new_outboard_grouped`](struct.Encoder.html#method.new_outboard_grouped),
+    /// like [`new_grouped`](#method.new_grouped).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_group_log` is greater than
+    /// [`MAX_CHUNK_GROUP_LOG`](constant.MAX_CHUNK_GROUP_LOG.html).
+    pub fn new_outboard_grouped(
+        input: T,
+        outboard: O,
+        slice_start: u64,
+        slice_len: u64,
+        chunk_group_log: u8,
+    ) -> Self {
+        Self::new_inner(
+            input,
+            Some(outboard),
+            slice_start,
+            slice_len,
+            chunk_group_log,
+        )
+    }
+
+    fn new_inner(
+        input: T,
+        outboard: Option<O>,
+        slice_start: u64,
+        slice_len: u64,
+        group_log: u8,
+    ) -> Self {
+        assert!(
+            group_log <= MAX_CHUNK_GROUP_LOG,
+            "chunk_group_log is too large",
+        );
         Self {
             input,
             outboard,
@@ -1034,8 +1825,8 @@ impl<T: Read + Seek, O: Read + Seek> SliceExtractor<T, O> {
             // Always try to include at least one byte.
             slice_len: cmp::max(slice_len, 1),
             slice_bytes_read: 0,
-            parser: ParseState::new(),
-            buf: [0; CHUNK_SIZE],
+            parser: ParseState::new_grouped(group_log),
+            buf: vec![0; group_size(group_log) as usize],
             buf_start: 0,
             buf_end: 0,
             seek_done: false,
@@ -1145,6 +1936,14 @@ impl<T: Read + Seek, O: Read + Seek> SliceExtractor<T, O> {
     }
 }
 
+impl<T: Read + Seek, S: OutboardStore> SliceExtractor<T, OutboardReader<S>> {
+    /// Create a new `SliceExtractor` to read from an unmodified input file and an
+    /// [`OutboardStore`](../outboard/trait.OutboardStore.html), like `SliceExtractor::new_outboard`.
+    pub fn new_outboard_store(input: T, store: S, slice_start: u64, slice_len: u64) -> Self {
+        Self::new_outboard(input, OutboardReader::new(store), slice_start, slice_len)
+    }
+}
+
 impl<T: Read + Seek, O: Read + Seek> Read for SliceExtractor<T, O> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         // If we don't have any output ready to go, try to read more.
@@ -1162,11 +1961,8 @@ impl<T: Read + Seek, O: Read + Seek> Read for SliceExtractor<T, O> {
 }
 
 pub(crate) fn cast_offset(offset: u128) -> io::Result<u64> {
-    if offset > u64::max_value() as u128 {
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            "seek offset overflowed u64",
-        ))
+    if offset > u64::MAX as u128 {
+        Err(io::Error::other("seek offset overflowed u64"))
     } else {
         Ok(offset as u64)
     }
@@ -1176,6 +1972,7 @@ pub(crate) fn cast_offset(offset: u128) -> io::Result<u64> {
 mod test {
     use super::*;
     use crate::decode::make_test_input;
+    use std::io::Cursor;
 
     #[test]
     fn test_encode() {
@@ -1194,6 +1991,253 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_encoder_checkpoint() {
+        for &case in crate::test::TEST_CASES {
+            let input = make_test_input(case);
+            let expected = encode(&input);
+            let expected_outboard = outboard(&input);
+            let mut split_points = vec![0, case / 2, case];
+            split_points.extend(case.checked_sub(1));
+            for &split in &split_points {
+                // Writes of whole chunks, and of pieces that fill chunks across calls.
+                for &piece in &[CHUNK_SIZE, 700] {
+                    for &is_outboard in &[false, true] {
+                        println!(
+                            "case {} split {} piece {} outboard {}",
+                            case, split, piece, is_outboard
+                        );
+                        let mut output = Cursor::new(Vec::new());
+                        let mut encoder = if is_outboard {
+                            Encoder::new_outboard(&mut output)
+                        } else {
+                            Encoder::new(&mut output)
+                        };
+                        for piece in input[..split].chunks(piece) {
+                            encoder.write_all(piece).unwrap();
+                        }
+                        let checkpoint = encoder.checkpoint().unwrap();
+                        assert_eq!(split as u64, checkpoint.content_len());
+                        // Simulate writing more before a crash, which gets truncated away.
+                        encoder.write_all(&input[split..]).unwrap();
+                        drop(encoder);
+                        let mut output_bytes = output.into_inner();
+                        output_bytes.truncate(checkpoint.position() as usize);
+
+                        let checkpoint =
+                            EncoderCheckpoint::deserialize(&checkpoint.serialize()).unwrap();
+                        let mut output = Cursor::new(output_bytes);
+                        let mut encoder = Encoder::resume(&mut output, &checkpoint).unwrap();
+                        // A resumed encoder checkpoints the same way.
+                        assert_eq!(
+                            checkpoint.serialize(),
+                            encoder.checkpoint().unwrap().serialize()
+                        );
+                        encoder.write_all(&input[split..]).unwrap();
+                        let hash = encoder.finalize().unwrap();
+                        let result = (output.into_inner(), hash);
+                        if is_outboard {
+                            assert_eq!(expected_outboard, result);
+                        } else {
+                            assert_eq!(expected, result);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_grouped_encoders() {
+        for &case in crate::test::TEST_CASES {
+            let input = make_test_input(case);
+            let expected_hash = blake3::hash(&input);
+            for &group_log in &[0, 1, 2, 4] {
+                println!("case {} group_log {}", case, group_log);
+                let mut encoder = Encoder::new_grouped(Cursor::new(Vec::new()), group_log);
+                encoder.write_all(&input).unwrap();
+                assert_eq!(expected_hash, encoder.finalize().unwrap());
+                let encoded = encoder.inner.into_inner();
+                assert_eq!(
+                    encoded_size_grouped(case as u64, group_log),
+                    encoded.len() as u128
+                );
+                let mut encoder = Encoder::new_outboard_grouped(Cursor::new(Vec::new()), group_log);
+                encoder.write_all(&input).unwrap();
+                assert_eq!(expected_hash, encoder.finalize().unwrap());
+                let outboard = encoder.inner.into_inner();
+                assert_eq!(
+                    outboard_size_grouped(case as u64, group_log),
+                    outboard.len() as u128
+                );
+                // The combined encoding has the same parents as the outboard one, in the same
+                // places relative to the content.
+                let mut parser = ParseState::new_grouped(group_log);
+                let (mut encoded_pos, mut outboard_pos) = (0, 0);
+                loop {
+                    match parser.read_next() {
+                        NextRead::Header => {
+                            assert_eq!(encoded[..HEADER_SIZE], outboard[..HEADER_SIZE]);
+                            parser.feed_header(array_ref!(encoded, 0, HEADER_SIZE));
+                            encoded_pos += HEADER_SIZE;
+                            outboard_pos += HEADER_SIZE;
+                        }
+                        NextRead::Parent => {
+                            assert_eq!(
+                                encoded[encoded_pos..][..PARENT_SIZE],
+                                outboard[outboard_pos..][..PARENT_SIZE]
+                            );
+                            parser.advance_parent();
+                            encoded_pos += PARENT_SIZE;
+                            outboard_pos += PARENT_SIZE;
+                        }
+                        NextRead::Chunk { size, index, .. } => {
+                            let start = (index * group_size(group_log)) as usize;
+                            assert_eq!(input[start..][..size], encoded[encoded_pos..][..size]);
+                            parser.advance_chunk();
+                            encoded_pos += size;
+                        }
+                        NextRead::Done => break,
+                    }
+                }
+                assert_eq!(encoded.len(), encoded_pos);
+                assert_eq!(outboard.len(), outboard_pos);
+                if group_log == 0 {
+                    assert_eq!((encoded, expected_hash), encode(&input));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_grouped_encoder_checkpoint() {
+        let group_log = 2;
+        let input = make_test_input(11 * CHUNK_SIZE + 7);
+        let mut expected = Encoder::new_grouped(Cursor::new(Vec::new()), group_log);
+        expected.write_all(&input).unwrap();
+        let expected_hash = expected.finalize().unwrap();
+        let expected = expected.inner.into_inner();
+        for &split in &[
+            0,
+            3 * CHUNK_SIZE,
+            4 * CHUNK_SIZE + 1,
+            9 * CHUNK_SIZE,
+            input.len(),
+        ] {
+            println!("split {}", split);
+            let mut output = Cursor::new(Vec::new());
+            let mut encoder = Encoder::new_grouped(&mut output, group_log);
+            encoder.write_all(&input[..split]).unwrap();
+            let checkpoint = encoder.checkpoint().unwrap();
+            drop(encoder);
+            let mut output_bytes = output.into_inner();
+            assert_eq!(checkpoint.position(), output_bytes.len() as u64);
+            output_bytes.truncate(checkpoint.position() as usize);
+            let checkpoint = EncoderCheckpoint::deserialize(&checkpoint.serialize()).unwrap();
+            let mut output = Cursor::new(output_bytes);
+            let mut encoder = Encoder::resume(&mut output, &checkpoint).unwrap();
+            encoder.write_all(&input[split..]).unwrap();
+            assert_eq!(expected_hash, encoder.finalize().unwrap());
+            assert_eq!(expected, output.into_inner());
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_group_log_too_large() {
+        Encoder::new_grouped(Cursor::new(Vec::new()), MAX_CHUNK_GROUP_LOG + 1);
+    }
+
+    #[test]
+    fn test_keyed_encoders() {
+        let key = [42; blake3::KEY_LEN];
+        let context = "bao 2020-01-01 test context";
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = crate::decode::make_test_input(case);
+            let expected_keyed = blake3::keyed_hash(&key, &input);
+            let expected_derived = blake3::Hasher::new_derive_key(context)
+                .update(&input)
+                .finalize();
+            let mut encoders = vec![
+                (
+                    Encoder::new_keyed(Cursor::new(Vec::new()), &key),
+                    expected_keyed,
+                ),
+                (
+                    Encoder::new_outboard_keyed(Cursor::new(Vec::new()), &key),
+                    expected_keyed,
+                ),
+                (
+                    Encoder::new_derive_key(Cursor::new(Vec::new()), context),
+                    expected_derived,
+                ),
+                (
+                    Encoder::new_outboard_derive_key(Cursor::new(Vec::new()), context),
+                    expected_derived,
+                ),
+            ];
+            for (encoder, expected) in &mut encoders {
+                encoder.write_all(&input[..case / 2]).unwrap();
+                // The checkpoint holds the key, so a resumed encoder produces the same encoding.
+                let checkpoint = encoder.checkpoint().unwrap().serialize();
+                let checkpoint = EncoderCheckpoint::deserialize(&checkpoint).unwrap();
+                let mut resumed = Encoder::resume(encoder.inner.clone(), &checkpoint).unwrap();
+                resumed.write_all(&input[case / 2..]).unwrap();
+                assert_eq!(*expected, resumed.finalize().unwrap());
+                encoder.write_all(&input[case / 2..]).unwrap();
+                assert_eq!(*expected, encoder.finalize().unwrap());
+                assert_eq!(encoder.inner.get_ref(), resumed.inner.get_ref());
+                // The layout is the same as in the regular hash mode.
+                let expected_size = if encoder.outboard {
+                    outboard_size(case as u64)
+                } else {
+                    encoded_size(case as u64)
+                };
+                assert_eq!(expected_size, encoder.inner.get_ref().len() as u128);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encoder_checkpoint_errors() {
+        let input = make_test_input(5 * CHUNK_SIZE + 1);
+        let mut output = Cursor::new(Vec::new());
+        let mut encoder = Encoder::new(&mut output);
+        encoder.write_all(&input).unwrap();
+        let checkpoint = encoder.checkpoint().unwrap();
+        let serialized = checkpoint.serialize();
+
+        // Every truncation or extension of the serialized bytes is rejected.
+        for len in 0..serialized.len() {
+            let err = EncoderCheckpoint::deserialize(&serialized[..len]).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+        let mut long = serialized.clone();
+        long.push(0);
+        let err = EncoderCheckpoint::deserialize(&long).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // So is a length that doesn't match the number of subtrees.
+        let mut bad = serialized.clone();
+        bad[8..16].copy_from_slice(&crate::encode_len(4 * CHUNK_SIZE as u64));
+        let err = EncoderCheckpoint::deserialize(&bad).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // And bad magic bytes, an unknown version, or an unknown hash mode.
+        for index in [0, 1, 2, 3, 4, 6] {
+            let mut bad = serialized.clone();
+            bad[index] ^= 1;
+            let err = EncoderCheckpoint::deserialize(&bad).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+
+        // Resuming on an output that's too short fails.
+        let mut short = Cursor::new(vec![0; checkpoint.position() as usize - 1]);
+        let err = Encoder::resume(&mut short, &checkpoint).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
     #[test]
     fn test_outboard_encode() {
         for &case in crate::test::TEST_CASES {
@@ -1275,9 +2319,7 @@ mod test {
         let mut state = State::new();
         let mut chunk_index = 0;
         while input.len() > CHUNK_SIZE {
-            let hash = blake3::guts::ChunkState::new(chunk_index)
-                .update(&input[..CHUNK_SIZE])
-                .finalize(false);
+            let hash = Mode::Hash.chunk_cv(chunk_index, &input[..CHUNK_SIZE], false);
             chunk_index += 1;
             state.push_subtree(&hash, CHUNK_SIZE);
             input = &input[CHUNK_SIZE..];
@@ -1285,9 +2327,7 @@ mod test {
             // them, but we need to avoid tripping an assert.
             while state.merge_parent().is_some() {}
         }
-        let hash = blake3::guts::ChunkState::new(chunk_index)
-            .update(input)
-            .finalize(last_chunk_is_root);
+        let hash = Mode::Hash.chunk_cv(chunk_index, input, last_chunk_is_root);
         state.push_subtree(&hash, input.len());
         loop {
             match state.merge_finalize() {
@@ -1307,8 +2347,8 @@ mod test {
         for &case in crate::test::TEST_CASES {
             dbg!(case);
             let input = &buf[..case];
-            let expected = blake3::hash(&input);
-            let found = drive_state(&input);
+            let expected = blake3::hash(input);
+            let found = drive_state(input);
             assert_eq!(expected, found, "hashes don't match");
         }
     }
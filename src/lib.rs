@@ -41,10 +41,19 @@
 #![forbid(unsafe_code)]
 
 pub mod decode;
+pub mod download;
 pub mod encode;
+pub mod outboard;
+pub mod partial;
+pub mod proof;
+pub mod protocol;
+pub mod subtree;
+pub mod sync;
 
 pub use blake3::Hash;
 
+use blake3::hazmat::HasherExt;
+use std::fmt;
 use std::mem;
 
 /// The size of a `Hash`, 32 bytes.
@@ -52,7 +61,8 @@ pub const HASH_SIZE: usize = 32;
 pub(crate) const PARENT_SIZE: usize = 2 * HASH_SIZE;
 pub(crate) const HEADER_SIZE: usize = 8;
 pub(crate) const CHUNK_SIZE: usize = blake3::CHUNK_LEN;
-pub(crate) const MAX_DEPTH: usize = blake3::MAX_DEPTH;
+// 2^54 * CHUNK_SIZE = 2^64
+pub(crate) const MAX_DEPTH: usize = 54;
 
 /// An array of `HASH_SIZE` bytes. This will be a wrapper type in a future version.
 pub(crate) type ParentNode = [u8; 2 * HASH_SIZE];
@@ -66,6 +76,65 @@ pub(crate) fn decode_len(bytes: &[u8; HEADER_SIZE]) -> u64 {
     u64::from_le_bytes(*bytes)
 }
 
+// Split a parent node into the CVs of its left and right children.
+pub(crate) fn split_parent(parent: &ParentNode) -> (Hash, Hash) {
+    let left_cv = (*arrayref::array_ref!(parent, 0, HASH_SIZE)).into();
+    let right_cv = (*arrayref::array_ref!(parent, HASH_SIZE, HASH_SIZE)).into();
+    (left_cv, right_cv)
+}
+
+// A cursor over serialized checkpoints and proofs, for their deserialize methods. Running out of
+// bytes, or having some left over at the end, is an error of kind InvalidData with the given
+// message, and so is anything the caller finds wrong with the bytes it took.
+pub(crate) struct ByteReader<'a> {
+    bytes: &'a [u8],
+    message: &'static str,
+}
+
+impl<'a> ByteReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8], message: &'static str) -> Self {
+        Self { bytes, message }
+    }
+
+    pub(crate) fn invalid(&self) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, self.message)
+    }
+
+    pub(crate) fn take(&mut self, n: usize) -> std::io::Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            return Err(self.invalid());
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    pub(crate) fn byte(&mut self) -> std::io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub(crate) fn hash(&mut self) -> std::io::Result<Hash> {
+        Ok((*arrayref::array_ref!(self.take(HASH_SIZE)?, 0, HASH_SIZE)).into())
+    }
+
+    // The inverse of Mode::serialize.
+    pub(crate) fn mode(&mut self) -> std::io::Result<Mode> {
+        Ok(match self.byte()? {
+            0 => Mode::Hash,
+            1 => Mode::KeyedHash(*self.hash()?.as_bytes()),
+            2 => Mode::DeriveKey(*self.hash()?.as_bytes()),
+            _ => return Err(self.invalid()),
+        })
+    }
+
+    pub(crate) fn finish(self) -> std::io::Result<()> {
+        if !self.bytes.is_empty() {
+            return Err(self.invalid());
+        }
+        Ok(())
+    }
+}
+
 // The root node is hashed differently from interior nodes. It gets suffixed
 // with the length of the entire input, and we set the Blake2 final node flag.
 // That means that no root hash can ever collide with an interior hash, or with
@@ -85,6 +154,121 @@ impl Finalization {
     }
 }
 
+// The BLAKE3 mode an encoding is hashed in. All of the chunk and parent
+// hashing in this crate goes through here, so that the keyed_hash and
+// derive_key modes work everywhere the regular hash mode does.
+#[derive(Clone, Copy, Default)]
+pub(crate) enum Mode {
+    #[default]
+    Hash,
+    KeyedHash([u8; blake3::KEY_LEN]),
+    // The key here is the context key, not the context string.
+    DeriveKey(blake3::hazmat::ContextKey),
+}
+
+impl Mode {
+    pub(crate) fn derive_key(context: &str) -> Self {
+        Mode::DeriveKey(blake3::hazmat::hash_derive_key_context(context))
+    }
+
+    // Checkpoints store the mode as a byte, 0 for the regular hash mode, 1 for keyed_hash, and 2
+    // for derive_key, followed by the 32-byte key or context key for the last two.
+    pub(crate) fn serialize(&self, bytes: &mut Vec<u8>) {
+        match self {
+            Mode::Hash => bytes.push(0),
+            Mode::KeyedHash(key) => {
+                bytes.push(1);
+                bytes.extend_from_slice(key);
+            }
+            Mode::DeriveKey(context_key) => {
+                bytes.push(2);
+                bytes.extend_from_slice(context_key);
+            }
+        }
+    }
+
+    fn hazmat(&self) -> blake3::hazmat::Mode<'_> {
+        match self {
+            Mode::Hash => blake3::hazmat::Mode::Hash,
+            Mode::KeyedHash(key) => blake3::hazmat::Mode::KeyedHash(key),
+            Mode::DeriveKey(context_key) => blake3::hazmat::Mode::DeriveKeyMaterial(context_key),
+        }
+    }
+
+    pub(crate) fn chunk_state(&self, chunk_index: u64) -> ChunkState {
+        let mut hasher = match self {
+            Mode::Hash => blake3::Hasher::new(),
+            Mode::KeyedHash(key) => blake3::Hasher::new_keyed(key),
+            Mode::DeriveKey(context_key) => blake3::Hasher::new_from_context_key(context_key),
+        };
+        hasher.set_input_offset(chunk_index * CHUNK_SIZE as u64);
+        ChunkState { hasher }
+    }
+
+    // Hash an entire chunk at once.
+    pub(crate) fn chunk_cv(&self, chunk_index: u64, chunk: &[u8], is_root: bool) -> Hash {
+        let mut chunk_state = self.chunk_state(chunk_index);
+        chunk_state.update(chunk);
+        chunk_state.finalize(is_root)
+    }
+
+    // Hash a whole subtree at once, such as a chunk group, which can be more than one chunk. The
+    // input offset has to be a multiple of the subtree size, which is a power of two number of
+    // chunks unless the subtree is at the end.
+    pub(crate) fn subtree_cv(&self, chunk_index: u64, input: &[u8], is_root: bool) -> Hash {
+        let mut chunk_state = self.chunk_state(chunk_index);
+        chunk_state.hasher.update(input);
+        chunk_state.finalize(is_root)
+    }
+
+    pub(crate) fn parent_cv(&self, left_child: &Hash, right_child: &Hash, is_root: bool) -> Hash {
+        let (left_child, right_child) = (left_child.as_bytes(), right_child.as_bytes());
+        if is_root {
+            blake3::hazmat::merge_subtrees_root(left_child, right_child, self.hazmat())
+        } else {
+            blake3::hazmat::merge_subtrees_non_root(left_child, right_child, self.hazmat()).into()
+        }
+    }
+}
+
+impl fmt::Debug for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing keys, they're secret.
+        match self {
+            Mode::Hash => write!(f, "Hash"),
+            Mode::KeyedHash(_) => write!(f, "KeyedHash"),
+            Mode::DeriveKey(_) => write!(f, "DeriveKey"),
+        }
+    }
+}
+
+// The state of a single chunk being hashed in some Mode.
+#[derive(Clone, Debug)]
+pub(crate) struct ChunkState {
+    hasher: blake3::Hasher,
+}
+
+impl ChunkState {
+    pub(crate) fn len(&self) -> usize {
+        self.hasher.count() as usize
+    }
+
+    pub(crate) fn update(&mut self, input: &[u8]) -> &mut Self {
+        debug_assert!(self.len() + input.len() <= CHUNK_SIZE);
+        self.hasher.update(input);
+        self
+    }
+
+    // Only the first chunk can be the root, when it's the only chunk.
+    pub(crate) fn finalize(&self, is_root: bool) -> Hash {
+        if is_root {
+            self.hasher.finalize()
+        } else {
+            self.hasher.finalize_non_root().into()
+        }
+    }
+}
+
 #[doc(hidden)]
 pub mod benchmarks {
     pub const CHUNK_SIZE: usize = super::CHUNK_SIZE;
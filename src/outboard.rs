@@ -0,0 +1,2161 @@
+//! Pluggable storage for outboard tree nodes.
+//!
+//! An outboard encoding is usually a single file, with the length header followed by the parent
+//! nodes of the tree in pre-order. The [`OutboardStore`](trait.OutboardStore.html) trait lets
+//! parent nodes come from somewhere else instead, like a key-value store or a database table,
+//! looked up by their [`TreePosition`](struct.TreePosition.html). The pre-order layout is still
+//! supported, as [`PreOrderOutboard`](struct.PreOrderOutboard.html) for any `Read + Seek` stream,
+//! and directly for in-memory `Vec<u8>` and `&[u8]` outboard encodings. The post-order layout
+//! from [`Encoder::new_outboard_post_order`](../encode/struct.Encoder.html#method.new_outboard_post_order)
+//! is supported as [`PostOrderOutboard`](struct.PostOrderOutboard.html).
+//!
+//! [`OutboardReader`](struct.OutboardReader.html) presents any store as a pre-order outboard
+//! stream, and that's how `Decoder::new_outboard_store` and `SliceExtractor::new_outboard_store`
+//! use it. Note that nothing a store returns is trusted. Decoders verify every parent node against
+//! the root hash, just like they do for outboard files.
+//!
+//! [`coarsen_outboard`](fn.coarsen_outboard.html) and [`refine_outboard`](fn.refine_outboard.html)
+//! convert between full outboard encodings and the smaller ones for chunk groups, described in
+//! the [`encode`](../encode/index.html) module. [`diff_outboards`](fn.diff_outboards.html)
+//! compares two stores to find the ranges of content that changed.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use bao::outboard::{OutboardStore, TreePosition};
+//! use std::collections::HashMap;
+//! use std::io::prelude::*;
+//!
+//! // A toy key-value store of parent nodes.
+//! struct MapStore {
+//!     content_len: u64,
+//!     parents: HashMap<TreePosition, [u8; 64]>,
+//! }
+//!
+//! impl OutboardStore for MapStore {
+//!     fn content_len(&mut self) -> std::io::Result<u64> {
+//!         Ok(self.content_len)
+//!     }
+//!
+//!     fn parent(&mut self, position: TreePosition) -> std::io::Result<[u8; 64]> {
+//!         self.parents.get(&position).copied().ok_or_else(|| {
+//!             std::io::Error::new(std::io::ErrorKind::NotFound, "missing parent node")
+//!         })
+//!     }
+//! }
+//!
+//! // Copy the parent nodes out of a regular outboard encoding.
+//! let input = vec![0; 1_000_000];
+//! let (mut outboard, hash) = bao::encode::outboard(&input);
+//! let mut store = MapStore {
+//!     content_len: outboard.content_len()?,
+//!     parents: HashMap::new(),
+//! };
+//! for position in bao::outboard::parent_positions(store.content_len) {
+//!     store.parents.insert(position, outboard.parent(position)?);
+//! }
+//!
+//! let mut decoder = bao::decode::Decoder::new_outboard_store(&*input, store, &hash);
+//! let mut output = Vec::new();
+//! decoder.read_to_end(&mut output)?;
+//! assert_eq!(input, output);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::decode::{self, ReadAt};
+use crate::encode;
+use crate::{Finalization, Hash, ParentNode, CHUNK_SIZE, HASH_SIZE, HEADER_SIZE, PARENT_SIZE};
+use arrayref::array_ref;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::ops::Range;
+
+/// The position of a subtree in the tree: the index of its first chunk, and its height. Height
+/// zero is a single chunk, and a subtree of height `h` covers up to `2^h` chunks. Parent nodes
+/// have a height of at least one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct TreePosition {
+    pub start_chunk: u64,
+    pub height: u8,
+}
+
+/// A source of outboard tree nodes.
+///
+/// Implementations return untrusted bytes, and callers verify them. A store that's missing a node
+/// should return an error, and that error will be passed along to the caller of the decoder.
+pub trait OutboardStore {
+    /// Return the length of the content, which is the length header of an outboard encoding.
+    /// Like the header, this isn't verified until the final chunk is.
+    fn content_len(&mut self) -> io::Result<u64>;
+
+    /// Return the parent node at `position`, the concatenated CVs of its left and right
+    /// children.
+    fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]>;
+}
+
+impl<S: OutboardStore + ?Sized> OutboardStore for &mut S {
+    fn content_len(&mut self) -> io::Result<u64> {
+        (**self).content_len()
+    }
+
+    fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]> {
+        (**self).parent(position)
+    }
+}
+
+/// Return the positions of all the parent nodes in a tree with `content_len` bytes, in pre-order.
+/// That's the order they're stored in an outboard encoding.
+pub fn parent_positions(content_len: u64) -> impl Iterator<Item = TreePosition> {
+    let num_parents = encode::count_chunks(content_len) - 1;
+    (0..num_parents).map(move |index| position_at_index(content_len, index))
+}
+
+// The index of the parent node at `position` in a pre-order list of all the parent nodes, or an
+// error if there's no parent node there.
+fn pre_order_index(content_len: u64, position: TreePosition) -> io::Result<u64> {
+    let mut start_chunk = 0;
+    let mut num_chunks = encode::count_chunks(content_len);
+    let mut index = 0;
+    while num_chunks > 1 {
+        if start_chunk == position.start_chunk
+            && encode::subtree_height(num_chunks) == position.height
+        {
+            return Ok(index);
+        }
+        // The root of this subtree comes first, followed by all the parents in the left subtree,
+        // of which there are one less than the number of chunks.
+        let left_chunks = encode::left_subtree_chunks(num_chunks);
+        if position.start_chunk < start_chunk + left_chunks {
+            index += 1;
+            num_chunks = left_chunks;
+        } else {
+            index += left_chunks;
+            start_chunk += left_chunks;
+            num_chunks -= left_chunks;
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "no parent node at tree position",
+    ))
+}
+
+// The inverse of pre_order_index.
+fn position_at_index(content_len: u64, mut index: u64) -> TreePosition {
+    let mut start_chunk = 0;
+    let mut num_chunks = encode::count_chunks(content_len);
+    loop {
+        debug_assert!(num_chunks > 1, "parent index out of range");
+        if index == 0 {
+            return TreePosition {
+                start_chunk,
+                height: encode::subtree_height(num_chunks),
+            };
+        }
+        let left_chunks = encode::left_subtree_chunks(num_chunks);
+        if index < left_chunks {
+            index -= 1;
+            num_chunks = left_chunks;
+        } else {
+            index -= left_chunks;
+            start_chunk += left_chunks;
+            num_chunks -= left_chunks;
+        }
+    }
+}
+
+// The index of the parent node at `position` in a post-order list of all the parent nodes, or an
+// error if there's no parent node there. Each parent node comes after the last chunk of its
+// subtree, along with any others that end there, from the bottom up.
+fn post_order_index(content_len: u64, position: TreePosition) -> io::Result<u64> {
+    // Check that there's a parent node at this position at all.
+    pre_order_index(content_len, position)?;
+    let num_chunks = cmp::min(
+        1 << position.height,
+        encode::count_chunks(content_len) - position.start_chunk,
+    );
+    let last_chunk = position.start_chunk + num_chunks - 1;
+    // The parent nodes for all the chunks before the last one. Each chunk is followed by one per
+    // trailing one bit in its index, and those add up to this.
+    let before = last_chunk - last_chunk.count_ones() as u64;
+    // Then the ones below this one on the right edge of its subtree. Like the parent nodes after
+    // the final chunk of the whole tree, there's one per one bit in num_chunks - 1, including
+    // this one.
+    Ok(before + (num_chunks - 1).count_ones() as u64 - 1)
+}
+
+fn parent_offset(content_len: u64, position: TreePosition) -> io::Result<u64> {
+    Ok(HEADER_SIZE as u64 + pre_order_index(content_len, position)? * PARENT_SIZE as u64)
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "outboard encoding truncated")
+}
+
+/// An `OutboardStore` for an outboard encoding in the usual pre-order layout, like the output of
+/// `encode::Encoder::new_outboard`, from any `Read + Seek` stream.
+#[derive(Clone, Debug)]
+pub struct PreOrderOutboard<T: Read + Seek> {
+    inner: T,
+    content_len: Option<u64>,
+}
+
+impl<T: Read + Seek> PreOrderOutboard<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            content_len: None,
+        }
+    }
+
+    /// Return the underlying stream.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read + Seek> OutboardStore for PreOrderOutboard<T> {
+    fn content_len(&mut self) -> io::Result<u64> {
+        if let Some(content_len) = self.content_len {
+            return Ok(content_len);
+        }
+        let mut header = [0; HEADER_SIZE];
+        self.inner.seek(SeekFrom::Start(0))?;
+        self.inner.read_exact(&mut header)?;
+        let content_len = crate::decode_len(&header);
+        self.content_len = Some(content_len);
+        Ok(content_len)
+    }
+
+    fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]> {
+        let offset = parent_offset(self.content_len()?, position)?;
+        let mut parent = [0; PARENT_SIZE];
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.inner.read_exact(&mut parent)?;
+        Ok(parent)
+    }
+}
+
+/// An `OutboardStore` for an outboard encoding in the post-order layout, like the output of
+/// [`Encoder::new_outboard_post_order`](../encode/struct.Encoder.html#method.new_outboard_post_order),
+/// from any `Read + Seek` stream. The length header is at the end of the stream.
+#[derive(Clone, Debug)]
+pub struct PostOrderOutboard<T: Read + Seek> {
+    inner: T,
+    content_len: Option<u64>,
+}
+
+impl<T: Read + Seek> PostOrderOutboard<T> {
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            content_len: None,
+        }
+    }
+
+    /// Return the underlying stream.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Read + Seek> OutboardStore for PostOrderOutboard<T> {
+    fn content_len(&mut self) -> io::Result<u64> {
+        if let Some(content_len) = self.content_len {
+            return Ok(content_len);
+        }
+        let end = self.inner.seek(SeekFrom::End(0))?;
+        if end < HEADER_SIZE as u64 {
+            return Err(truncated());
+        }
+        let mut header = [0; HEADER_SIZE];
+        self.inner.seek(SeekFrom::Start(end - HEADER_SIZE as u64))?;
+        self.inner.read_exact(&mut header)?;
+        let content_len = crate::decode_len(&header);
+        self.content_len = Some(content_len);
+        Ok(content_len)
+    }
+
+    fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]> {
+        let offset = post_order_index(self.content_len()?, position)? * PARENT_SIZE as u64;
+        let mut parent = [0; PARENT_SIZE];
+        self.inner.seek(SeekFrom::Start(offset))?;
+        self.inner.read_exact(&mut parent)?;
+        Ok(parent)
+    }
+}
+
+impl OutboardStore for &[u8] {
+    fn content_len(&mut self) -> io::Result<u64> {
+        if self.len() < HEADER_SIZE {
+            return Err(truncated());
+        }
+        Ok(crate::decode_len(array_ref!(self, 0, HEADER_SIZE)))
+    }
+
+    fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]> {
+        let offset = parent_offset(self.content_len()?, position)?;
+        if (self.len() as u64) < offset + PARENT_SIZE as u64 {
+            return Err(truncated());
+        }
+        Ok(*array_ref!(self, offset as usize, PARENT_SIZE))
+    }
+}
+
+impl OutboardStore for Vec<u8> {
+    fn content_len(&mut self) -> io::Result<u64> {
+        self.as_slice().content_len()
+    }
+
+    fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]> {
+        self.as_slice().parent(position)
+    }
+}
+
+/// An outboard encoding in memory, together with its root hash and content length.
+///
+/// This owns the bytes of a pre-order outboard encoding, like the output of
+/// [`encode::outboard`](../encode/fn.outboard.html), and answers questions about the tree without
+/// any byte arithmetic on the caller's part. The bytes are available with
+/// [`as_bytes`](#method.as_bytes) for use with `Decoder::new_outboard`, and `Outboard` also
+/// implements [`OutboardStore`](trait.OutboardStore.html).
+///
+/// Constructing an `Outboard` from existing bytes checks every parent node against the root hash,
+/// but that doesn't verify the length header, which decides the shape of the tree. Only hashing
+/// the final chunk does that, as a decoder will. Until then, a forged length can go unnoticed, and
+/// so can wrong CVs along the right edge of the tree, where a forged length changes the shape.
+/// Content of one chunk or less has no parent nodes, so for it nothing is checked at all. The
+/// content itself is verified when decoding.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use bao::outboard::{Outboard, TreePosition};
+/// use std::io::prelude::*;
+///
+/// let input = vec![0xab; 1_000_000];
+/// let outboard = Outboard::new(&input);
+/// assert_eq!(input.len() as u64, outboard.content_len());
+///
+/// // Look up the CV of the subtree covering the first 16 chunks, and the parent node above it.
+/// let position = TreePosition { start_chunk: 0, height: 4 };
+/// let cv = outboard.cv(position).unwrap();
+/// let parent = TreePosition { start_chunk: 0, height: 5 };
+/// assert_eq!(cv, outboard.parent(parent).unwrap().0);
+///
+/// // Round-trip the outboard through its serialized form.
+/// let outboard = Outboard::deserialize(&outboard.serialize())?;
+///
+/// // Decode the input and extract a slice.
+/// let mut decoder = bao::decode::Decoder::new_outboard(&*input, outboard.as_bytes(), outboard.hash());
+/// let mut output = Vec::new();
+/// decoder.read_to_end(&mut output)?;
+/// assert_eq!(input, output);
+/// let slice = outboard.slice(&input, 65536, 8192)?;
+/// let mut decoder = bao::decode::SliceDecoder::new(&*slice, outboard.hash(), 65536, 8192);
+/// output.clear();
+/// decoder.read_to_end(&mut output)?;
+/// assert_eq!(&input[65536..][..8192], &*output);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct Outboard {
+    bytes: Vec<u8>,
+    hash: Hash,
+    content_len: u64,
+}
+
+impl Outboard {
+    /// Compute the outboard encoding of `input`.
+    pub fn new(input: impl AsRef<[u8]>) -> Self {
+        let (bytes, hash) = encode::outboard(input);
+        let content_len = crate::decode_len(array_ref!(bytes, 0, HEADER_SIZE));
+        Self {
+            bytes,
+            hash,
+            content_len,
+        }
+    }
+
+    /// Take ownership of the bytes of an outboard encoding with the given root hash. This returns
+    /// an error of kind `UnexpectedEof` if the bytes are too short for the length header, or
+    /// `InvalidData` if they're too long or if any parent node doesn't match the root hash. The
+    /// length header isn't verified, as described in the
+    /// [type documentation](struct.Outboard.html).
+    pub fn from_bytes(bytes: Vec<u8>, hash: &Hash) -> io::Result<Self> {
+        let content_len = (&*bytes).content_len()?;
+        let expected_size = encode::outboard_size(content_len);
+        if (bytes.len() as u128) < expected_size {
+            return Err(truncated());
+        }
+        if (bytes.len() as u128) > expected_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "trailing bytes after outboard encoding",
+            ));
+        }
+        let outboard = Self {
+            bytes,
+            hash: *hash,
+            content_len,
+        };
+        outboard.verify_parents(
+            0,
+            encode::count_chunks(content_len),
+            hash,
+            Finalization::Root,
+        )?;
+        Ok(outboard)
+    }
+
+    // For callers who've already verified every parent node.
+    pub(crate) fn from_verified_parts(bytes: Vec<u8>, hash: &Hash, content_len: u64) -> Self {
+        debug_assert_eq!(encode::outboard_size(content_len), bytes.len() as u128);
+        Self {
+            bytes,
+            hash: *hash,
+            content_len,
+        }
+    }
+
+    /// Serialize the `Outboard`, as the 32-byte root hash followed by the outboard encoding.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut serialized = Vec::with_capacity(HASH_SIZE + self.bytes.len());
+        serialized.extend_from_slice(self.hash.as_bytes());
+        serialized.extend_from_slice(&self.bytes);
+        serialized
+    }
+
+    /// Deserialize the output of [`serialize`](#method.serialize). This checks the bytes just like
+    /// [`from_bytes`](#method.from_bytes) does.
+    pub fn deserialize(serialized: &[u8]) -> io::Result<Self> {
+        if serialized.len() < HASH_SIZE {
+            return Err(truncated());
+        }
+        let hash: Hash = (*array_ref!(serialized, 0, HASH_SIZE)).into();
+        Self::from_bytes(serialized[HASH_SIZE..].to_vec(), &hash)
+    }
+
+    /// The root hash.
+    pub fn hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    /// The length of the content. For an `Outboard` made from existing bytes, this comes from the
+    /// length header, and it isn't verified until the final chunk is.
+    pub fn content_len(&self) -> u64 {
+        self.content_len
+    }
+
+    /// The bytes of the outboard encoding.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Return the bytes of the outboard encoding.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// Return the CVs of the left and right children of the parent node at `position`, or `None`
+    /// if there's no parent node there.
+    pub fn parent(&self, position: TreePosition) -> Option<(Hash, Hash)> {
+        let index = pre_order_index(self.content_len, position).ok()?;
+        Some(self.parent_at_index(index))
+    }
+
+    /// Return the CV of the subtree at `position`, which may be a parent node or a single chunk,
+    /// or `None` if there's no such subtree. The CV of the root is the root hash.
+    pub fn cv(&self, position: TreePosition) -> Option<Hash> {
+        let mut start_chunk = 0;
+        let mut num_chunks = encode::count_chunks(self.content_len);
+        if position.start_chunk == 0 && position.height == encode::subtree_height(num_chunks) {
+            return Some(self.hash);
+        }
+        // Walk down from the root, reading the target's CV from its parent.
+        let mut index = 0;
+        while num_chunks > 1 {
+            let left_chunks = encode::left_subtree_chunks(num_chunks);
+            let (left_cv, right_cv) = self.parent_at_index(index);
+            let cv;
+            if position.start_chunk < start_chunk + left_chunks {
+                cv = left_cv;
+                index += 1;
+                num_chunks = left_chunks;
+            } else {
+                cv = right_cv;
+                index += left_chunks;
+                start_chunk += left_chunks;
+                num_chunks -= left_chunks;
+            }
+            if start_chunk == position.start_chunk
+                && encode::subtree_height(num_chunks) == position.height
+            {
+                return Some(cv);
+            }
+        }
+        None
+    }
+
+    /// Extract a slice of `input`, which must be the content of this outboard encoding. This is
+    /// the same as using `SliceExtractor::new_outboard`, and the result is the proof for that range
+    /// of content, which `SliceDecoder` verifies.
+    pub fn slice(
+        &self,
+        input: impl AsRef<[u8]>,
+        slice_start: u64,
+        slice_len: u64,
+    ) -> io::Result<Vec<u8>> {
+        let mut extractor = encode::SliceExtractor::new_outboard(
+            io::Cursor::new(input.as_ref()),
+            io::Cursor::new(&*self.bytes),
+            slice_start,
+            slice_len,
+        );
+        let mut slice = Vec::new();
+        extractor.read_to_end(&mut slice)?;
+        Ok(slice)
+    }
+
+    /// Extend the outboard for content that's been appended to the input, and return the new
+    /// root hash. `input` must be the complete new input, with its first `content_len()` bytes
+    /// unchanged.
+    ///
+    /// Only the nodes on the right edge of the tree are recomputed. The CVs of the complete
+    /// subtrees covering the unchanged content come from the existing parent nodes, and hashing
+    /// resumes from there, starting with the old final chunk if it was partial. Apart from that
+    /// chunk, this only reads the appended content. The parent nodes that don't change aren't
+    /// rehashed, and they're moved into their new pre-order positions in a few contiguous blocks,
+    /// one for each complete subtree of the unchanged chunks, so extending costs O(n) time.
+    ///
+    /// The old final chunk is checked against its CV, and if it's changed, this returns an error
+    /// of kind `InvalidData` and leaves the outboard alone. If the input is shorter than before,
+    /// this returns an error of kind `InvalidInput`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut log = b"first entry\n".to_vec();
+    /// let mut outboard = bao::outboard::Outboard::new(&log);
+    /// log.extend_from_slice(b"second entry\n");
+    /// let hash = outboard.extend(std::io::Cursor::new(&log))?;
+    /// assert_eq!(bao::encode::outboard(&log), (outboard.as_bytes().to_vec(), hash));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn extend(&mut self, mut input: impl Read + Seek) -> io::Result<Hash> {
+        let old_len = self.content_len;
+        let old_chunks = encode::count_chunks(old_len);
+        // All the chunks before this one are complete and unchanged, and they're covered by
+        // complete subtrees in the old tree. If the old tree is a single chunk, we don't have its
+        // non-root CV, so we rehash it.
+        let first_new_chunk = if old_len <= CHUNK_SIZE as u64 {
+            0
+        } else {
+            old_len / CHUNK_SIZE as u64
+        };
+        let input_len = input.seek(SeekFrom::End(0))?;
+        if input_len < old_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "input is shorter than the outboard",
+            ));
+        }
+        let tail_start = first_new_chunk * CHUNK_SIZE as u64;
+        input.seek(SeekFrom::Start(tail_start))?;
+        let mut buf = [0; CHUNK_SIZE];
+        let mut buf_len = read_chunk(&mut input, &mut buf)?;
+        if old_len > tail_start {
+            let old_chunk_len = (old_len - tail_start) as usize;
+            let old_cv =
+                crate::Mode::Hash.chunk_cv(first_new_chunk, &buf[..old_chunk_len], old_chunks == 1);
+            let position = TreePosition {
+                start_chunk: first_new_chunk,
+                height: 0,
+            };
+            // Hash implements constant time equality.
+            if Some(old_cv) != self.cv(position) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "input doesn't match the outboard",
+                ));
+            }
+        }
+        if input_len == old_len {
+            // Nothing was appended.
+            return Ok(self.hash);
+        }
+
+        // The State doesn't keep track of subtree positions, so we do that alongside it, to
+        // know where each new parent node goes.
+        let mut state = encode::State::new();
+        let mut subtrees: Vec<(u64, u64)> = Vec::new();
+        let mut new_parents = HashMap::new();
+        let mut record_parent = |subtrees: &mut Vec<(u64, u64)>, parent| {
+            let (_, right_chunks) = subtrees.pop().unwrap();
+            let (start_chunk, left_chunks) = subtrees.pop().unwrap();
+            let num_chunks = left_chunks + right_chunks;
+            subtrees.push((start_chunk, num_chunks));
+            let position = TreePosition {
+                start_chunk,
+                height: encode::subtree_height(num_chunks),
+            };
+            new_parents.insert(position, parent);
+        };
+        // Push the complete subtrees covering the unchanged chunks, largest first. Like a binary
+        // number, these never need to be merged with each other yet.
+        let mut start_chunk = 0;
+        for height in (0..64).rev() {
+            let num_chunks = 1 << height;
+            if first_new_chunk & num_chunks != 0 {
+                let cv = self.non_root_cv(TreePosition {
+                    start_chunk,
+                    height: height as u8,
+                });
+                state.push_subtree(&cv, num_chunks as usize * CHUNK_SIZE);
+                subtrees.push((start_chunk, num_chunks));
+                start_chunk += num_chunks;
+            }
+        }
+        // Hash everything from first_new_chunk to the end, one chunk behind the reader, so that
+        // we know which chunk is the last.
+        let mut chunk_index = first_new_chunk;
+        let new_hash = loop {
+            let mut next_buf = [0; CHUNK_SIZE];
+            let next_len = if buf_len == CHUNK_SIZE {
+                read_chunk(&mut input, &mut next_buf)?
+            } else {
+                0
+            };
+            let mut chunk_state = crate::Mode::Hash.chunk_state(chunk_index);
+            chunk_state.update(&buf[..buf_len]);
+            if next_len == 0 && state.count() == 0 {
+                // The new input is a single chunk.
+                break chunk_state.finalize(true);
+            }
+            state.push_subtree(&chunk_state.finalize(false), buf_len);
+            subtrees.push((chunk_index, 1));
+            if next_len == 0 {
+                let root = loop {
+                    match state.merge_finalize() {
+                        encode::StateFinish::Parent(parent) => record_parent(&mut subtrees, parent),
+                        encode::StateFinish::Root(root) => break root,
+                    }
+                };
+                break root;
+            }
+            while let Some(parent) = state.merge_parent() {
+                record_parent(&mut subtrees, parent);
+            }
+            buf = next_buf;
+            buf_len = next_len;
+            chunk_index += 1;
+        };
+
+        // Lay out the new tree in pre-order. Every parent node that wasn't just computed is
+        // inside one of the unchanged subtrees, and it's the same as before.
+        let new_len = chunk_index * CHUNK_SIZE as u64 + buf_len as u64;
+        let mut bytes = Vec::with_capacity(encode::outboard_size(new_len) as usize);
+        bytes.extend_from_slice(&crate::encode_len(new_len));
+        self.relayout(
+            Subtree::root(new_len),
+            first_new_chunk,
+            &new_parents,
+            &mut bytes,
+        );
+        self.bytes = bytes;
+        self.hash = new_hash;
+        self.content_len = new_len;
+        Ok(new_hash)
+    }
+
+    /// Update the outboard after the bytes in `range` of the input have been modified in place,
+    /// and return the new root hash. `input` is the complete modified input, and its length must
+    /// not have changed.
+    ///
+    /// Only the chunks that overlap `range` are read and rehashed, and only the parent nodes on
+    /// their paths to the root are recomputed. Those are overwritten in place once they've all
+    /// been computed, so if reading the input fails, the outboard is left unchanged. See
+    /// [`update_outboard`](fn.update_outboard.html) for an outboard encoding in a file.
+    ///
+    /// If `range` extends past the end of the content, or the input isn't exactly
+    /// `content_len()` bytes long, this returns an error of kind `InvalidInput` and changes
+    /// nothing.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// let mut input = vec![0; 1_000_000];
+    /// let mut outboard = bao::outboard::Outboard::new(&input);
+    /// input[500_000..500_010].copy_from_slice(b"new bytes!");
+    /// let hash = outboard.update(&*input, 500_000..500_010)?;
+    /// assert_eq!(bao::encode::outboard(&input), (outboard.as_bytes().to_vec(), hash));
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn update(&mut self, input: impl ReadAt, range: Range<u64>) -> io::Result<Hash> {
+        self.hash = update_parents(&input, &mut self.bytes, self.content_len, range)?;
+        Ok(self.hash)
+    }
+
+    // Append the parent nodes of a subtree of the extended tree to `bytes`, in pre-order. The
+    // parent nodes of a subtree that's entirely before `first_new_chunk` are contiguous in the
+    // old outboard, so they're copied as one block. That keeps extending O(n) overall, with only
+    // the right edge of the tree walked node by node.
+    fn relayout(
+        &self,
+        subtree: Subtree,
+        first_new_chunk: u64,
+        new_parents: &HashMap<TreePosition, ParentNode>,
+        bytes: &mut Vec<u8>,
+    ) {
+        if subtree.num_chunks == 1 {
+            return;
+        }
+        let position = subtree.position();
+        if subtree.start_chunk + subtree.num_chunks <= first_new_chunk {
+            let index = pre_order_index(self.content_len, position).expect("unchanged parent node");
+            let offset = HEADER_SIZE + index as usize * PARENT_SIZE;
+            let len = (subtree.num_chunks - 1) as usize * PARENT_SIZE;
+            bytes.extend_from_slice(&self.bytes[offset..][..len]);
+            return;
+        }
+        bytes.extend_from_slice(&new_parents[&position]);
+        let (left, right) = subtree.children();
+        self.relayout(left, first_new_chunk, new_parents, bytes);
+        self.relayout(right, first_new_chunk, new_parents, bytes);
+    }
+
+    // The non-root CV of a complete subtree. For the root itself, that has to be computed from
+    // the root parent node.
+    fn non_root_cv(&self, position: TreePosition) -> Hash {
+        let num_chunks = encode::count_chunks(self.content_len);
+        if position.start_chunk == 0 && position.height == encode::subtree_height(num_chunks) {
+            debug_assert!(num_chunks > 1);
+            let (left_cv, right_cv) = self.parent_at_index(0);
+            crate::Mode::Hash.parent_cv(&left_cv, &right_cv, false)
+        } else {
+            self.cv(position).expect("complete subtree")
+        }
+    }
+
+    fn parent_at_index(&self, index: u64) -> (Hash, Hash) {
+        let offset = HEADER_SIZE + index as usize * PARENT_SIZE;
+        let left_cv = (*array_ref!(self.bytes, offset, HASH_SIZE)).into();
+        let right_cv = (*array_ref!(self.bytes, offset + HASH_SIZE, HASH_SIZE)).into();
+        (left_cv, right_cv)
+    }
+
+    // Check every parent node of the subtree against its CV. Subtrees are numbered by the
+    // pre-order index of their root parent node.
+    fn verify_parents(
+        &self,
+        index: u64,
+        num_chunks: u64,
+        cv: &Hash,
+        finalization: Finalization,
+    ) -> io::Result<()> {
+        if num_chunks == 1 {
+            return Ok(());
+        }
+        let (left_cv, right_cv) = self.parent_at_index(index);
+        let computed = crate::Mode::Hash.parent_cv(&left_cv, &right_cv, finalization.is_root());
+        // Hash implements constant time equality.
+        if &computed != cv {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "outboard parent node doesn't match",
+            ));
+        }
+        let left_chunks = encode::left_subtree_chunks(num_chunks);
+        self.verify_parents(index + 1, left_chunks, &left_cv, Finalization::NotRoot)?;
+        self.verify_parents(
+            index + left_chunks,
+            num_chunks - left_chunks,
+            &right_cv,
+            Finalization::NotRoot,
+        )
+    }
+}
+
+impl AsRef<[u8]> for Outboard {
+    fn as_ref(&self) -> &[u8] {
+        &self.bytes
+    }
+}
+
+impl OutboardStore for Outboard {
+    fn content_len(&mut self) -> io::Result<u64> {
+        Ok(self.content_len)
+    }
+
+    fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]> {
+        (&*self.bytes).parent(position)
+    }
+}
+
+impl OutboardStore for &Outboard {
+    fn content_len(&mut self) -> io::Result<u64> {
+        Ok(self.content_len)
+    }
+
+    fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]> {
+        (&*self.bytes).parent(position)
+    }
+}
+
+impl fmt::Debug for Outboard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing hashes, they might be secret.
+        write!(f, "Outboard {{ content_len: {}, ... }}", self.content_len)
+    }
+}
+
+/// Update an outboard encoding in place, after the bytes in `range` of the input have been
+/// modified, and return the new root hash. This is like
+/// [`Outboard::update`](struct.Outboard.html#method.update), but for an outboard encoding in a file
+/// or any other `Read + Write + Seek` stream. Only the parent nodes on the paths from the modified
+/// chunks to the root are read and rewritten.
+///
+/// If `range` is empty, nothing changes, and the root hash is computed from the root parent node
+/// (or the single chunk) without checking anything else. If `range` extends past the end of the
+/// content, or the input's length doesn't match the length header, this returns an error of kind
+/// `InvalidInput` and changes nothing.
+///
+/// The new parent nodes are all computed before any of them are written, so an error reading the
+/// input or the outboard leaves the outboard unchanged. An error partway through writing them
+/// can still leave it partly updated.
+pub fn update_outboard(
+    input: impl ReadAt,
+    mut outboard: impl Read + Write + Seek,
+    range: Range<u64>,
+) -> io::Result<Hash> {
+    let mut header = [0; HEADER_SIZE];
+    outboard.seek(SeekFrom::Start(0))?;
+    outboard.read_exact(&mut header)?;
+    let content_len = crate::decode_len(&header);
+    update_parents(&input, &mut StreamParents(outboard), content_len, range)
+}
+
+// Parent node storage for updates, addressed by pre-order index.
+trait ParentNodes {
+    fn read_parent(&mut self, index: u64) -> io::Result<ParentNode>;
+    fn write_parent(&mut self, index: u64, parent: &ParentNode) -> io::Result<()>;
+}
+
+impl ParentNodes for Vec<u8> {
+    fn read_parent(&mut self, index: u64) -> io::Result<ParentNode> {
+        Ok(*array_ref!(
+            self,
+            HEADER_SIZE + index as usize * PARENT_SIZE,
+            PARENT_SIZE
+        ))
+    }
+
+    fn write_parent(&mut self, index: u64, parent: &ParentNode) -> io::Result<()> {
+        let offset = HEADER_SIZE + index as usize * PARENT_SIZE;
+        self[offset..][..PARENT_SIZE].copy_from_slice(parent);
+        Ok(())
+    }
+}
+
+struct StreamParents<O>(O);
+
+impl<O: Read + Write + Seek> ParentNodes for StreamParents<O> {
+    fn read_parent(&mut self, index: u64) -> io::Result<ParentNode> {
+        let mut parent = [0; PARENT_SIZE];
+        self.0.seek(SeekFrom::Start(
+            HEADER_SIZE as u64 + index * PARENT_SIZE as u64,
+        ))?;
+        self.0.read_exact(&mut parent)?;
+        Ok(parent)
+    }
+
+    fn write_parent(&mut self, index: u64, parent: &ParentNode) -> io::Result<()> {
+        self.0.seek(SeekFrom::Start(
+            HEADER_SIZE as u64 + index * PARENT_SIZE as u64,
+        ))?;
+        self.0.write_all(parent)
+    }
+}
+
+// Parent node writes held back until a whole update has succeeded, so that a read error or a
+// short input partway through leaves the outboard as it was.
+struct PendingParents<'a, N> {
+    nodes: &'a mut N,
+    writes: BTreeMap<u64, ParentNode>,
+}
+
+impl<N: ParentNodes> ParentNodes for PendingParents<'_, N> {
+    fn read_parent(&mut self, index: u64) -> io::Result<ParentNode> {
+        match self.writes.get(&index) {
+            Some(parent) => Ok(*parent),
+            None => self.nodes.read_parent(index),
+        }
+    }
+
+    fn write_parent(&mut self, index: u64, parent: &ParentNode) -> io::Result<()> {
+        self.writes.insert(index, *parent);
+        Ok(())
+    }
+}
+
+// Rehash the chunks overlapping `range`, rewrite the parent nodes above them, and return the
+// new root hash. Nothing is written until all the new parent nodes have been computed.
+fn update_parents(
+    input: &impl ReadAt,
+    nodes: &mut impl ParentNodes,
+    content_len: u64,
+    range: Range<u64>,
+) -> io::Result<Hash> {
+    if range.start > range.end || range.end > content_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "range out of bounds",
+        ));
+    }
+    check_input_len(input, content_len)?;
+    let mut pending = PendingParents {
+        nodes,
+        writes: BTreeMap::new(),
+    };
+    let hash = update_root(input, &mut pending, content_len, range)?;
+    for (index, parent) in &pending.writes {
+        pending.nodes.write_parent(*index, parent)?;
+    }
+    Ok(hash)
+}
+
+// The input of an update has to be exactly as long as the content. Check that up front, rather
+// than failing on a short read partway through, or hashing a longer input as if it weren't.
+fn check_input_len(input: &impl ReadAt, content_len: u64) -> io::Result<()> {
+    let mut byte = [0; 1];
+    let long_enough = content_len == 0 || input.read_at(&mut byte, content_len - 1)? == 1;
+    if !long_enough || input.read_at(&mut byte, content_len)? != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "input length doesn't match the outboard",
+        ));
+    }
+    Ok(())
+}
+
+fn update_root(
+    input: &impl ReadAt,
+    nodes: &mut impl ParentNodes,
+    content_len: u64,
+    range: Range<u64>,
+) -> io::Result<Hash> {
+    let root = Subtree::root(content_len);
+    if range.start == range.end {
+        // Nothing changed, but we still need the root hash.
+        if root.num_chunks == 1 {
+            return hash_chunk(input, content_len, 0, Finalization::Root);
+        }
+        let (left_cv, right_cv) = crate::split_parent(&nodes.read_parent(0)?);
+        return Ok(crate::Mode::Hash.parent_cv(&left_cv, &right_cv, true));
+    }
+    let chunks = range.start / CHUNK_SIZE as u64..(range.end - 1) / CHUNK_SIZE as u64 + 1;
+    update_subtree(input, nodes, content_len, root, Finalization::Root, &chunks)
+}
+
+// Return the new CV of a subtree that overlaps `chunks`.
+fn update_subtree(
+    input: &impl ReadAt,
+    nodes: &mut impl ParentNodes,
+    content_len: u64,
+    subtree: Subtree,
+    finalization: Finalization,
+    chunks: &Range<u64>,
+) -> io::Result<Hash> {
+    if subtree.num_chunks == 1 {
+        return hash_chunk(input, content_len, subtree.start_chunk, finalization);
+    }
+    let (mut left_cv, mut right_cv) =
+        crate::split_parent(&nodes.read_parent(subtree.parent_index)?);
+    let (left, right) = subtree.children();
+    if left.overlaps(chunks) {
+        left_cv = update_subtree(
+            input,
+            nodes,
+            content_len,
+            left,
+            Finalization::NotRoot,
+            chunks,
+        )?;
+    }
+    if right.overlaps(chunks) {
+        right_cv = update_subtree(
+            input,
+            nodes,
+            content_len,
+            right,
+            Finalization::NotRoot,
+            chunks,
+        )?;
+    }
+    let mut new_parent = [0; PARENT_SIZE];
+    new_parent[..HASH_SIZE].copy_from_slice(left_cv.as_bytes());
+    new_parent[HASH_SIZE..].copy_from_slice(right_cv.as_bytes());
+    nodes.write_parent(subtree.parent_index, &new_parent)?;
+    Ok(crate::Mode::Hash.parent_cv(&left_cv, &right_cv, finalization.is_root()))
+}
+
+fn hash_chunk(
+    input: &impl ReadAt,
+    content_len: u64,
+    chunk_index: u64,
+    finalization: Finalization,
+) -> io::Result<Hash> {
+    let size = encode::chunk_size(chunk_index, content_len);
+    let mut chunk = [0; CHUNK_SIZE];
+    input.read_exact_at(&mut chunk[..size], chunk_index * CHUNK_SIZE as u64)?;
+    Ok(crate::Mode::Hash.chunk_cv(chunk_index, &chunk[..size], finalization.is_root()))
+}
+
+// A subtree, numbered by the pre-order index of its root parent node (if any) in the outboard
+// encoding.
+#[derive(Clone, Copy)]
+pub(crate) struct Subtree {
+    pub start_chunk: u64,
+    pub num_chunks: u64,
+    pub parent_index: u64,
+}
+
+impl Subtree {
+    pub fn root(content_len: u64) -> Subtree {
+        Subtree {
+            start_chunk: 0,
+            num_chunks: encode::count_chunks(content_len),
+            parent_index: 0,
+        }
+    }
+
+    pub fn position(&self) -> TreePosition {
+        TreePosition {
+            start_chunk: self.start_chunk,
+            height: encode::subtree_height(self.num_chunks),
+        }
+    }
+
+    pub fn overlaps(&self, chunks: &Range<u64>) -> bool {
+        self.start_chunk < chunks.end && chunks.start < self.start_chunk + self.num_chunks
+    }
+
+    pub fn children(&self) -> (Subtree, Subtree) {
+        let left_chunks = encode::left_subtree_chunks(self.num_chunks);
+        let left = Subtree {
+            start_chunk: self.start_chunk,
+            num_chunks: left_chunks,
+            parent_index: self.parent_index + 1,
+        };
+        let right = Subtree {
+            start_chunk: self.start_chunk + left_chunks,
+            num_chunks: self.num_chunks - left_chunks,
+            parent_index: self.parent_index + left_chunks,
+        };
+        (left, right)
+    }
+}
+
+/// Find the ranges of content that differ between two inputs of the same length, by comparing
+/// the parent nodes of their outboard encodings from the top down. Subtrees with equal CVs have
+/// equal content, so they're skipped, and only the parent nodes above a change are read. That's
+/// O(log n) nodes per changed chunk.
+///
+/// The ranges are in bytes, in order, with neighboring ranges merged, and each one covers whole
+/// chunks except at the end of the content. The root hashes `hash_a` and `hash_b` are compared
+/// first, so equal content reads no parent nodes at all, and content of a single chunk, which has
+/// no parent nodes, is reported as changed only if its hashes differ.
+///
+/// Every parent node read is verified against the CV above it, starting from the root hashes, and
+/// the parent nodes along the right edge of each tree are always read, so a content length that
+/// doesn't match the shape of the tree fails too. The final chunk itself isn't available here, so
+/// a length that's wrong by less than a chunk can't be caught.
+///
+/// This returns an error of kind `InvalidInput` if the two content lengths are different, and an
+/// error of kind `InvalidData` if a parent node doesn't match its CV.
+pub fn diff_outboards(
+    mut a: impl OutboardStore,
+    hash_a: &Hash,
+    mut b: impl OutboardStore,
+    hash_b: &Hash,
+) -> io::Result<Vec<Range<u64>>> {
+    let content_len = a.content_len()?;
+    if b.content_len()? != content_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "content lengths differ",
+        ));
+    }
+    let mut ranges = Vec::new();
+    // Hash implements constant time equality.
+    if hash_a == hash_b {
+        return Ok(ranges);
+    }
+    let root = Subtree::root(content_len);
+    if root.num_chunks > 1 {
+        diff_subtree(
+            &mut a,
+            &mut b,
+            root,
+            hash_a,
+            hash_b,
+            content_len,
+            &mut ranges,
+        )?;
+    } else {
+        ranges.push(0..content_len);
+    }
+    Ok(ranges)
+}
+
+fn diff_subtree(
+    a: &mut impl OutboardStore,
+    b: &mut impl OutboardStore,
+    subtree: Subtree,
+    a_cv: &Hash,
+    b_cv: &Hash,
+    content_len: u64,
+    ranges: &mut Vec<Range<u64>>,
+) -> io::Result<()> {
+    let position = subtree.position();
+    let is_root = subtree.parent_index == 0;
+    let (a_left_cv, a_right_cv) = crate::split_parent(&a.parent(position)?);
+    let (b_left_cv, b_right_cv) = crate::split_parent(&b.parent(position)?);
+    // Hash implements constant time equality.
+    if &crate::Mode::Hash.parent_cv(&a_left_cv, &a_right_cv, is_root) != a_cv
+        || &crate::Mode::Hash.parent_cv(&b_left_cv, &b_right_cv, is_root) != b_cv
+    {
+        return Err(decode::Error::HashMismatch.into());
+    }
+    let (left, right) = subtree.children();
+    for (child, a_cv, b_cv) in [
+        (left, a_left_cv, b_left_cv),
+        (right, a_right_cv, b_right_cv),
+    ] {
+        // Hash implements constant time equality.
+        let changed = a_cv != b_cv;
+        // The right edge is read even when it's unchanged, to check the content length.
+        let right_edge = child.start_chunk + child.num_chunks == encode::count_chunks(content_len);
+        if child.num_chunks > 1 {
+            if changed || right_edge {
+                diff_subtree(a, b, child, &a_cv, &b_cv, content_len, ranges)?;
+            }
+            continue;
+        }
+        if !changed {
+            continue;
+        }
+        let start = child.start_chunk * CHUNK_SIZE as u64;
+        let end = cmp::min(start + CHUNK_SIZE as u64, content_len);
+        match ranges.last_mut() {
+            Some(last) if last.end == start => last.end = end,
+            _ => ranges.push(start..end),
+        }
+    }
+    Ok(())
+}
+
+/// Convert a full outboard encoding into the smaller outboard encoding for chunk groups of
+/// 2^`chunk_group_log` chunks, as made by
+/// [`Encoder::new_outboard_grouped`](../encode/struct.Encoder.html#method.new_outboard_grouped).
+/// The parent nodes of the lowest `chunk_group_log` levels of the tree are dropped, and the rest
+/// are copied to `output` unchanged, so the root hash stays the same. Nothing is verified.
+///
+/// If `outboard` is truncated, this returns an error of kind `UnexpectedEof`.
+///
+/// # Panics
+///
+/// Panics if `chunk_group_log` is greater than
+/// [`MAX_CHUNK_GROUP_LOG`](../encode/constant.MAX_CHUNK_GROUP_LOG.html).
+pub fn coarsen_outboard(
+    mut outboard: impl Read,
+    mut output: impl Write,
+    chunk_group_log: u8,
+) -> io::Result<()> {
+    assert!(
+        chunk_group_log <= encode::MAX_CHUNK_GROUP_LOG,
+        "chunk_group_log is too large",
+    );
+    let mut header = [0; HEADER_SIZE];
+    outboard.read_exact(&mut header)?;
+    output.write_all(&header)?;
+    let num_chunks = encode::count_chunks(crate::decode_len(&header));
+    coarsen_subtree(&mut outboard, &mut output, num_chunks, 1 << chunk_group_log)
+}
+
+// The full outboard has a parent node for every subtree of more than one chunk, in pre-order.
+// The coarse one only has the ones for subtrees of more than one group.
+fn coarsen_subtree(
+    outboard: &mut impl Read,
+    output: &mut impl Write,
+    num_chunks: u64,
+    group_chunks: u64,
+) -> io::Result<()> {
+    if num_chunks == 1 {
+        return Ok(());
+    }
+    let mut parent = [0; PARENT_SIZE];
+    outboard.read_exact(&mut parent)?;
+    if num_chunks > group_chunks {
+        output.write_all(&parent)?;
+    }
+    let left_chunks = encode::left_subtree_chunks(num_chunks);
+    coarsen_subtree(outboard, output, left_chunks, group_chunks)?;
+    coarsen_subtree(outboard, output, num_chunks - left_chunks, group_chunks)
+}
+
+/// Rebuild the full outboard encoding of `input` from the outboard encoding for chunk groups of
+/// 2^`chunk_group_log` chunks, like the output of [`coarsen_outboard`](fn.coarsen_outboard.html),
+/// and return the root hash. The parent nodes of the coarse outboard are copied to `output`, and
+/// the ones below them are computed from the input, one chunk group at a time.
+///
+/// Every coarse parent node is checked against the subtrees of the input below it, and if one
+/// doesn't match, this returns an error of kind `InvalidData`, as does input or coarse outboard
+/// bytes past the end given by the length header. If `input` or `coarse` is truncated, this
+/// returns an error of kind `UnexpectedEof`. In any of these cases, `output` is left incomplete
+/// or unverified. Note that the coarse outboard has no parent nodes when the input is a single
+/// chunk group, so compare the returned hash to the expected one.
+///
+/// # Panics
+///
+/// Panics if `chunk_group_log` is greater than
+/// [`MAX_CHUNK_GROUP_LOG`](../encode/constant.MAX_CHUNK_GROUP_LOG.html).
+pub fn refine_outboard(
+    mut input: impl Read,
+    mut coarse: impl Read,
+    mut output: impl Write,
+    chunk_group_log: u8,
+) -> io::Result<Hash> {
+    assert!(
+        chunk_group_log <= encode::MAX_CHUNK_GROUP_LOG,
+        "chunk_group_log is too large",
+    );
+    let mut header = [0; HEADER_SIZE];
+    coarse.read_exact(&mut header)?;
+    output.write_all(&header)?;
+    let content_len = crate::decode_len(&header);
+    let mut refiner = Refiner {
+        input: &mut input,
+        coarse: &mut coarse,
+        output: &mut output,
+        content_len,
+        group_chunks: 1 << chunk_group_log,
+        group_buf: Vec::new(),
+        group_parents: Vec::new(),
+    };
+    let root = Subtree::root(content_len);
+    let hash = refiner.refine_subtree(root, Finalization::Root)?;
+    if !at_eof(&mut input)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "input is longer than the length header",
+        ));
+    }
+    if !at_eof(&mut coarse)? {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "trailing bytes after coarse outboard encoding",
+        ));
+    }
+    Ok(hash)
+}
+
+fn at_eof(reader: &mut impl Read) -> io::Result<bool> {
+    let mut byte = [0];
+    loop {
+        match reader.read(&mut byte) {
+            Ok(n) => return Ok(n == 0),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+struct Refiner<'a, I: Read, C: Read, O: Write> {
+    input: &'a mut I,
+    coarse: &'a mut C,
+    output: &'a mut O,
+    content_len: u64,
+    group_chunks: u64,
+    // Reused for each group.
+    group_buf: Vec<u8>,
+    group_parents: Vec<u8>,
+}
+
+impl<I: Read, C: Read, O: Write> Refiner<'_, I, C, O> {
+    // The parent_index of the subtree isn't used. Both the coarse and the full outboard are
+    // written in pre-order as we go.
+    fn refine_subtree(&mut self, subtree: Subtree, finalization: Finalization) -> io::Result<Hash> {
+        if subtree.num_chunks <= self.group_chunks {
+            return self.refine_group(subtree, finalization);
+        }
+        let mut parent = [0; PARENT_SIZE];
+        self.coarse.read_exact(&mut parent)?;
+        self.output.write_all(&parent)?;
+        let (left, right) = subtree.children();
+        let left_cv = self.refine_subtree(left, Finalization::NotRoot)?;
+        let right_cv = self.refine_subtree(right, Finalization::NotRoot)?;
+        // Hash implements constant time equality.
+        if (left_cv, right_cv) != crate::split_parent(&parent) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "input doesn't match the coarse outboard",
+            ));
+        }
+        Ok(crate::Mode::Hash.parent_cv(&left_cv, &right_cv, finalization.is_root()))
+    }
+
+    fn refine_group(&mut self, group: Subtree, finalization: Finalization) -> io::Result<Hash> {
+        let start = group.start_chunk * CHUNK_SIZE as u64;
+        let end = cmp::min(
+            start + group.num_chunks * CHUNK_SIZE as u64,
+            self.content_len,
+        );
+        self.group_buf.resize((end - start) as usize, 0);
+        self.input.read_exact(&mut self.group_buf)?;
+        self.group_parents.clear();
+        let cv = group_parents(
+            &self.group_buf,
+            group.start_chunk,
+            finalization,
+            &mut self.group_parents,
+        );
+        self.output.write_all(&self.group_parents)?;
+        Ok(cv)
+    }
+}
+
+// Append the parent nodes of the subtree of `input` starting at `start_chunk` to `parents` in
+// pre-order, and return its CV.
+pub(crate) fn group_parents(
+    input: &[u8],
+    start_chunk: u64,
+    finalization: Finalization,
+    parents: &mut Vec<u8>,
+) -> Hash {
+    if input.len() <= CHUNK_SIZE {
+        return crate::Mode::Hash.chunk_cv(start_chunk, input, finalization.is_root());
+    }
+    let left_chunks = encode::left_subtree_chunks(encode::count_chunks(input.len() as u64));
+    let left_len = left_chunks as usize * CHUNK_SIZE;
+    let parent_offset = parents.len();
+    parents.extend_from_slice(&[0; PARENT_SIZE]);
+    let left_cv = group_parents(
+        &input[..left_len],
+        start_chunk,
+        Finalization::NotRoot,
+        parents,
+    );
+    let right_cv = group_parents(
+        &input[left_len..],
+        start_chunk + left_chunks,
+        Finalization::NotRoot,
+        parents,
+    );
+    parents[parent_offset..][..HASH_SIZE].copy_from_slice(left_cv.as_bytes());
+    parents[parent_offset + HASH_SIZE..][..HASH_SIZE].copy_from_slice(right_cv.as_bytes());
+    crate::Mode::Hash.parent_cv(&left_cv, &right_cv, finalization.is_root())
+}
+
+// Read until the buffer is full or the reader hits EOF, and return the number of bytes read.
+fn read_chunk(reader: &mut impl Read, buf: &mut [u8; CHUNK_SIZE]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// A `Read + Seek` adapter that presents any `OutboardStore` as an outboard encoding in the usual
+/// pre-order layout. This is what lets the decoders and the `SliceExtractor` read from a store.
+#[derive(Clone, Debug)]
+pub struct OutboardReader<S: OutboardStore> {
+    store: S,
+    position: u64,
+    content_len: Option<u64>,
+    // The most recently read parent node, since reads of one parent may be split up.
+    last_parent: Option<(u64, ParentNode)>,
+}
+
+impl<S: OutboardStore> OutboardReader<S> {
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            position: 0,
+            content_len: None,
+            last_parent: None,
+        }
+    }
+
+    /// Return the underlying store.
+    pub fn into_inner(self) -> S {
+        self.store
+    }
+
+    fn content_len(&mut self) -> io::Result<u64> {
+        if let Some(content_len) = self.content_len {
+            return Ok(content_len);
+        }
+        let content_len = self.store.content_len()?;
+        self.content_len = Some(content_len);
+        Ok(content_len)
+    }
+}
+
+impl<S: OutboardStore> Read for OutboardReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let content_len = self.content_len()?;
+        let n;
+        if self.position < HEADER_SIZE as u64 {
+            let header = crate::encode_len(content_len);
+            let available = &header[self.position as usize..];
+            n = cmp::min(buf.len(), available.len());
+            buf[..n].copy_from_slice(&available[..n]);
+        } else {
+            let index = (self.position - HEADER_SIZE as u64) / PARENT_SIZE as u64;
+            if index >= encode::count_chunks(content_len) - 1 {
+                return Ok(0); // EOF
+            }
+            let parent = match self.last_parent {
+                Some((last_index, parent)) if last_index == index => parent,
+                _ => {
+                    let parent = self.store.parent(position_at_index(content_len, index))?;
+                    self.last_parent = Some((index, parent));
+                    parent
+                }
+            };
+            let offset = ((self.position - HEADER_SIZE as u64) % PARENT_SIZE as u64) as usize;
+            let available = &parent[offset..];
+            n = cmp::min(buf.len(), available.len());
+            buf[..n].copy_from_slice(&available[..n]);
+        }
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<S: OutboardStore> Seek for OutboardReader<S> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => {
+                self.position = n;
+                return Ok(n);
+            }
+            SeekFrom::Current(offset) => (self.position, offset),
+            SeekFrom::End(offset) => {
+                let size = encode::outboard_size(self.content_len()?);
+                (encode::cast_offset(size)?, offset)
+            }
+        };
+        let new_position = if offset >= 0 {
+            base.checked_add(offset as u64)
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        match new_position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek offset",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decode::{make_test_input, Decoder, SliceDecoder};
+    use crate::encode::SliceExtractor;
+    use rand::prelude::*;
+    use rand_chacha::ChaChaRng;
+    use std::io::Cursor;
+
+    // A store of parent nodes in a hash map, like a key-value store might hold them.
+    struct MapStore {
+        content_len: u64,
+        parents: HashMap<TreePosition, ParentNode>,
+    }
+
+    impl MapStore {
+        fn new(outboard: &[u8]) -> Self {
+            let mut outboard = outboard;
+            let content_len = outboard.content_len().unwrap();
+            let parents = parent_positions(content_len)
+                .map(|position| (position, outboard.parent(position).unwrap()))
+                .collect();
+            Self {
+                content_len,
+                parents,
+            }
+        }
+    }
+
+    impl OutboardStore for MapStore {
+        fn content_len(&mut self) -> io::Result<u64> {
+            Ok(self.content_len)
+        }
+
+        fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]> {
+            self.parents
+                .get(&position)
+                .copied()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "missing parent"))
+        }
+    }
+
+    #[test]
+    fn test_parent_positions() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let positions: Vec<_> = parent_positions(case as u64).collect();
+            assert_eq!(
+                encode::count_chunks(case as u64) - 1,
+                positions.len() as u64
+            );
+            for (index, &position) in positions.iter().enumerate() {
+                assert!(position.height > 0);
+                assert_eq!(
+                    index as u64,
+                    pre_order_index(case as u64, position).unwrap()
+                );
+            }
+            // Chunks aren't parent nodes, and neither is anything past the end.
+            for &position in &[
+                TreePosition {
+                    start_chunk: 0,
+                    height: 0,
+                },
+                TreePosition {
+                    start_chunk: encode::count_chunks(case as u64),
+                    height: 1,
+                },
+                TreePosition {
+                    start_chunk: 0,
+                    height: 63,
+                },
+            ] {
+                let err = pre_order_index(case as u64, position).unwrap_err();
+                assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+            }
+        }
+    }
+
+    #[test]
+    fn test_stores() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (outboard, hash) = encode::outboard(&input);
+
+            // All the stores should agree with the bytes of the outboard encoding.
+            let mut vec_store = outboard.clone();
+            let mut file_store = PreOrderOutboard::new(Cursor::new(&outboard));
+            let mut map_store = MapStore::new(&outboard);
+            let stores: [&mut dyn OutboardStore; 3] =
+                [&mut vec_store, &mut file_store, &mut map_store];
+            for store in stores {
+                assert_eq!(case as u64, store.content_len().unwrap());
+                for (index, position) in parent_positions(case as u64).enumerate() {
+                    let offset = HEADER_SIZE + index * PARENT_SIZE;
+                    assert_eq!(
+                        &outboard[offset..][..PARENT_SIZE],
+                        &store.parent(position).unwrap()[..]
+                    );
+                }
+            }
+
+            // Reading a store back should reproduce the outboard encoding.
+            let mut reader = OutboardReader::new(MapStore::new(&outboard));
+            let mut output = Vec::new();
+            reader.read_to_end(&mut output).unwrap();
+            assert_eq!(outboard, output);
+            assert_eq!(
+                outboard.len() as u64,
+                reader.seek(SeekFrom::End(0)).unwrap()
+            );
+            reader.seek(SeekFrom::End(-1)).unwrap();
+            output.clear();
+            reader.read_to_end(&mut output).unwrap();
+            assert_eq!(&outboard[outboard.len() - 1..], &*output);
+
+            // Decode from a store.
+            let mut decoder = Decoder::new_outboard_store(&*input, MapStore::new(&outboard), &hash);
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(input, output);
+
+            // Extract a slice from a store.
+            let slice_start = case as u64 / 3;
+            let slice_len = 2 * CHUNK_SIZE as u64;
+            let mut expected = Vec::new();
+            SliceExtractor::new_outboard(
+                Cursor::new(&input),
+                Cursor::new(&outboard),
+                slice_start,
+                slice_len,
+            )
+            .read_to_end(&mut expected)
+            .unwrap();
+            let mut slice = Vec::new();
+            SliceExtractor::new_outboard_store(
+                Cursor::new(&input),
+                MapStore::new(&outboard),
+                slice_start,
+                slice_len,
+            )
+            .read_to_end(&mut slice)
+            .unwrap();
+            assert_eq!(expected, slice);
+        }
+    }
+
+    #[test]
+    fn test_post_order() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (outboard, hash) = encode::outboard(&input);
+            let mut post_order = Vec::new();
+            let mut encoder =
+                encode::Encoder::new_outboard_post_order(Cursor::new(&mut post_order));
+            encoder.write_all(&input).unwrap();
+            assert_eq!(hash, encoder.finalize().unwrap());
+            assert_eq!(outboard.len(), post_order.len());
+            assert_eq!(
+                outboard[..HEADER_SIZE],
+                post_order[post_order.len() - HEADER_SIZE..]
+            );
+
+            // Every parent node is in its post-order place, and they fill the whole encoding.
+            let mut store = PostOrderOutboard::new(Cursor::new(&post_order));
+            assert_eq!(case as u64, store.content_len().unwrap());
+            let mut offsets = Vec::new();
+            for position in parent_positions(case as u64) {
+                assert_eq!(
+                    (&*outboard).parent(position).unwrap(),
+                    store.parent(position).unwrap()
+                );
+                offsets.push(post_order_index(case as u64, position).unwrap());
+            }
+            offsets.sort_unstable();
+            assert!(offsets.iter().copied().eq(0..offsets.len() as u64));
+
+            // Decode from the post-order encoding.
+            let store = PostOrderOutboard::new(Cursor::new(&post_order));
+            let mut decoder = Decoder::new_outboard_store(&*input, store, &hash);
+            let mut output = Vec::new();
+            decoder.read_to_end(&mut output).unwrap();
+            assert_eq!(input, output);
+
+            // Extract a slice from it.
+            let slice_start = case as u64 / 3;
+            let slice_len = 2 * CHUNK_SIZE as u64;
+            let mut expected = Vec::new();
+            SliceExtractor::new_outboard(
+                Cursor::new(&input),
+                Cursor::new(&outboard),
+                slice_start,
+                slice_len,
+            )
+            .read_to_end(&mut expected)
+            .unwrap();
+            let mut slice = Vec::new();
+            SliceExtractor::new_outboard_store(
+                Cursor::new(&input),
+                PostOrderOutboard::new(Cursor::new(&post_order)),
+                slice_start,
+                slice_len,
+            )
+            .read_to_end(&mut slice)
+            .unwrap();
+            assert_eq!(expected, slice);
+        }
+
+        // Post-order outboard encoders can be checkpointed too.
+        let input = make_test_input(10 * CHUNK_SIZE + 1);
+        let mut post_order = Cursor::new(Vec::new());
+        let mut encoder = encode::Encoder::new_outboard_post_order(&mut post_order);
+        encoder.write_all(&input[..5000]).unwrap();
+        let checkpoint =
+            encode::EncoderCheckpoint::deserialize(&encoder.checkpoint().unwrap().serialize())
+                .unwrap();
+        let mut encoder = encode::Encoder::resume(&mut post_order, &checkpoint).unwrap();
+        encoder.write_all(&input[5000..]).unwrap();
+        let hash = encoder.finalize().unwrap();
+        let store = PostOrderOutboard::new(Cursor::new(post_order.into_inner()));
+        let mut decoder = Decoder::new_outboard_store(&*input, store, &hash);
+        let mut output = Vec::new();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(input, output);
+
+        // A post-order encoding too short for a header is an EOF error.
+        let err = PostOrderOutboard::new(Cursor::new(&[0; HEADER_SIZE - 1]))
+            .content_len()
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn test_outboard_type() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (outboard_bytes, hash) = encode::outboard(&input);
+            let outboard = Outboard::new(&input);
+            assert_eq!(&outboard_bytes, outboard.as_bytes());
+            assert_eq!(&hash, outboard.hash());
+            assert_eq!(case as u64, outboard.content_len());
+
+            // Every chunk CV should match the chunk, and every parent CV should match its
+            // children.
+            let num_chunks = encode::count_chunks(case as u64);
+            let is_root = num_chunks == 1;
+            for chunk in 0..num_chunks {
+                let start = chunk as usize * CHUNK_SIZE;
+                let size = encode::chunk_size(chunk, case as u64);
+                let expected = crate::Mode::Hash.chunk_cv(chunk, &input[start..][..size], is_root);
+                let position = TreePosition {
+                    start_chunk: chunk,
+                    height: 0,
+                };
+                assert_eq!(Some(expected), outboard.cv(position));
+                assert_eq!(None, outboard.parent(position));
+            }
+            for (index, position) in parent_positions(case as u64).enumerate() {
+                let (left_cv, right_cv) = outboard.parent(position).unwrap();
+                let offset = HEADER_SIZE + index * PARENT_SIZE;
+                assert_eq!(left_cv.as_bytes(), &outboard_bytes[offset..][..HASH_SIZE]);
+                let expected = crate::Mode::Hash.parent_cv(&left_cv, &right_cv, index == 0);
+                assert_eq!(Some(expected), outboard.cv(position));
+            }
+            let past_the_end = TreePosition {
+                start_chunk: num_chunks,
+                height: 0,
+            };
+            assert_eq!(None, outboard.cv(past_the_end));
+
+            // Round trips.
+            let deserialized = Outboard::deserialize(&outboard.serialize()).unwrap();
+            assert_eq!(outboard.as_bytes(), deserialized.as_bytes());
+            assert_eq!(outboard.hash(), deserialized.hash());
+            let from_bytes = Outboard::from_bytes(outboard_bytes.clone(), &hash).unwrap();
+            assert_eq!(outboard.as_bytes(), from_bytes.as_bytes());
+
+            // The bytes work with the decoders and the slice extractor.
+            let mut output = Vec::new();
+            Decoder::new_outboard(&*input, outboard.as_bytes(), outboard.hash())
+                .read_to_end(&mut output)
+                .unwrap();
+            assert_eq!(input, output);
+            let mut output = Vec::new();
+            Decoder::new_outboard_store(&*input, &outboard, outboard.hash())
+                .read_to_end(&mut output)
+                .unwrap();
+            assert_eq!(input, output);
+            let slice_start = case as u64 / 3;
+            let slice_len = 2 * CHUNK_SIZE as u64;
+            let slice = outboard.slice(&input, slice_start, slice_len).unwrap();
+            let mut output = Vec::new();
+            SliceDecoder::new(&*slice, &hash, slice_start, slice_len)
+                .read_to_end(&mut output)
+                .unwrap();
+            let expected_end = cmp::min(case as u64, slice_start + slice_len) as usize;
+            assert_eq!(&input[slice_start as usize..expected_end], &*output);
+        }
+    }
+
+    #[test]
+    fn test_outboard_type_errors() {
+        let input = make_test_input(10 * CHUNK_SIZE);
+        let outboard = Outboard::new(&input);
+        let serialized = outboard.serialize();
+
+        // Every corrupt parent node should be caught, since each one is committed to by the one
+        // above it, and the root is committed to by the hash.
+        for index in 0..encode::count_chunks(input.len() as u64) as usize - 1 {
+            for &side in &[0, HASH_SIZE] {
+                let mut bad = serialized.clone();
+                bad[HASH_SIZE + HEADER_SIZE + index * PARENT_SIZE + side] ^= 1;
+                let err = Outboard::deserialize(&bad).unwrap_err();
+                assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            }
+        }
+
+        // A wrong hash is caught.
+        let mut bad = serialized.clone();
+        bad[0] ^= 1;
+        let err = Outboard::deserialize(&bad).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // So are the wrong sizes.
+        let err = Outboard::deserialize(&serialized[..serialized.len() - 1]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        let err = Outboard::deserialize(&serialized[..HASH_SIZE + 1]).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        let mut long = serialized.clone();
+        long.push(0);
+        let err = Outboard::deserialize(&long).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // A forged length with the same number of chunks has the same parent nodes, so it isn't
+        // caught until the final chunk is hashed.
+        let mut forged = outboard.as_bytes().to_vec();
+        forged[..HEADER_SIZE].copy_from_slice(&crate::encode_len(input.len() as u64 - 1));
+        let forged = Outboard::from_bytes(forged, outboard.hash()).unwrap();
+        assert_eq!(input.len() as u64 - 1, forged.content_len());
+        let mut decoder = crate::decode::Decoder::new_outboard(
+            &input[..input.len() - 1],
+            forged.as_bytes(),
+            forged.hash(),
+        );
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_extend() {
+        for &old_case in crate::test::TEST_CASES {
+            for &new_case in crate::test::TEST_CASES {
+                if new_case < old_case {
+                    continue;
+                }
+                println!("old case {} new case {}", old_case, new_case);
+                let input = make_test_input(new_case);
+                let mut outboard = Outboard::new(&input[..old_case]);
+                let hash = outboard.extend(Cursor::new(&input)).unwrap();
+                let expected = Outboard::new(&input);
+                assert_eq!(expected.hash(), &hash);
+                assert_eq!(expected.hash(), outboard.hash());
+                assert_eq!(new_case as u64, outboard.content_len());
+                assert_eq!(expected.as_bytes(), outboard.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn test_extend_errors() {
+        for &old_case in &[10, CHUNK_SIZE, 5 * CHUNK_SIZE + 1, 8 * CHUNK_SIZE] {
+            println!("old case {}", old_case);
+            let input = make_test_input(old_case + 1000);
+            let outboard = Outboard::new(&input[..old_case]);
+
+            // A shorter input is rejected.
+            let err = outboard
+                .clone()
+                .extend(Cursor::new(&input[..old_case - 1]))
+                .unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+            // A change to the old final chunk is caught. Changes before that aren't, since those
+            // chunks aren't read at all.
+            let mut bad_input = input.clone();
+            bad_input[old_case - 1] ^= 1;
+            let result = outboard.clone().extend(Cursor::new(&bad_input));
+            if old_case <= CHUNK_SIZE || old_case % CHUNK_SIZE != 0 {
+                assert_eq!(io::ErrorKind::InvalidData, result.unwrap_err().kind());
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn test_update() {
+        let mut prng = ChaChaRng::from_seed([0; 32]);
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let mut input = make_test_input(case);
+            let mut outboard = Outboard::new(&input);
+            let mut outboard_file = tempfile::tempfile().unwrap();
+            outboard_file.write_all(outboard.as_bytes()).unwrap();
+            for _ in 0..10 {
+                let start = prng.gen_range(0, case + 1);
+                let end = prng.gen_range(start, cmp::min(case, start + 3 * CHUNK_SIZE) + 1);
+                prng.fill(&mut input[start..end]);
+                let range = start as u64..end as u64;
+                let hash = outboard.update(&*input, range.clone()).unwrap();
+                let file_hash = update_outboard(&*input, &mut outboard_file, range).unwrap();
+                let expected = Outboard::new(&input);
+                assert_eq!(expected.hash(), &hash);
+                assert_eq!(expected.hash(), &file_hash);
+                assert_eq!(expected.hash(), outboard.hash());
+                assert_eq!(expected.as_bytes(), outboard.as_bytes());
+                let mut file_bytes = Vec::new();
+                outboard_file.seek(SeekFrom::Start(0)).unwrap();
+                outboard_file.read_to_end(&mut file_bytes).unwrap();
+                assert_eq!(expected.as_bytes(), &*file_bytes);
+            }
+
+            // An out of bounds range changes nothing.
+            let err = outboard.update(&*input, 0..case as u64 + 1).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+            assert_eq!(&blake3::hash(&input), outboard.hash());
+        }
+    }
+
+    // Input that fails to read past a certain offset.
+    struct FailingInput<'a> {
+        input: &'a [u8],
+        fail_at: u64,
+    }
+
+    impl ReadAt for FailingInput<'_> {
+        fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+            if offset + buf.len() as u64 > self.fail_at {
+                return Err(io::Error::other("read failed"));
+            }
+            self.input.read_at(buf, offset)
+        }
+    }
+
+    #[test]
+    fn test_update_errors() {
+        let case = 10 * CHUNK_SIZE;
+        let mut input = make_test_input(case);
+        let outboard = Outboard::new(&input);
+        input[..5 * CHUNK_SIZE].iter_mut().for_each(|b| *b ^= 1);
+
+        // Inputs that are too short or too long are rejected up front.
+        for len in [case - 1, case + 1] {
+            let mut other = input.clone();
+            other.resize(len, 0);
+            let mut updated = outboard.clone();
+            let err = updated.update(&*other, 0..1).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+            assert_eq!(outboard.as_bytes(), updated.as_bytes());
+            let mut file = Cursor::new(outboard.as_bytes().to_vec());
+            let err = update_outboard(&*other, &mut file, 0..1).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+            assert_eq!(outboard.as_bytes(), &**file.get_ref());
+        }
+
+        // A read error after some parent nodes have been recomputed leaves everything unchanged.
+        let failing = FailingInput {
+            input: &input,
+            fail_at: 4 * CHUNK_SIZE as u64,
+        };
+        let range = 0..5 * CHUNK_SIZE as u64;
+        let mut updated = outboard.clone();
+        let err = updated.update(&failing, range.clone()).unwrap_err();
+        assert_eq!(io::ErrorKind::Other, err.kind());
+        assert_eq!(outboard.as_bytes(), updated.as_bytes());
+        assert_eq!(outboard.hash(), updated.hash());
+        let mut file = Cursor::new(outboard.as_bytes().to_vec());
+        let err = update_outboard(&failing, &mut file, range).unwrap_err();
+        assert_eq!(io::ErrorKind::Other, err.kind());
+        assert_eq!(outboard.as_bytes(), &**file.get_ref());
+    }
+
+    #[test]
+    fn test_store_errors() {
+        let input = make_test_input(10 * CHUNK_SIZE);
+        let (outboard, hash) = encode::outboard(&input);
+
+        // A missing parent node is passed along to the caller.
+        let mut store = MapStore::new(&outboard);
+        let position = parent_positions(input.len() as u64).nth(3).unwrap();
+        store.parents.remove(&position);
+        let mut decoder = Decoder::new_outboard_store(&*input, store, &hash);
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+
+        // A corrupt parent node fails verification.
+        let mut store = MapStore::new(&outboard);
+        store.parents.get_mut(&position).unwrap()[0] ^= 1;
+        let mut decoder = Decoder::new_outboard_store(&*input, store, &hash);
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // A truncated in-memory store is an EOF error.
+        let mut truncated = &outboard[..outboard.len() - 1];
+        let last_position = parent_positions(input.len() as u64).last().unwrap();
+        let err = truncated.parent(last_position).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        let err = (&outboard[..HEADER_SIZE - 1]).content_len().unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn test_coarsen_refine() {
+        for &case in crate::test::TEST_CASES {
+            let input = make_test_input(case);
+            let (full, hash) = encode::outboard(&input);
+            for &group_log in &[0, 1, 3] {
+                println!("case {} group_log {}", case, group_log);
+                let mut coarse = Vec::new();
+                coarsen_outboard(&*full, &mut coarse, group_log).unwrap();
+                let mut expected = Cursor::new(Vec::new());
+                let mut encoder = encode::Encoder::new_outboard_grouped(&mut expected, group_log);
+                encoder.write_all(&input).unwrap();
+                encoder.finalize().unwrap();
+                assert_eq!(expected.into_inner(), coarse);
+
+                let mut refined = Vec::new();
+                let refined_hash =
+                    refine_outboard(&*input, &*coarse, &mut refined, group_log).unwrap();
+                assert_eq!(hash, refined_hash);
+                assert_eq!(full, refined);
+            }
+        }
+    }
+
+    #[test]
+    fn test_refine_errors() {
+        let group_log = 2;
+        let input = make_test_input(9 * CHUNK_SIZE + 1);
+        let (full, hash) = encode::outboard(&input);
+        let mut coarse = Vec::new();
+        coarsen_outboard(&*full, &mut coarse, group_log).unwrap();
+
+        // Content that doesn't match a coarse parent node is an error.
+        for &tweak in &[0, 5 * CHUNK_SIZE, input.len() - 1] {
+            let mut bad_input = input.clone();
+            bad_input[tweak] ^= 1;
+            let err = refine_outboard(&*bad_input, &*coarse, Vec::new(), group_log).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+
+        // So is a coarse parent node that doesn't match the content.
+        for tweak in (HEADER_SIZE..coarse.len()).step_by(HASH_SIZE) {
+            let mut bad_coarse = coarse.clone();
+            bad_coarse[tweak] ^= 1;
+            let err = refine_outboard(&*input, &*bad_coarse, Vec::new(), group_log).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+        let refined_hash = refine_outboard(&*input, &*coarse, Vec::new(), group_log).unwrap();
+        assert_eq!(hash, refined_hash);
+
+        // Truncated inputs are EOF errors.
+        let err = refine_outboard(&input[..input.len() - 1], &*coarse, Vec::new(), group_log)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+        let err = refine_outboard(&*input, &coarse[..coarse.len() - 1], Vec::new(), group_log)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+        // Trailing bytes in either are invalid.
+        let mut long_input = input.clone();
+        long_input.push(0);
+        let err = refine_outboard(&*long_input, &*coarse, Vec::new(), group_log).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let mut long_coarse = coarse.clone();
+        long_coarse.push(0);
+        let err = refine_outboard(&*input, &*long_coarse, Vec::new(), group_log).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        let err = coarsen_outboard(&full[..full.len() - 1], Vec::new(), group_log).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+    }
+
+    #[test]
+    fn test_diff_outboards() {
+        // Counts the parent nodes read from a store.
+        struct CountingStore<S> {
+            inner: S,
+            reads: u64,
+        }
+
+        impl<S: OutboardStore> OutboardStore for CountingStore<S> {
+            fn content_len(&mut self) -> io::Result<u64> {
+                self.inner.content_len()
+            }
+
+            fn parent(&mut self, position: TreePosition) -> io::Result<[u8; 64]> {
+                self.reads += 1;
+                self.inner.parent(position)
+            }
+        }
+
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (outboard, hash) = encode::outboard(&input);
+            let ranges = diff_outboards(&*outboard, &hash, &*outboard, &hash).unwrap();
+            assert!(ranges.is_empty());
+            if case == 0 {
+                continue;
+            }
+
+            // Change the first byte, a byte in the middle, and the last byte. The last chunk
+            // might be the same as the middle one, or next to it.
+            let mut changed = input.clone();
+            let offsets = [0, case / 2, case - 1];
+            for &offset in &offsets {
+                changed[offset] ^= 1;
+            }
+            let (changed_outboard, changed_hash) = encode::outboard(&changed);
+            let mut expected: Vec<Range<u64>> = Vec::new();
+            for &offset in &offsets {
+                let start = (offset / CHUNK_SIZE * CHUNK_SIZE) as u64;
+                let end = cmp::min(start + CHUNK_SIZE as u64, case as u64);
+                match expected.last_mut() {
+                    Some(last) if last.end >= start => last.end = end,
+                    _ => expected.push(start..end),
+                }
+            }
+            let mut store = CountingStore {
+                inner: MapStore::new(&changed_outboard),
+                reads: 0,
+            };
+            let ranges = diff_outboards(&*outboard, &hash, &mut store, &changed_hash).unwrap();
+            assert_eq!(expected, ranges);
+            let height = encode::subtree_height(encode::count_chunks(case as u64)) as u64;
+            assert!(store.reads <= offsets.len() as u64 * height);
+        }
+
+        let (short, short_hash) = encode::outboard(make_test_input(2 * CHUNK_SIZE));
+        let (long, long_hash) = encode::outboard(make_test_input(2 * CHUNK_SIZE + 1));
+        let err = diff_outboards(&*short, &short_hash, &*long, &long_hash).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+
+    #[test]
+    fn test_diff_outboards_tampered() {
+        let input = make_test_input(10 * CHUNK_SIZE + 1);
+        let (outboard, hash) = encode::outboard(&input);
+        let mut changed = input.clone();
+        changed[0] ^= 1;
+        let (changed_outboard, changed_hash) = encode::outboard(&changed);
+        let ranges = diff_outboards(&*outboard, &hash, &*changed_outboard, &changed_hash).unwrap();
+        assert_eq!(vec![0..CHUNK_SIZE as u64], ranges);
+
+        // Tamper with the root node, the lowest node above the changed chunk, and the lowest node
+        // on the right edge, which covers chunks 8 to 10 and isn't needed for the diff itself.
+        // All of them are caught, on either side.
+        let right_edge = outboard.len() - 2 * PARENT_SIZE;
+        let lowest_left = HEADER_SIZE + (encode::subtree_height(11) as usize - 1) * PARENT_SIZE;
+        for &offset in &[HEADER_SIZE, lowest_left, right_edge] {
+            let mut tampered = changed_outboard.clone();
+            tampered[offset] ^= 1;
+            let err = diff_outboards(&*outboard, &hash, &*tampered, &changed_hash).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+            let err = diff_outboards(&*tampered, &changed_hash, &*outboard, &hash).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+
+        // A wrong root hash is caught too.
+        let err = diff_outboards(&*outboard, &hash, &*outboard, &changed_hash).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+}
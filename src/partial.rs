@@ -0,0 +1,509 @@
+//! Assemble a blob from verified slices, possibly from many sources.
+//!
+//! A [`PartialBlob`](struct.PartialBlob.html) holds whatever parts of a blob have been verified
+//! so far: the content bytes in a sparse content file, the verified parent nodes in a partial
+//! outboard encoding, and a bitmap of verified chunks. It accepts slices in the format produced by
+//! `SliceExtractor` and checked by `SliceDecoder`, reports which ranges are still missing, serves
+//! slices of the ranges it has, and turns into a complete input and
+//! [`Outboard`](../outboard/struct.Outboard.html) once everything is present.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use bao::partial::PartialBlob;
+//!
+//! let input = vec![0xab; 100_000];
+//! let (encoded, hash) = bao::encode::encode(&input);
+//! let extract = |start, len| -> std::io::Result<Vec<u8>> {
+//!     let mut slice = Vec::new();
+//!     let cursor = std::io::Cursor::new(&encoded);
+//!     let mut extractor = bao::encode::SliceExtractor::new(cursor, start, len);
+//!     std::io::copy(&mut extractor, &mut slice)?;
+//!     Ok(slice)
+//! };
+//!
+//! // Put the second half of the blob in place first.
+//! let mut blob = PartialBlob::new(Vec::new(), &hash, input.len() as u64);
+//! blob.add_slice(&*extract(50_000, 50_000)?, 50_000, 50_000)?;
+//! assert_eq!(vec![0..49_152], blob.missing_ranges());
+//!
+//! // Then fill in the rest.
+//! blob.add_slice(&*extract(0, 50_000)?, 0, 50_000)?;
+//! assert!(blob.is_complete());
+//! let (content, outboard) = blob.into_complete().unwrap();
+//! assert_eq!(input, content);
+//! assert_eq!(&hash, outboard.hash());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::decode::{self, ReadAt, VerifiedTree};
+use crate::encode;
+use crate::outboard::{Outboard, Subtree};
+use crate::{Finalization, Hash, Mode, ParentNode, CHUNK_SIZE, HEADER_SIZE, PARENT_SIZE};
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::ops::Range;
+
+/// Positional writes, the counterpart of [`ReadAt`](../decode/trait.ReadAt.html). This is
+/// implemented for `File` on Unix and Windows, where writing past the end leaves a sparse hole,
+/// and for `Vec<u8>`, which grows as needed.
+pub trait WriteAt {
+    /// Write all of `buf` starting at `offset`.
+    fn write_all_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()>;
+}
+
+impl WriteAt for Vec<u8> {
+    fn write_all_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        let end = offset as usize + buf.len();
+        if self.len() < end {
+            self.resize(end, 0);
+        }
+        self[offset as usize..end].copy_from_slice(buf);
+        Ok(())
+    }
+}
+
+#[cfg(any(unix, windows))]
+impl WriteAt for std::fs::File {
+    fn write_all_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        decode::write_all_at(self, buf, offset)
+    }
+}
+
+impl<T: WriteAt + ?Sized> WriteAt for &mut T {
+    fn write_all_at(&mut self, buf: &[u8], offset: u64) -> io::Result<()> {
+        (**self).write_all_at(buf, offset)
+    }
+}
+
+/// A blob that's being assembled from verified slices.
+///
+/// The content length is given up front, typically by whoever provided the hash. Like the length
+/// header of an encoding, it isn't trusted until the final chunk is verified, and every slice
+/// must have a length header that matches it. Slices are verified as they're read, and each chunk
+/// is written to the content storage only after it's verified, so the content never contains
+/// unverified bytes in a chunk marked as verified. If a slice fails verification partway through,
+/// the chunks before the failure are kept.
+///
+/// The verified parent nodes are kept in memory, about 1/16th of the size of the content verified
+/// so far. Nothing is allocated up front based on the content length, so a forged length doesn't
+/// cost anything until slices are verified against it.
+pub struct PartialBlob<T> {
+    content: T,
+    hash: Hash,
+    content_len: u64,
+    // The verified parent nodes, by pre-order index.
+    parents: BTreeMap<u64, ParentNode>,
+    // One bit per chunk, in words of 64 chunks, set when the chunk and all its parent nodes have
+    // been verified. Words with no bits set are left out.
+    verified_chunks: BTreeMap<u64, u64>,
+    verified_count: u64,
+}
+
+impl<T> PartialBlob<T> {
+    /// Create an empty `PartialBlob`, with the content stored in `content`. Anything already in
+    /// `content` is ignored and eventually overwritten.
+    pub fn new(content: T, hash: &Hash, content_len: u64) -> Self {
+        Self {
+            content,
+            hash: *hash,
+            content_len,
+            parents: BTreeMap::new(),
+            verified_chunks: BTreeMap::new(),
+            verified_count: 0,
+        }
+    }
+
+    /// The root hash.
+    pub fn hash(&self) -> &Hash {
+        &self.hash
+    }
+
+    /// The content length given to `new`. This is verified once the final chunk is present.
+    pub fn content_len(&self) -> u64 {
+        self.content_len
+    }
+
+    /// Return `true` if every chunk has been verified.
+    pub fn is_complete(&self) -> bool {
+        self.verified_count == encode::count_chunks(self.content_len)
+    }
+
+    /// Return the ranges of content bytes that haven't been verified yet, in order. Adjacent
+    /// missing chunks are merged into one range. For empty content, the single empty chunk is
+    /// reported as `0..0` until it's verified.
+    pub fn missing_ranges(&self) -> Vec<Range<u64>> {
+        // Walk the verified chunks rather than all of them, so that this is proportional to what's
+        // been verified, and the gaps between them are the missing ranges.
+        let count = encode::count_chunks(self.content_len);
+        let chunk_start = |chunk: u64| {
+            if chunk == count {
+                self.content_len
+            } else {
+                chunk * CHUNK_SIZE as u64
+            }
+        };
+        let mut ranges = Vec::new();
+        let mut next_missing = 0;
+        for (&word_index, &word) in &self.verified_chunks {
+            for bit in 0..64 {
+                let chunk = word_index * 64 + bit;
+                if word & (1 << bit) == 0 {
+                    continue;
+                }
+                if next_missing < chunk {
+                    ranges.push(chunk_start(next_missing)..chunk_start(chunk));
+                }
+                next_missing = chunk + 1;
+            }
+        }
+        if next_missing < count {
+            ranges.push(chunk_start(next_missing)..chunk_start(count));
+        }
+        ranges
+    }
+
+    /// Return `true` if a slice with these parameters can be served from what's already been
+    /// verified.
+    pub fn has_slice(&self, slice_start: u64, slice_len: u64) -> bool {
+        slice_chunks(self.content_len, slice_start, slice_len).all(|c| self.chunk_is_verified(c))
+    }
+
+    /// If every chunk has been verified, return the content storage and the complete outboard
+    /// encoding. Otherwise return the `PartialBlob` unchanged.
+    pub fn into_complete(self) -> Result<(T, Outboard), Self> {
+        if !self.is_complete() {
+            return Err(self);
+        }
+        // Every parent node is above some chunk, so they've all been verified too.
+        let mut bytes = Vec::with_capacity(encode::outboard_size(self.content_len) as usize);
+        bytes.extend_from_slice(&crate::encode_len(self.content_len));
+        for parent in self.parents.values() {
+            bytes.extend_from_slice(parent);
+        }
+        let outboard = Outboard::from_verified_parts(bytes, &self.hash, self.content_len);
+        Ok((self.content, outboard))
+    }
+
+    fn chunk_is_verified(&self, chunk: u64) -> bool {
+        let word = self
+            .verified_chunks
+            .get(&(chunk / 64))
+            .copied()
+            .unwrap_or(0);
+        word & (1 << (chunk % 64)) != 0
+    }
+
+    fn set_chunk_verified(&mut self, chunk: u64) {
+        let word = self.verified_chunks.entry(chunk / 64).or_insert(0);
+        if *word & (1 << (chunk % 64)) == 0 {
+            *word |= 1 << (chunk % 64);
+            self.verified_count += 1;
+        }
+    }
+
+    fn root(&self) -> Subtree {
+        Subtree::root(self.content_len)
+    }
+}
+
+impl<T: WriteAt> PartialBlob<T> {
+    /// Read a slice, as produced by `SliceExtractor` with the same `slice_start` and `slice_len`,
+    /// and verify it. Verified chunks are written to the content storage, and verified parent
+    /// nodes to the partial outboard. Verification failures are returned as `InvalidData`, and
+    /// a short slice as `UnexpectedEof`, like `SliceDecoder`.
+    pub fn add_slice(
+        &mut self,
+        mut slice: impl Read,
+        slice_start: u64,
+        slice_len: u64,
+    ) -> io::Result<()> {
+        let mut header = [0; HEADER_SIZE];
+        slice.read_exact(&mut header)?;
+        if crate::decode_len(&header) != self.content_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "slice length header doesn't match",
+            ));
+        }
+        let chunks = slice_chunks(self.content_len, slice_start, slice_len);
+        let (hash, content_len, root) = (self.hash, self.content_len, self.root());
+        let mut tree = SliceTree { blob: self, slice };
+        decode::verify_subtrees(
+            &mut tree,
+            &Mode::Hash,
+            content_len,
+            root,
+            &hash,
+            Finalization::Root,
+            &chunks,
+        )
+    }
+}
+
+// A PartialBlob, and a slice being added to it. Verified parent nodes go into the partial
+// outboard, and verified chunks that are new go into the content storage.
+struct SliceTree<'a, T, R> {
+    blob: &'a mut PartialBlob<T>,
+    slice: R,
+}
+
+impl<T: WriteAt, R: Read> VerifiedTree for SliceTree<'_, T, R> {
+    fn read_parent(&mut self, _subtree: Subtree) -> io::Result<[u8; PARENT_SIZE]> {
+        let mut parent = [0; PARENT_SIZE];
+        self.slice.read_exact(&mut parent)?;
+        Ok(parent)
+    }
+
+    fn verified_parent(&mut self, subtree: Subtree, parent: &[u8; PARENT_SIZE]) {
+        self.blob.parents.insert(subtree.parent_index, *parent);
+    }
+
+    fn read_chunk(&mut self, _subtree: Subtree, buf: &mut [u8]) -> io::Result<()> {
+        self.slice.read_exact(buf)
+    }
+
+    fn verified_chunk(&mut self, subtree: Subtree, chunk: &[u8]) -> io::Result<()> {
+        if !self.blob.chunk_is_verified(subtree.start_chunk) {
+            let offset = subtree.start_chunk * CHUNK_SIZE as u64;
+            self.blob.content.write_all_at(chunk, offset)?;
+            self.blob.set_chunk_verified(subtree.start_chunk);
+        }
+        Ok(())
+    }
+}
+
+impl<T: ReadAt> PartialBlob<T> {
+    /// Write a slice with the given parameters to `output`, in the same format as
+    /// `SliceExtractor`. If any of the chunks it needs haven't been verified yet, this returns an
+    /// error of kind `NotFound` without writing anything.
+    ///
+    /// The content isn't re-hashed on the way out, so the recipient should verify the slice as
+    /// usual.
+    pub fn write_slice(
+        &self,
+        slice_start: u64,
+        slice_len: u64,
+        mut output: impl Write,
+    ) -> io::Result<()> {
+        if !self.has_slice(slice_start, slice_len) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "slice hasn't been verified yet",
+            ));
+        }
+        output.write_all(&crate::encode_len(self.content_len))?;
+        let chunks = slice_chunks(self.content_len, slice_start, slice_len);
+        self.write_subtree(&mut output, self.root(), &chunks)
+    }
+
+    fn write_subtree(
+        &self,
+        output: &mut impl Write,
+        subtree: Subtree,
+        chunks: &Range<u64>,
+    ) -> io::Result<()> {
+        if !subtree.overlaps(chunks) {
+            return Ok(());
+        }
+        if subtree.num_chunks == 1 {
+            let size = encode::chunk_size(subtree.start_chunk, self.content_len);
+            let mut chunk = [0; CHUNK_SIZE];
+            let offset = subtree.start_chunk * CHUNK_SIZE as u64;
+            self.content.read_exact_at(&mut chunk[..size], offset)?;
+            return output.write_all(&chunk[..size]);
+        }
+        // Every parent node above a verified chunk has been verified.
+        output.write_all(&self.parents[&subtree.parent_index])?;
+        let (left, right) = subtree.children();
+        self.write_subtree(output, left, chunks)?;
+        self.write_subtree(output, right, chunks)
+    }
+}
+
+impl<T> fmt::Debug for PartialBlob<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing hashes, they might be secret.
+        write!(
+            f,
+            "PartialBlob {{ content_len: {}, complete: {}, ... }}",
+            self.content_len,
+            self.is_complete(),
+        )
+    }
+}
+
+// The chunks that a slice covers. Like SliceExtractor, this always covers at least one byte, and
+// a slice that starts at or past the end covers the final chunk.
+fn slice_chunks(content_len: u64, slice_start: u64, slice_len: u64) -> Range<u64> {
+    let total_chunks = encode::count_chunks(content_len);
+    if slice_start >= content_len {
+        return total_chunks - 1..total_chunks;
+    }
+    let slice_end = cmp::min(
+        slice_start.saturating_add(cmp::max(slice_len, 1)),
+        content_len,
+    );
+    slice_start / CHUNK_SIZE as u64..(slice_end - 1) / CHUNK_SIZE as u64 + 1
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decode::{make_test_input, SliceDecoder};
+    use crate::encode::SliceExtractor;
+    use rand::prelude::*;
+    use rand_chacha::ChaChaRng;
+    use std::io::Cursor;
+
+    fn extract(encoded: &[u8], slice_start: u64, slice_len: u64) -> Vec<u8> {
+        let mut slice = Vec::new();
+        SliceExtractor::new(Cursor::new(encoded), slice_start, slice_len)
+            .read_to_end(&mut slice)
+            .unwrap();
+        slice
+    }
+
+    #[test]
+    fn test_partial_blob() {
+        let mut prng = ChaChaRng::from_seed([0; 32]);
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (encoded, hash) = encode::encode(&input);
+            let (outboard, _) = encode::outboard(&input);
+            let mut blob = PartialBlob::new(Vec::new(), &hash, case as u64);
+            assert!(!blob.is_complete());
+            let expected_missing = if case == 0 { 0..0 } else { 0..case as u64 };
+            assert_eq!(vec![expected_missing], blob.missing_ranges());
+
+            // Add random slices until the blob is complete, checking the missing ranges and
+            // serving slices along the way.
+            let mut verified = vec![false; encode::count_chunks(case as u64) as usize];
+            while !blob.is_complete() {
+                let start = prng.gen_range(0, case as u64 + 1);
+                let len = prng.gen_range(0, 3 * CHUNK_SIZE as u64);
+                blob.add_slice(&*extract(&encoded, start, len), start, len)
+                    .unwrap();
+                for chunk in slice_chunks(case as u64, start, len) {
+                    verified[chunk as usize] = true;
+                }
+                let missing: Vec<u64> = blob
+                    .missing_ranges()
+                    .iter()
+                    .flat_map(|range| {
+                        let first = range.start / CHUNK_SIZE as u64;
+                        let end = cmp::max(first + 1, range.end.div_ceil(CHUNK_SIZE as u64));
+                        first..end
+                    })
+                    .collect();
+                let expected: Vec<u64> = (0..verified.len() as u64)
+                    .filter(|&c| !verified[c as usize])
+                    .collect();
+                assert_eq!(expected, missing);
+
+                // The same slice can be served back out, and it decodes.
+                let mut served = Vec::new();
+                blob.write_slice(start, len, &mut served).unwrap();
+                assert_eq!(extract(&encoded, start, len), served);
+                let mut output = Vec::new();
+                SliceDecoder::new(&*served, &hash, start, len)
+                    .read_to_end(&mut output)
+                    .unwrap();
+            }
+            assert!(blob.missing_ranges().is_empty());
+            let mut served = Vec::new();
+            blob.write_slice(0, case as u64, &mut served).unwrap();
+            assert_eq!(encoded, served);
+            // Check the edge cases of slice parameters against SliceExtractor.
+            let case = case as u64;
+            for &(start, len) in &[(0, 0), (1, 0), (case, 0), (case, 1), (case + 1, 5)] {
+                let mut served = Vec::new();
+                blob.write_slice(start, len, &mut served).unwrap();
+                assert_eq!(extract(&encoded, start, len), served);
+            }
+            let (content, complete_outboard) = blob.into_complete().unwrap();
+            assert_eq!(input, content);
+            assert_eq!(&outboard, complete_outboard.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_partial_blob_errors() {
+        let case = 10 * CHUNK_SIZE;
+        let input = make_test_input(case);
+        let (encoded, hash) = encode::encode(&input);
+        let mut blob = PartialBlob::new(Vec::new(), &hash, case as u64);
+
+        // Slices that haven't been verified can't be served.
+        let err = blob.write_slice(0, 1, &mut Vec::new()).unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+
+        // A corrupt chunk in the middle of a slice keeps the chunks before it.
+        let mut slice = extract(&encoded, 0, 3 * CHUNK_SIZE as u64);
+        let last = slice.len() - 1;
+        slice[last] ^= 1;
+        let err = blob
+            .add_slice(&*slice, 0, 3 * CHUNK_SIZE as u64)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(
+            vec![2 * CHUNK_SIZE as u64..case as u64],
+            blob.missing_ranges()
+        );
+
+        // A truncated slice is an EOF error.
+        let slice = extract(&encoded, 5 * CHUNK_SIZE as u64, 1);
+        let err = blob
+            .add_slice(&slice[..slice.len() - 1], 5 * CHUNK_SIZE as u64, 1)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+        // A slice with the wrong length header is rejected.
+        let (other_encoded, _) = encode::encode(&input[..case - 1]);
+        let slice = extract(&other_encoded, 0, 1);
+        let err = blob.add_slice(&*slice, 0, 1).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // An incomplete blob gives itself back.
+        let blob = blob.into_complete().unwrap_err();
+        assert!(!blob.is_complete());
+
+        // A forged length doesn't allocate anything up front, and slices of the real content
+        // don't verify against it.
+        let mut blob = PartialBlob::new(Vec::new(), &hash, u64::MAX);
+        assert_eq!(vec![0..u64::MAX], blob.missing_ranges());
+        assert!(!blob.has_slice(0, 1));
+        let slice = extract(&encoded, 0, 1);
+        let err = blob.add_slice(&*slice, 0, 1).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        let mut forged = slice.clone();
+        forged[..HEADER_SIZE].copy_from_slice(&crate::encode_len(u64::MAX));
+        let err = blob.add_slice(&*forged, 0, 1).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(!blob.is_complete());
+    }
+
+    #[test]
+    fn test_partial_blob_file() {
+        let case = 100 * CHUNK_SIZE + 1;
+        let input = make_test_input(case);
+        let (encoded, hash) = encode::encode(&input);
+        let mut blob = PartialBlob::new(tempfile::tempfile().unwrap(), &hash, case as u64);
+        // Fill the blob from the end, so that the file starts out sparse.
+        for chunk in (0..101).rev() {
+            let start = chunk * CHUNK_SIZE as u64;
+            let slice = extract(&encoded, start, CHUNK_SIZE as u64);
+            blob.add_slice(&*slice, start, CHUNK_SIZE as u64).unwrap();
+        }
+        let (mut file, _) = blob.into_complete().unwrap();
+        let mut content = Vec::new();
+        file.read_to_end(&mut content).unwrap();
+        assert_eq!(input, content);
+    }
+}
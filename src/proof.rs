@@ -0,0 +1,731 @@
+//! Compact proofs that a range of content belongs to a root hash.
+//!
+//! A slice from `SliceExtractor` carries whole parent nodes, both child CVs of every parent on the
+//! way down to the range, even though the verifier computes one of the two itself. A
+//! [`RangeProof`](struct.RangeProof.html) holds only the CVs the verifier can't compute: one for
+//! each subtree next to the path that doesn't overlap the range. That's about half the size, and
+//! it doesn't include the content. The verifier hashes the content of the range, rebuilds the path
+//! to the root with the CVs from the proof, and compares the result to the root hash.
+//!
+//! As with slices, a proof covers every chunk that overlaps the requested range, and at least one
+//! chunk. A range starting at or past the end of the content covers the final chunk.
+//!
+//! For chunks that arrive one at a time and in any order, for example from several network peers,
+//! [`chunk_proof`](fn.chunk_proof.html) makes a proof for a single chunk, and a
+//! [`ChunkVerifier`](struct.ChunkVerifier.html) checks chunks against the root hash as they come
+//! in. The verifier remembers every CV it has verified, so later proofs only need to reach the
+//! nearest of those, rather than the root.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use bao::proof::RangeProof;
+//!
+//! let input = vec![0xab; 1_000_000];
+//! let (outboard, hash) = bao::encode::outboard(&input);
+//!
+//! // The prover makes a proof from the outboard encoding.
+//! let proof = RangeProof::new(&*outboard, 500_000..600_000)?;
+//! let proof_bytes = proof.serialize();
+//!
+//! // The verifier checks the covered content against the root hash.
+//! let proof = RangeProof::deserialize(&proof_bytes)?;
+//! let range = proof.content_range();
+//! proof.verify(&hash, &input[range.start as usize..range.end as usize])?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::decode;
+use crate::encode;
+use crate::outboard::{OutboardStore, Subtree, TreePosition};
+use crate::{Finalization, Hash, CHUNK_SIZE, HASH_SIZE, HEADER_SIZE};
+use arrayref::array_ref;
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io;
+use std::ops::Range;
+
+/// A compact proof that a range of chunks belongs to the content with a given root hash. See the
+/// [module documentation](index.html).
+#[derive(Clone, PartialEq, Eq)]
+pub struct RangeProof {
+    content_len: u64,
+    chunks: Range<u64>,
+    // The CVs of the subtrees beside the path that don't overlap the chunks, from left to right.
+    cvs: Vec<Hash>,
+}
+
+impl RangeProof {
+    /// Make a proof for the chunks overlapping `range` of the content, from an outboard encoding
+    /// of it. The parent nodes from the store aren't verified here. If any of them are wrong, the
+    /// proof will fail to verify.
+    pub fn new(mut outboard: impl OutboardStore, range: Range<u64>) -> io::Result<Self> {
+        let content_len = outboard.content_len()?;
+        let chunks = covered_chunks(content_len, range);
+        let mut cvs = Vec::new();
+        let root = Subtree::root(content_len);
+        if !covers(&chunks, &root) {
+            collect_cvs(&mut outboard, root, &chunks, &mut cvs)?;
+        }
+        Ok(Self {
+            content_len,
+            chunks,
+            cvs,
+        })
+    }
+
+    /// The length of the content, from the outboard encoding the proof was made from. Like the
+    /// length header of an encoding, this is only verified if the proof covers the final chunk.
+    pub fn content_len(&self) -> u64 {
+        self.content_len
+    }
+
+    /// The range of content bytes that the proof covers, which is what
+    /// [`verify`](#method.verify) takes. This is the requested range, extended to whole chunks.
+    pub fn content_range(&self) -> Range<u64> {
+        let start = self.chunks.start * CHUNK_SIZE as u64;
+        // The end chunk can be the final chunk of content near u64::MAX bytes long, so saturate.
+        let end = cmp::min(
+            self.chunks.end.saturating_mul(CHUNK_SIZE as u64),
+            self.content_len,
+        );
+        start..end
+    }
+
+    /// Check `content`, the bytes in [`content_range`](#method.content_range), against the root
+    /// hash. This returns an error of kind `InvalidInput` if `content` is the wrong length, and
+    /// an error of kind `InvalidData` if it doesn't match the hash.
+    pub fn verify(&self, hash: &Hash, content: &[u8]) -> io::Result<()> {
+        let range = self.content_range();
+        if content.len() as u64 != range.end - range.start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "content doesn't match the proof range",
+            ));
+        }
+        let mut verifier = Verifier {
+            chunks: &self.chunks,
+            content,
+            content_start: range.start,
+            cvs: self.cvs.iter(),
+        };
+        let root_cv = verifier.subtree_cv(Subtree::root(self.content_len), Finalization::Root);
+        // The CVs were all used up when the proof was deserialized, so this is only a check.
+        debug_assert_eq!(0, verifier.cvs.len());
+        // Hash implements constant time equality.
+        if &root_cv != hash {
+            return Err(decode::Error::HashMismatch.into());
+        }
+        Ok(())
+    }
+
+    /// Serialize the proof. The format is the 8-byte little-endian content length, the 8-byte
+    /// little-endian indexes of the first chunk and one past the last chunk, and then the 32-byte
+    /// CVs. The number of CVs follows from the rest.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(3 * HEADER_SIZE + self.cvs.len() * HASH_SIZE);
+        bytes.extend_from_slice(&crate::encode_len(self.content_len));
+        bytes.extend_from_slice(&self.chunks.start.to_le_bytes());
+        bytes.extend_from_slice(&self.chunks.end.to_le_bytes());
+        for cv in &self.cvs {
+            bytes.extend_from_slice(cv.as_bytes());
+        }
+        bytes
+    }
+
+    /// Deserialize the output of [`serialize`](#method.serialize). This returns an error of kind
+    /// `InvalidData` if the bytes aren't a well-formed proof.
+    pub fn deserialize(bytes: &[u8]) -> io::Result<Self> {
+        let mut bytes = crate::ByteReader::new(bytes, "invalid range proof");
+        let content_len = crate::decode_len(array_ref!(bytes.take(HEADER_SIZE)?, 0, HEADER_SIZE));
+        let start = u64::from_le_bytes(*array_ref!(bytes.take(8)?, 0, 8));
+        let end = u64::from_le_bytes(*array_ref!(bytes.take(8)?, 0, 8));
+        if start >= end || end > encode::count_chunks(content_len) {
+            return Err(bytes.invalid());
+        }
+        let chunks = start..end;
+        let root = Subtree::root(content_len);
+        let expected_cvs = if covers(&chunks, &root) {
+            0
+        } else {
+            count_cvs(root, &chunks)
+        };
+        // The count is at most a couple of CVs per level of the tree, so it can't overflow.
+        let mut cvs = Vec::with_capacity(expected_cvs as usize);
+        for _ in 0..expected_cvs {
+            cvs.push(bytes.hash()?);
+        }
+        bytes.finish()?;
+        Ok(Self {
+            content_len,
+            chunks,
+            cvs,
+        })
+    }
+}
+
+impl fmt::Debug for RangeProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing hashes, they might be secret.
+        write!(
+            f,
+            "RangeProof {{ content_len: {}, chunks: {:?}, cvs: {} }}",
+            self.content_len,
+            self.chunks,
+            self.cvs.len(),
+        )
+    }
+}
+
+/// Make a proof for a single chunk, from an outboard encoding of the content. The proof is the CVs
+/// of the subtrees beside the path from the chunk up to the root, from the bottom up. A
+/// [`ChunkVerifier`](struct.ChunkVerifier.html) that has already verified part of the tree needs
+/// only the first [`proof_len`](struct.ChunkVerifier.html#method.proof_len) of them, and the
+/// rest can be left off.
+///
+/// This returns an error of kind `InvalidInput` if `chunk_index` is past the final chunk. As with
+/// [`RangeProof::new`](struct.RangeProof.html#method.new), the parent nodes from the store aren't
+/// verified here.
+pub fn chunk_proof(mut outboard: impl OutboardStore, chunk_index: u64) -> io::Result<Vec<Hash>> {
+    let content_len = outboard.content_len()?;
+    if chunk_index >= encode::count_chunks(content_len) {
+        return Err(chunk_index_error());
+    }
+    let path = chunk_path(content_len, chunk_index);
+    let mut cvs = Vec::with_capacity(path.len() - 1);
+    for pair in path.windows(2).rev() {
+        let (parent, child) = (pair[0], pair[1]);
+        let parent_bytes = outboard.parent(parent.position())?;
+        let (left, _) = parent.children();
+        let sibling_offset = if child.start_chunk == left.start_chunk {
+            HASH_SIZE
+        } else {
+            0
+        };
+        cvs.push((*array_ref!(parent_bytes, sibling_offset, HASH_SIZE)).into());
+    }
+    Ok(cvs)
+}
+
+/// Verifies chunks of content against a root hash, in any order, using proofs from
+/// [`chunk_proof`](fn.chunk_proof.html).
+///
+/// Every CV that a successful verification checks, along the path to the root and beside it, is
+/// kept, and a later proof only needs to reach the nearest of them. After every chunk has been
+/// verified, chunks can be checked again without any proof at all. This costs memory in
+/// proportion to the number of chunks verified.
+///
+/// The content length given to the verifier is untrusted, like the length header of an encoding.
+/// It's verified along with the final chunk, and
+/// [`final_chunk_verified`](#method.final_chunk_verified) reports whether that has happened.
+///
+/// # Example
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// use bao::proof::{chunk_proof, ChunkVerifier};
+///
+/// let input = vec![0xab; 10 * 1024];
+/// let (outboard, hash) = bao::encode::outboard(&input);
+///
+/// let mut verifier = ChunkVerifier::new(&hash, input.len() as u64);
+/// for &chunk_index in &[9, 2, 5] {
+///     let chunk = &input[chunk_index as usize * 1024..][..1024];
+///     let mut proof = chunk_proof(&*outboard, chunk_index)?;
+///     // The verifier tells the prover how much of the proof it still needs.
+///     proof.truncate(verifier.proof_len(chunk_index));
+///     verifier.verify_chunk(chunk_index, chunk, &proof)?;
+/// }
+/// assert!(verifier.final_chunk_verified());
+/// // Chunk 3 was in the proof for chunk 2, so it doesn't need a proof of its own.
+/// assert_eq!(0, verifier.proof_len(3));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ChunkVerifier {
+    hash: Hash,
+    content_len: u64,
+    // Verified non-root CVs, including single chunks. Sibling CVs from proofs are in here too,
+    // so this only says how far proofs need to go, not which chunks have been seen.
+    verified: HashMap<TreePosition, Hash>,
+    // Chunks verified from their own bytes.
+    verified_chunks: HashSet<u64>,
+    final_chunk_verified: bool,
+}
+
+impl ChunkVerifier {
+    /// Create a verifier for content with the given root hash and claimed length.
+    pub fn new(hash: &Hash, content_len: u64) -> Self {
+        Self {
+            hash: *hash,
+            content_len,
+            verified: HashMap::new(),
+            verified_chunks: HashSet::new(),
+            final_chunk_verified: false,
+        }
+    }
+
+    /// The content length the verifier was created with. This is only verified once
+    /// [`final_chunk_verified`](#method.final_chunk_verified) returns true.
+    pub fn content_len(&self) -> u64 {
+        self.content_len
+    }
+
+    /// Whether the final chunk, and with it the content length, has been verified.
+    pub fn final_chunk_verified(&self) -> bool {
+        self.final_chunk_verified
+    }
+
+    /// Whether the chunk at `chunk_index` has been passed to
+    /// [`verify_chunk`](#method.verify_chunk) and verified. A chunk whose CV only showed up as a
+    /// sibling in another chunk's proof doesn't count, because its content hasn't been seen.
+    pub fn is_verified(&self, chunk_index: u64) -> bool {
+        self.verified_chunks.contains(&chunk_index)
+    }
+
+    /// The number of CVs from the start of a [`chunk_proof`](fn.chunk_proof.html) that
+    /// [`verify_chunk`](#method.verify_chunk) needs for the chunk at `chunk_index`, given what's
+    /// already been verified.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_index` is past the final chunk.
+    pub fn proof_len(&self, chunk_index: u64) -> usize {
+        assert!(
+            chunk_index < encode::count_chunks(self.content_len),
+            "chunk index past the end"
+        );
+        let path = chunk_path(self.content_len, chunk_index);
+        path[1..]
+            .iter()
+            .rev()
+            .take_while(|&&subtree| !self.verified.contains_key(&subtree.position()))
+            .count()
+    }
+
+    /// Verify the chunk at `chunk_index` against the root hash. `proof` is a
+    /// [`chunk_proof`](fn.chunk_proof.html) for the chunk, which may be cut down to
+    /// [`proof_len`](#method.proof_len) CVs. Any CVs past that are ignored.
+    ///
+    /// This returns an error of kind `InvalidInput` if `chunk_index` is past the final chunk or
+    /// `chunk` is the wrong length, and an error of kind `InvalidData` if the chunk and the proof
+    /// don't match the hash, or the proof is too short. Nothing is remembered from a chunk that
+    /// fails to verify.
+    pub fn verify_chunk(
+        &mut self,
+        chunk_index: u64,
+        chunk: &[u8],
+        proof: &[Hash],
+    ) -> io::Result<()> {
+        if chunk_index >= encode::count_chunks(self.content_len) {
+            return Err(chunk_index_error());
+        }
+        if chunk.len() != encode::chunk_size(chunk_index, self.content_len) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "chunk is the wrong length",
+            ));
+        }
+        let path = chunk_path(self.content_len, chunk_index);
+        let mut proof = proof.iter();
+        let mut new_cvs = Vec::new();
+        let mut cv = crate::Mode::Hash.chunk_cv(chunk_index, chunk, path.len() == 1);
+        for depth in (1..path.len()).rev() {
+            let subtree = path[depth];
+            if let Some(verified_cv) = self.verified.get(&subtree.position()) {
+                // Hash implements constant time equality.
+                if &cv != verified_cv {
+                    return Err(decode::Error::HashMismatch.into());
+                }
+                self.add_verified(chunk_index, new_cvs);
+                return Ok(());
+            }
+            let sibling_cv = *proof.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "chunk proof too short")
+            })?;
+            let parent = path[depth - 1];
+            let (left, right) = parent.children();
+            let is_root = depth == 1;
+            new_cvs.push((subtree.position(), cv));
+            if subtree.start_chunk == left.start_chunk {
+                new_cvs.push((right.position(), sibling_cv));
+                cv = crate::Mode::Hash.parent_cv(&cv, &sibling_cv, is_root);
+            } else {
+                new_cvs.push((left.position(), sibling_cv));
+                cv = crate::Mode::Hash.parent_cv(&sibling_cv, &cv, is_root);
+            }
+        }
+        // Hash implements constant time equality.
+        if cv != self.hash {
+            return Err(decode::Error::HashMismatch.into());
+        }
+        self.add_verified(chunk_index, new_cvs);
+        Ok(())
+    }
+
+    fn add_verified(&mut self, chunk_index: u64, cvs: Vec<(TreePosition, Hash)>) {
+        self.verified.extend(cvs);
+        self.verified_chunks.insert(chunk_index);
+        if chunk_index == encode::count_chunks(self.content_len) - 1 {
+            self.final_chunk_verified = true;
+        }
+    }
+}
+
+impl fmt::Debug for ChunkVerifier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing hashes, they might be secret.
+        write!(
+            f,
+            "ChunkVerifier {{ content_len: {}, verified: {}, final_chunk_verified: {} }}",
+            self.content_len,
+            self.verified_chunks.len(),
+            self.final_chunk_verified,
+        )
+    }
+}
+
+fn chunk_index_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "chunk index past the end")
+}
+
+// The chunks a proof for `range` covers, following the same rules as slices.
+fn covered_chunks(content_len: u64, range: Range<u64>) -> Range<u64> {
+    let last_chunk = encode::count_chunks(content_len) - 1;
+    let start = cmp::min(range.start / CHUNK_SIZE as u64, last_chunk);
+    let end = if range.end > range.start {
+        cmp::min((range.end - 1) / CHUNK_SIZE as u64, last_chunk) + 1
+    } else {
+        start + 1
+    };
+    start..end
+}
+
+// The subtrees containing a chunk, from the root down to the chunk itself.
+fn chunk_path(content_len: u64, chunk_index: u64) -> Vec<Subtree> {
+    let mut path = vec![Subtree::root(content_len)];
+    loop {
+        let subtree = *path.last().unwrap();
+        if subtree.num_chunks == 1 {
+            return path;
+        }
+        let (left, right) = subtree.children();
+        path.push(if chunk_index < right.start_chunk {
+            left
+        } else {
+            right
+        });
+    }
+}
+
+fn covers(chunks: &Range<u64>, subtree: &Subtree) -> bool {
+    chunks.start <= subtree.start_chunk && subtree.start_chunk + subtree.num_chunks <= chunks.end
+}
+
+// Push the CVs needed for a subtree that partially overlaps the chunks.
+fn collect_cvs(
+    outboard: &mut impl OutboardStore,
+    subtree: Subtree,
+    chunks: &Range<u64>,
+    cvs: &mut Vec<Hash>,
+) -> io::Result<()> {
+    debug_assert!(subtree.num_chunks > 1);
+    let parent = outboard.parent(subtree.position())?;
+    let (left, right) = subtree.children();
+    for (child, cv) in [(left, 0), (right, HASH_SIZE)] {
+        if !child.overlaps(chunks) {
+            cvs.push((*array_ref!(parent, cv, HASH_SIZE)).into());
+        } else if !covers(chunks, &child) {
+            collect_cvs(outboard, child, chunks, cvs)?;
+        }
+    }
+    Ok(())
+}
+
+// The number of CVs collect_cvs pushes.
+fn count_cvs(subtree: Subtree, chunks: &Range<u64>) -> u64 {
+    let (left, right) = subtree.children();
+    let mut count = 0;
+    for child in [left, right] {
+        if !child.overlaps(chunks) {
+            count += 1;
+        } else if !covers(chunks, &child) {
+            count += count_cvs(child, chunks);
+        }
+    }
+    count
+}
+
+struct Verifier<'a> {
+    chunks: &'a Range<u64>,
+    content: &'a [u8],
+    content_start: u64,
+    cvs: std::slice::Iter<'a, Hash>,
+}
+
+impl Verifier<'_> {
+    // Compute the CV of a subtree that overlaps the chunks, from the content where it's covered,
+    // and from the proof where it isn't.
+    fn subtree_cv(&mut self, subtree: Subtree, finalization: Finalization) -> Hash {
+        if covers(self.chunks, &subtree) {
+            let start = (subtree.start_chunk * CHUNK_SIZE as u64 - self.content_start) as usize;
+            let len = cmp::min(
+                subtree.num_chunks as usize * CHUNK_SIZE,
+                self.content.len() - start,
+            );
+            let bytes = &self.content[start..][..len];
+            return crate::Mode::Hash.subtree_cv(
+                subtree.start_chunk,
+                bytes,
+                finalization.is_root(),
+            );
+        }
+        let (left, right) = subtree.children();
+        let mut child_cv = |child: Subtree| {
+            if child.overlaps(self.chunks) {
+                self.subtree_cv(child, Finalization::NotRoot)
+            } else {
+                *self
+                    .cvs
+                    .next()
+                    .expect("deserialize checked the number of CVs")
+            }
+        };
+        let left_cv = child_cv(left);
+        let right_cv = child_cv(right);
+        crate::Mode::Hash.parent_cv(&left_cv, &right_cv, finalization.is_root())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decode::make_test_input;
+    use crate::encode::SliceExtractor;
+    use std::io::prelude::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_range_proofs() {
+        for &case in crate::test::TEST_CASES {
+            let input = make_test_input(case);
+            let (outboard, hash) = encode::outboard(&input);
+            let ranges = [
+                (0, 0),
+                (0, case as u64),
+                (case as u64 / 3, case as u64 / 2),
+                (CHUNK_SIZE as u64, 1),
+                (case as u64, 1),
+                (case as u64 + 1, 10),
+            ];
+            for &(start, len) in &ranges {
+                println!("case {} start {} len {}", case, start, len);
+                let proof = RangeProof::new(&*outboard, start..start + len).unwrap();
+                let proof = RangeProof::deserialize(&proof.serialize()).unwrap();
+                assert_eq!(case as u64, proof.content_len());
+                let range = proof.content_range();
+                let content = &input[range.start as usize..range.end as usize];
+                proof.verify(&hash, content).unwrap();
+
+                // The proof covers the same chunks as a slice, with at most half the tree bytes.
+                let mut slice = Vec::new();
+                SliceExtractor::new_outboard(
+                    Cursor::new(&input),
+                    Cursor::new(&outboard),
+                    start,
+                    len,
+                )
+                .read_to_end(&mut slice)
+                .unwrap();
+                let slice_tree_len = slice.len() - HEADER_SIZE - content.len();
+                assert!(proof.cvs.len() * 2 * HASH_SIZE <= slice_tree_len);
+
+                // Any change to the content or the proof fails verification.
+                if !content.is_empty() {
+                    let mut bad_content = content.to_vec();
+                    *bad_content.last_mut().unwrap() ^= 1;
+                    let err = proof.verify(&hash, &bad_content).unwrap_err();
+                    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+                }
+                for i in 0..proof.cvs.len() {
+                    let mut bad_proof = proof.clone();
+                    let mut cv_bytes = *bad_proof.cvs[i].as_bytes();
+                    cv_bytes[0] ^= 1;
+                    bad_proof.cvs[i] = cv_bytes.into();
+                    let err = bad_proof.verify(&hash, content).unwrap_err();
+                    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+                }
+                if !content.is_empty() {
+                    let err = proof.verify(&hash, &content[1..]).unwrap_err();
+                    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_range_proof_deserialize_errors() {
+        let input = make_test_input(10 * CHUNK_SIZE);
+        let (outboard, _) = encode::outboard(&input);
+        let proof = RangeProof::new(&*outboard, 3000..5000).unwrap();
+        let bytes = proof.serialize();
+        assert_eq!(proof, RangeProof::deserialize(&bytes).unwrap());
+
+        let mut bad_inputs = vec![
+            bytes[..bytes.len() - 1].to_vec(),
+            bytes[..3 * HEADER_SIZE - 1].to_vec(),
+            [&bytes[..], &[0; HASH_SIZE]].concat(),
+        ];
+        // An empty chunk range, and one past the end.
+        let mut empty = bytes.clone();
+        empty[2 * HEADER_SIZE..][..8].copy_from_slice(&2u64.to_le_bytes());
+        bad_inputs.push(empty);
+        let mut past_end = bytes.clone();
+        past_end[2 * HEADER_SIZE..][..8].copy_from_slice(&11u64.to_le_bytes());
+        bad_inputs.push(past_end);
+        for bad in &bad_inputs {
+            let err = RangeProof::deserialize(bad).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+
+        // A valid proof for the final chunk of the longest possible content, which has 2^54
+        // chunks and a CV for every level above it, doesn't overflow the content range.
+        let mut longest = Vec::new();
+        longest.extend_from_slice(&crate::encode_len(u64::MAX));
+        longest.extend_from_slice(&((1u64 << 54) - 1).to_le_bytes());
+        longest.extend_from_slice(&(1u64 << 54).to_le_bytes());
+        longest.extend_from_slice(&[0; 54 * HASH_SIZE]);
+        let proof = RangeProof::deserialize(&longest).unwrap();
+        assert_eq!(
+            ((1 << 54) - 1) * CHUNK_SIZE as u64..u64::MAX,
+            proof.content_range()
+        );
+        let err = proof
+            .verify(&[0; HASH_SIZE].into(), &[0; CHUNK_SIZE - 1])
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn test_chunk_verifier() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let input = make_test_input(case);
+            let (outboard, hash) = encode::outboard(&input);
+            let num_chunks = encode::count_chunks(case as u64);
+            // Odd chunks going up, then even chunks going down.
+            let order = (0..num_chunks)
+                .filter(|i| i % 2 == 1)
+                .chain((0..num_chunks).rev().filter(|i| i % 2 == 0));
+            let mut verifier = ChunkVerifier::new(&hash, case as u64);
+            for chunk_index in order {
+                let start = chunk_index as usize * CHUNK_SIZE;
+                let chunk = &input[start..cmp::min(start + CHUNK_SIZE, case)];
+                let full_proof = chunk_proof(&*outboard, chunk_index).unwrap();
+                let proof_len = verifier.proof_len(chunk_index);
+                assert!(proof_len <= full_proof.len());
+                assert!(!verifier.is_verified(chunk_index) || proof_len == 0);
+                if proof_len > 0 {
+                    let err = verifier
+                        .verify_chunk(chunk_index, chunk, &full_proof[..proof_len - 1])
+                        .unwrap_err();
+                    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+                }
+                verifier
+                    .verify_chunk(chunk_index, chunk, &full_proof[..proof_len])
+                    .unwrap();
+                assert!(verifier.is_verified(chunk_index));
+                assert_eq!(
+                    chunk_index == num_chunks - 1 || verifier.final_chunk_verified(),
+                    verifier.final_chunk_verified(),
+                );
+                // A full proof works too.
+                verifier
+                    .verify_chunk(chunk_index, chunk, &full_proof)
+                    .unwrap();
+            }
+            assert!(verifier.final_chunk_verified());
+            for chunk_index in 0..num_chunks {
+                assert_eq!(0, verifier.proof_len(chunk_index));
+            }
+        }
+    }
+
+    #[test]
+    fn test_chunk_verifier_errors() {
+        let input = make_test_input(10 * CHUNK_SIZE + 1);
+        let (outboard, hash) = encode::outboard(&input);
+        let chunk = &input[3 * CHUNK_SIZE..][..CHUNK_SIZE];
+        let proof = chunk_proof(&*outboard, 3).unwrap();
+        let mut verifier = ChunkVerifier::new(&hash, input.len() as u64);
+
+        // Bad content, a bad proof, and a wrong root hash.
+        let mut bad_chunk = chunk.to_vec();
+        bad_chunk[0] ^= 1;
+        let err = verifier.verify_chunk(3, &bad_chunk, &proof).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        for i in 0..proof.len() {
+            let mut bad_proof = proof.clone();
+            let mut cv_bytes = *bad_proof[i].as_bytes();
+            cv_bytes[0] ^= 1;
+            bad_proof[i] = cv_bytes.into();
+            let err = verifier.verify_chunk(3, chunk, &bad_proof).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+        let mut wrong_hash = ChunkVerifier::new(&[0; HASH_SIZE].into(), input.len() as u64);
+        let err = wrong_hash.verify_chunk(3, chunk, &proof).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // Nothing was remembered from the failures.
+        assert_eq!(proof.len(), verifier.proof_len(3));
+        assert!(!verifier.is_verified(3));
+
+        // Bad arguments.
+        let err = verifier.verify_chunk(3, &chunk[1..], &proof).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        let err = verifier.verify_chunk(11, &input[..1], &proof).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        let err = chunk_proof(&*outboard, 11).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        // A length claim one byte short makes chunk 9 the final chunk, and that fails.
+        let mut short = ChunkVerifier::new(&hash, input.len() as u64 - 1);
+        let proof_9 = chunk_proof(&*outboard, 9).unwrap();
+        let chunk_9 = &input[9 * CHUNK_SIZE..][..CHUNK_SIZE];
+        let err = short.verify_chunk(9, chunk_9, &proof_9).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(!short.final_chunk_verified());
+    }
+
+    #[test]
+    fn test_chunk_verifier_siblings() {
+        let input = make_test_input(2 * CHUNK_SIZE);
+        let (outboard, hash) = encode::outboard(&input);
+        let mut verifier = ChunkVerifier::new(&hash, input.len() as u64);
+        let proof = chunk_proof(&*outboard, 0).unwrap();
+        verifier
+            .verify_chunk(0, &input[..CHUNK_SIZE], &proof)
+            .unwrap();
+
+        // Chunk 1's CV came in as chunk 0's sibling, but its content hasn't been seen.
+        assert!(verifier.is_verified(0));
+        assert!(!verifier.is_verified(1));
+        assert_eq!(0, verifier.proof_len(1));
+        assert!(!verifier.final_chunk_verified());
+
+        // Chunk 1 checks against the remembered CV without a proof.
+        let mut bad_chunk = input[CHUNK_SIZE..].to_vec();
+        bad_chunk[0] ^= 1;
+        let err = verifier.verify_chunk(1, &bad_chunk, &[]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(!verifier.is_verified(1));
+        verifier.verify_chunk(1, &input[CHUNK_SIZE..], &[]).unwrap();
+        assert!(verifier.is_verified(1));
+        assert!(verifier.final_chunk_verified());
+        assert!(!verifier.is_verified(2));
+    }
+}
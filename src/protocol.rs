@@ -0,0 +1,887 @@
+//! A small request/response protocol for serving slices over a stream.
+//!
+//! A client sends a request for one or more ranges of the content with a given root hash, and the
+//! server answers with a status, and then a slice for each range, as
+//! [`SliceExtractor`](../encode/struct.SliceExtractor.html) makes it: the length header, then the
+//! parent nodes and chunks that the range needs. The client verifies each slice with a
+//! [`SliceDecoder`](../decode/struct.SliceDecoder.html). A connection can carry any number of
+//! requests, one after another.
+//!
+//! Requests are the 3-byte magic `bao`, the 1-byte [`VERSION`](constant.VERSION.html), the 32-byte
+//! root hash, a 4-byte little-endian count of ranges from 1 to
+//! [`MAX_RANGES`](constant.MAX_RANGES.html), and then each range as its 8-byte little-endian start
+//! and length. Responses are the magic, the version, and a 1-byte status. A status of zero means
+//! the slices follow, and anything else is an [`Error`](enum.Error.html). A server that doesn't
+//! support the version of a request answers with its own version and
+//! `Error::UnsupportedVersion`, and closes the connection.
+//!
+//! [`serve`](fn.serve.html) and [`Client`](struct.Client.html) work over any blocking
+//! `Read + Write` stream, like a `TcpStream`. With the `tokio` feature enabled,
+//! [`serve_async`](fn.serve_async.html) and [`AsyncClient`](struct.AsyncClient.html) do the same
+//! over Tokio's `AsyncRead + AsyncWrite`. The two ends don't need to match.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use bao::outboard::PreOrderOutboard;
+//! use bao::protocol::{Client, Provider};
+//! use std::io::{self, Cursor};
+//! use std::net::{TcpListener, TcpStream};
+//!
+//! // A provider of a single input, kept in memory.
+//! struct OneInput {
+//!     hash: bao::Hash,
+//!     input: Vec<u8>,
+//!     outboard: Vec<u8>,
+//! }
+//!
+//! impl Provider for OneInput {
+//!     type Content = Cursor<Vec<u8>>;
+//!     type Outboard = PreOrderOutboard<Cursor<Vec<u8>>>;
+//!
+//!     fn open(&self, hash: &bao::Hash) -> io::Result<Option<(Self::Content, Self::Outboard)>> {
+//!         if hash != &self.hash {
+//!             return Ok(None);
+//!         }
+//!         let outboard = PreOrderOutboard::new(Cursor::new(self.outboard.clone()));
+//!         Ok(Some((Cursor::new(self.input.clone()), outboard)))
+//!     }
+//! }
+//!
+//! let input = vec![0xab; 1_000_000];
+//! let (outboard, hash) = bao::encode::outboard(&input);
+//! let provider = OneInput { hash, input: input.clone(), outboard };
+//!
+//! let listener = TcpListener::bind("127.0.0.1:0")?;
+//! let address = listener.local_addr()?;
+//! std::thread::spawn(move || -> io::Result<()> {
+//!     let (stream, _) = listener.accept()?;
+//!     bao::protocol::serve(&provider, stream)
+//! });
+//!
+//! let mut client = Client::new(TcpStream::connect(address)?);
+//! let mut output = Vec::new();
+//! let lens = client.get(&hash, &[1000..2000, 999_990..1_000_100], &mut output)?;
+//! assert_eq!(vec![1000, 10], lens);
+//! assert_eq!(&input[1000..2000], &output[..1000]);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::decode::SliceDecoder;
+use crate::encode::{self, SliceExtractor};
+use crate::outboard::OutboardStore;
+use crate::{Hash, HASH_SIZE, HEADER_SIZE};
+use arrayref::array_ref;
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use std::ops::Range;
+
+/// The version of the protocol that this module speaks.
+pub const VERSION: u8 = 1;
+
+/// The most ranges in a single request.
+pub const MAX_RANGES: usize = 1024;
+
+const MAGIC: [u8; 3] = *b"bao";
+const PREFIX_SIZE: usize = MAGIC.len() + 1;
+const REQUEST_FIXED_SIZE: usize = PREFIX_SIZE + HASH_SIZE + 4;
+const RANGE_SIZE: usize = 16;
+const RESPONSE_HEAD_SIZE: usize = PREFIX_SIZE + 1;
+const OK: u8 = 0;
+
+/// The errors a server can answer a request with. When they're converted to `std::io::Error`,
+/// `NotFound` becomes `ErrorKind::NotFound`, `BadRequest` becomes `ErrorKind::InvalidInput`,
+/// `UnsupportedVersion` becomes `ErrorKind::Unsupported`, and `ServerError` becomes
+/// `ErrorKind::Other`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The server doesn't have content with the requested hash.
+    NotFound,
+    /// The request was malformed, or had too many ranges.
+    BadRequest,
+    /// The server doesn't speak the version of the request.
+    UnsupportedVersion,
+    /// The server failed to open the content.
+    ServerError,
+}
+
+impl Error {
+    fn code(self) -> u8 {
+        match self {
+            Error::NotFound => 1,
+            Error::BadRequest => 2,
+            Error::UnsupportedVersion => 3,
+            Error::ServerError => 4,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            1 => Some(Error::NotFound),
+            2 => Some(Error::BadRequest),
+            3 => Some(Error::UnsupportedVersion),
+            4 => Some(Error::ServerError),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::NotFound => write!(f, "content not found"),
+            Error::BadRequest => write!(f, "bad request"),
+            Error::UnsupportedVersion => write!(f, "unsupported protocol version"),
+            Error::ServerError => write!(f, "server error"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        let kind = match e {
+            Error::NotFound => io::ErrorKind::NotFound,
+            Error::BadRequest => io::ErrorKind::InvalidInput,
+            Error::UnsupportedVersion => io::ErrorKind::Unsupported,
+            Error::ServerError => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+/// Where a server gets the content it serves, and its outboard encoding, by root hash.
+pub trait Provider {
+    type Content: Read + Seek;
+    type Outboard: OutboardStore;
+
+    /// Open the content with root hash `hash` and its outboard encoding, or return `None` if
+    /// there isn't any. The server answers an error here with `Error::ServerError`.
+    fn open(&self, hash: &Hash) -> io::Result<Option<(Self::Content, Self::Outboard)>>;
+}
+
+impl<P: Provider + ?Sized> Provider for &P {
+    type Content = P::Content;
+    type Outboard = P::Outboard;
+
+    fn open(&self, hash: &Hash) -> io::Result<Option<(Self::Content, Self::Outboard)>> {
+        (**self).open(hash)
+    }
+}
+
+// A request that's been read, or the error to answer it with.
+enum Request {
+    Ranges(Hash, Vec<Range<u64>>),
+    Rejected(Error),
+}
+
+fn request_bytes(hash: &Hash, ranges: &[Range<u64>]) -> io::Result<Vec<u8>> {
+    if ranges.is_empty() || ranges.len() > MAX_RANGES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "wrong number of ranges",
+        ));
+    }
+    let mut bytes = Vec::with_capacity(REQUEST_FIXED_SIZE + ranges.len() * RANGE_SIZE);
+    bytes.extend_from_slice(&MAGIC);
+    bytes.push(VERSION);
+    bytes.extend_from_slice(hash.as_bytes());
+    bytes.extend_from_slice(&(ranges.len() as u32).to_le_bytes());
+    for range in ranges {
+        if range.end < range.start {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "backwards range",
+            ));
+        }
+        bytes.extend_from_slice(&range.start.to_le_bytes());
+        bytes.extend_from_slice(&(range.end - range.start).to_le_bytes());
+    }
+    Ok(bytes)
+}
+
+// Check the fixed part of a request, and return the hash and the number of ranges.
+fn parse_request_fixed(fixed: &[u8; REQUEST_FIXED_SIZE]) -> Result<(Hash, usize), Error> {
+    if fixed[..MAGIC.len()] != MAGIC {
+        return Err(Error::BadRequest);
+    }
+    if fixed[MAGIC.len()] != VERSION {
+        return Err(Error::UnsupportedVersion);
+    }
+    let hash = (*array_ref!(fixed, PREFIX_SIZE, HASH_SIZE)).into();
+    let count = u32::from_le_bytes(*array_ref!(fixed, PREFIX_SIZE + HASH_SIZE, 4)) as usize;
+    if count == 0 || count > MAX_RANGES {
+        return Err(Error::BadRequest);
+    }
+    Ok((hash, count))
+}
+
+fn parse_ranges(bytes: &[u8]) -> Vec<Range<u64>> {
+    bytes
+        .chunks_exact(RANGE_SIZE)
+        .map(|range| {
+            let start = u64::from_le_bytes(*array_ref!(range, 0, 8));
+            let len = u64::from_le_bytes(*array_ref!(range, 8, 8));
+            start..start.saturating_add(len)
+        })
+        .collect()
+}
+
+fn response_head(status: Option<Error>) -> [u8; RESPONSE_HEAD_SIZE] {
+    let mut head = [0; RESPONSE_HEAD_SIZE];
+    head[..MAGIC.len()].copy_from_slice(&MAGIC);
+    head[MAGIC.len()] = VERSION;
+    head[PREFIX_SIZE] = status.map_or(OK, Error::code);
+    head
+}
+
+fn parse_response_head(head: &[u8; RESPONSE_HEAD_SIZE]) -> io::Result<()> {
+    if head[..MAGIC.len()] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid protocol response",
+        ));
+    }
+    let status = head[PREFIX_SIZE];
+    if head[MAGIC.len()] != VERSION {
+        return Err(Error::UnsupportedVersion.into());
+    }
+    if status == OK {
+        return Ok(());
+    }
+    match Error::from_code(status) {
+        Some(e) => Err(e.into()),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unknown protocol status",
+        )),
+    }
+}
+
+// The size of the rest of a slice, after its length header.
+fn slice_remaining(header: &[u8; HEADER_SIZE], range: &Range<u64>) -> u64 {
+    let content_len = crate::decode_len(header);
+    let size = encode::slice_size(content_len, range.start, range.end - range.start);
+    (size - HEADER_SIZE as u128) as u64
+}
+
+/// Answer requests from a [`Client`](struct.Client.html) on the other end of `stream`, with
+/// content from `provider`, until the client closes the connection.
+///
+/// This returns an error of kind `InvalidData` after answering a malformed request, or one with
+/// an unsupported version. Errors from the provider are answered with `Error::ServerError` and
+/// then returned. If reading the content or its outboard encoding fails after a slice has
+/// started, the error is returned without an answer, and the client sees the stream end early.
+pub fn serve(provider: &impl Provider, mut stream: impl Read + Write) -> io::Result<()> {
+    loop {
+        let request = match read_request(&mut stream)? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        let (hash, ranges) = match request {
+            Request::Ranges(hash, ranges) => (hash, ranges),
+            Request::Rejected(e) => {
+                stream.write_all(&response_head(Some(e)))?;
+                stream.flush()?;
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+        };
+        match provider.open(&hash) {
+            Ok(Some((mut content, mut outboard))) => {
+                stream.write_all(&response_head(None))?;
+                for range in ranges {
+                    // The extractor expects to start at the beginning of the content.
+                    content.seek(SeekFrom::Start(0))?;
+                    let mut extractor = SliceExtractor::new_outboard_store(
+                        &mut content,
+                        &mut outboard,
+                        range.start,
+                        range.end - range.start,
+                    );
+                    io::copy(&mut extractor, &mut stream)?;
+                }
+            }
+            Ok(None) => stream.write_all(&response_head(Some(Error::NotFound)))?,
+            Err(e) => {
+                stream.write_all(&response_head(Some(Error::ServerError)))?;
+                stream.flush()?;
+                return Err(e);
+            }
+        }
+        stream.flush()?;
+    }
+}
+
+// Read a request, or return None if the stream ends before one starts.
+fn read_request(stream: &mut impl Read) -> io::Result<Option<Request>> {
+    let mut fixed = [0; REQUEST_FIXED_SIZE];
+    let n = read_up_to(stream, &mut fixed)?;
+    if n == 0 {
+        return Ok(None);
+    }
+    // Check the prefix before waiting for the rest, so that a request of another version gets
+    // an answer even if it's shorter.
+    if n >= PREFIX_SIZE && fixed[..MAGIC.len()] == MAGIC && fixed[MAGIC.len()] != VERSION {
+        return Ok(Some(Request::Rejected(Error::UnsupportedVersion)));
+    }
+    if n < fixed.len() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (hash, count) = match parse_request_fixed(&fixed) {
+        Ok(fixed) => fixed,
+        Err(e) => return Ok(Some(Request::Rejected(e))),
+    };
+    let mut ranges = vec![0; count * RANGE_SIZE];
+    stream.read_exact(&mut ranges)?;
+    Ok(Some(Request::Ranges(hash, parse_ranges(&ranges))))
+}
+
+// Like read_exact, but return the number of bytes read if the stream ends early.
+fn read_up_to(stream: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut n = 0;
+    while n < buf.len() {
+        match stream.read(&mut buf[n..]) {
+            Ok(0) => break,
+            Ok(len) => n += len,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(n)
+}
+
+/// A client for a server running [`serve`](fn.serve.html), over a blocking stream.
+#[derive(Debug)]
+pub struct Client<T: Read + Write> {
+    stream: T,
+}
+
+impl<T: Read + Write> Client<T> {
+    pub fn new(stream: T) -> Self {
+        Self { stream }
+    }
+
+    /// Request `ranges` of the content with root hash `hash`, verify them, and write them to
+    /// `output` one after another. This returns the number of bytes written for each range,
+    /// which is less than its length if it goes past the end of the content. As with
+    /// [`SliceDecoder`](../decode/struct.SliceDecoder.html), a range that starts at or past the
+    /// end writes nothing, but it verifies the length of the content.
+    ///
+    /// This returns an error of kind `InvalidInput` if there are no ranges, more than
+    /// [`MAX_RANGES`](constant.MAX_RANGES.html), or a range that ends before it starts. A slice
+    /// that doesn't match the hash is an error of kind `InvalidData`, and errors from the server
+    /// are converted from [`Error`](enum.Error.html). After an error, the output might have some
+    /// of the content, all of it verified, and the client shouldn't be used again.
+    pub fn get(
+        &mut self,
+        hash: &Hash,
+        ranges: &[Range<u64>],
+        mut output: impl Write,
+    ) -> io::Result<Vec<u64>> {
+        self.stream.write_all(&request_bytes(hash, ranges)?)?;
+        self.stream.flush()?;
+        let mut head = [0; RESPONSE_HEAD_SIZE];
+        self.stream.read_exact(&mut head)?;
+        parse_response_head(&head)?;
+        let mut lens = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            let mut header = [0; HEADER_SIZE];
+            self.stream.read_exact(&mut header)?;
+            let mut rest = (&mut self.stream).take(slice_remaining(&header, range));
+            let mut decoder = SliceDecoder::new(
+                Cursor::new(header).chain(&mut rest),
+                hash,
+                range.start,
+                range.end - range.start,
+            );
+            lens.push(io::copy(&mut decoder, &mut output)?);
+            if rest.limit() != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "slice is the wrong length",
+                ));
+            }
+        }
+        output.flush()?;
+        Ok(lens)
+    }
+
+    /// Return the underlying stream.
+    pub fn into_inner(self) -> T {
+        self.stream
+    }
+}
+
+#[cfg(feature = "tokio")]
+pub use self::tokio_impl::{serve_async, AsyncClient};
+
+#[cfg(feature = "tokio")]
+mod tokio_impl {
+    use super::*;
+    use crate::{CHUNK_SIZE, MAX_DEPTH, PARENT_SIZE};
+    use std::cmp;
+    use std::collections::VecDeque;
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+    // Slices are copied in pieces of this size.
+    const BUF_SIZE: usize = 65536;
+
+    // The most input a SliceDecoder reads in a single call: the header, the parent nodes down to
+    // the start of the slice along with the final chunk if the slice starts past it, and then the
+    // parent nodes and the chunk for the bytes it returns.
+    const MAX_DECODER_READ: usize = HEADER_SIZE + 2 * (MAX_DEPTH * PARENT_SIZE + CHUNK_SIZE);
+
+    /// Like [`serve`](fn.serve.html), over a Tokio stream.
+    ///
+    /// The provider, the content, and its outboard encoding are still blocking, and they're used
+    /// directly on the calling task, so they should be fast, like in-memory content or local
+    /// files.
+    pub async fn serve_async(
+        provider: &impl Provider,
+        mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    ) -> io::Result<()> {
+        let mut buf = vec![0; BUF_SIZE];
+        loop {
+            let (hash, ranges) = match read_request_async(&mut stream).await? {
+                Some(Request::Ranges(hash, ranges)) => (hash, ranges),
+                Some(Request::Rejected(e)) => {
+                    stream.write_all(&response_head(Some(e))).await?;
+                    stream.flush().await?;
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                }
+                None => return Ok(()),
+            };
+            match provider.open(&hash) {
+                Ok(Some((mut content, mut outboard))) => {
+                    stream.write_all(&response_head(None)).await?;
+                    for range in ranges {
+                        content.seek(SeekFrom::Start(0))?;
+                        let mut extractor = SliceExtractor::new_outboard_store(
+                            &mut content,
+                            &mut outboard,
+                            range.start,
+                            range.end - range.start,
+                        );
+                        loop {
+                            let n = extractor.read(&mut buf)?;
+                            if n == 0 {
+                                break;
+                            }
+                            stream.write_all(&buf[..n]).await?;
+                        }
+                    }
+                }
+                Ok(None) => {
+                    stream
+                        .write_all(&response_head(Some(Error::NotFound)))
+                        .await?
+                }
+                Err(e) => {
+                    stream
+                        .write_all(&response_head(Some(Error::ServerError)))
+                        .await?;
+                    stream.flush().await?;
+                    return Err(e);
+                }
+            }
+            stream.flush().await?;
+        }
+    }
+
+    async fn read_request_async(
+        stream: &mut (impl AsyncRead + Unpin),
+    ) -> io::Result<Option<Request>> {
+        let mut fixed = [0; REQUEST_FIXED_SIZE];
+        let mut n = 0;
+        while n < fixed.len() {
+            let len = stream.read(&mut fixed[n..]).await?;
+            if len == 0 {
+                break;
+            }
+            n += len;
+        }
+        if n == 0 {
+            return Ok(None);
+        }
+        if n >= PREFIX_SIZE && fixed[..MAGIC.len()] == MAGIC && fixed[MAGIC.len()] != VERSION {
+            return Ok(Some(Request::Rejected(Error::UnsupportedVersion)));
+        }
+        if n < fixed.len() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (hash, count) = match parse_request_fixed(&fixed) {
+            Ok(fixed) => fixed,
+            Err(e) => return Ok(Some(Request::Rejected(e))),
+        };
+        let mut ranges = vec![0; count * RANGE_SIZE];
+        stream.read_exact(&mut ranges).await?;
+        Ok(Some(Request::Ranges(hash, parse_ranges(&ranges))))
+    }
+
+    /// Like [`Client`](struct.Client.html), over a Tokio stream.
+    #[derive(Debug)]
+    pub struct AsyncClient<T: AsyncRead + AsyncWrite + Unpin> {
+        stream: T,
+    }
+
+    impl<T: AsyncRead + AsyncWrite + Unpin> AsyncClient<T> {
+        pub fn new(stream: T) -> Self {
+            Self { stream }
+        }
+
+        /// Like [`Client::get`](struct.Client.html#method.get).
+        pub async fn get(
+            &mut self,
+            hash: &Hash,
+            ranges: &[Range<u64>],
+            mut output: impl AsyncWrite + Unpin,
+        ) -> io::Result<Vec<u64>> {
+            self.stream.write_all(&request_bytes(hash, ranges)?).await?;
+            self.stream.flush().await?;
+            let mut head = [0; RESPONSE_HEAD_SIZE];
+            self.stream.read_exact(&mut head).await?;
+            parse_response_head(&head)?;
+            let mut lens = Vec::with_capacity(ranges.len());
+            let mut buf = vec![0; BUF_SIZE];
+            for range in ranges {
+                let mut header = [0; HEADER_SIZE];
+                self.stream.read_exact(&mut header).await?;
+                let mut remaining = slice_remaining(&header, range);
+                // The decoder can't wait for the stream, so keep enough of the slice buffered
+                // ahead of it for its next read, and no more than that.
+                let mut decoder = SliceDecoder::new(
+                    VecDeque::from(header.to_vec()),
+                    hash,
+                    range.start,
+                    range.end - range.start,
+                );
+                let mut len = 0;
+                loop {
+                    let buffered = decoder.inner_mut();
+                    while buffered.len() < MAX_DECODER_READ && remaining > 0 {
+                        let want = cmp::min(remaining, BUF_SIZE as u64) as usize;
+                        let n = self.stream.read(&mut buf[..want]).await?;
+                        if n == 0 {
+                            return Err(io::ErrorKind::UnexpectedEof.into());
+                        }
+                        buffered.extend(&buf[..n]);
+                        remaining -= n as u64;
+                    }
+                    let n = decoder.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    output.write_all(&buf[..n]).await?;
+                    len += n as u64;
+                }
+                if remaining != 0 || !decoder.inner_mut().is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "slice is the wrong length",
+                    ));
+                }
+                lens.push(len);
+            }
+            output.flush().await?;
+            Ok(lens)
+        }
+
+        /// Return the underlying stream.
+        pub fn into_inner(self) -> T {
+            self.stream
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod test {
+    use super::*;
+    use crate::decode::make_test_input;
+    use crate::outboard::PreOrderOutboard;
+    use crate::CHUNK_SIZE;
+    use std::cmp;
+    use std::collections::HashMap;
+    use std::net::{SocketAddr, TcpListener, TcpStream};
+    use std::thread;
+
+    // A provider of inputs held in memory.
+    #[derive(Default)]
+    struct MemoryProvider {
+        inputs: HashMap<Hash, (Vec<u8>, Vec<u8>)>,
+        fail: bool,
+    }
+
+    impl MemoryProvider {
+        fn insert(&mut self, input: Vec<u8>) -> Hash {
+            let (outboard, hash) = encode::outboard(&input);
+            self.inputs.insert(hash, (input, outboard));
+            hash
+        }
+    }
+
+    impl Provider for MemoryProvider {
+        type Content = Cursor<Vec<u8>>;
+        type Outboard = PreOrderOutboard<Cursor<Vec<u8>>>;
+
+        fn open(&self, hash: &Hash) -> io::Result<Option<(Self::Content, Self::Outboard)>> {
+            if self.fail {
+                return Err(io::Error::other("provider failed"));
+            }
+            Ok(self.inputs.get(hash).map(|(input, outboard)| {
+                let outboard = PreOrderOutboard::new(Cursor::new(outboard.clone()));
+                (Cursor::new(input.clone()), outboard)
+            }))
+        }
+    }
+
+    // Serve one connection on a localhost socket, on another thread.
+    fn spawn_server(provider: MemoryProvider) -> (SocketAddr, thread::JoinHandle<io::Result<()>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            serve(&provider, stream)
+        });
+        (address, server)
+    }
+
+    fn test_ranges(len: usize) -> Vec<Range<u64>> {
+        let len = len as u64;
+        vec![
+            0..0,
+            0..len,
+            len / 3..len / 2,
+            len / 2..len + 10,
+            len..len + 1,
+            len + 1..len + 2,
+        ]
+    }
+
+    fn expected_output(input: &[u8], ranges: &[Range<u64>]) -> (Vec<u8>, Vec<u64>) {
+        let mut expected = Vec::new();
+        let mut lens = Vec::new();
+        for range in ranges {
+            let start = cmp::min(range.start as usize, input.len());
+            let end = cmp::min(range.end as usize, input.len());
+            expected.extend_from_slice(&input[start..end]);
+            lens.push((end - start) as u64);
+        }
+        (expected, lens)
+    }
+
+    #[test]
+    fn test_client_server() {
+        let mut provider = MemoryProvider::default();
+        let mut inputs = Vec::new();
+        for &case in crate::test::TEST_CASES {
+            let input = make_test_input(case);
+            inputs.push((provider.insert(input.clone()), input));
+        }
+        let (address, server) = spawn_server(provider);
+
+        // All the requests go over one connection.
+        let mut client = Client::new(TcpStream::connect(address).unwrap());
+        for (hash, input) in &inputs {
+            println!("case {}", input.len());
+            let ranges = test_ranges(input.len());
+            let mut output = Vec::new();
+            let lens = client.get(hash, &ranges, &mut output).unwrap();
+            let (expected, expected_lens) = expected_output(input, &ranges);
+            assert_eq!(expected_lens, lens);
+            assert_eq!(expected, output);
+        }
+
+        // Content the server doesn't have, and bad requests that don't reach the server.
+        let err = client
+            .get(&blake3::hash(b"foo"), &[0..1], io::sink())
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        let hash = &inputs[0].0;
+        let too_many = vec![0..1; MAX_RANGES + 1];
+        #[allow(clippy::reversed_empty_ranges)]
+        let bad_ranges = [&[][..], &too_many, &[2..1]];
+        for ranges in &bad_ranges {
+            let err = client.get(hash, ranges, io::sink()).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        }
+
+        // The connection still works, and the server stops when it closes.
+        let (hash, input) = inputs.last().unwrap();
+        let mut output = Vec::new();
+        client.get(hash, &[0..100], &mut output).unwrap();
+        assert_eq!(&input[..100], &output[..]);
+        drop(client);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_protocol_errors() {
+        // Corrupt content on the server fails verification.
+        let input = make_test_input(10 * CHUNK_SIZE);
+        let mut provider = MemoryProvider::default();
+        let hash = provider.insert(input.clone());
+        provider.inputs.get_mut(&hash).unwrap().0[5 * CHUNK_SIZE] ^= 1;
+        let (address, _) = spawn_server(provider);
+        let mut client = Client::new(TcpStream::connect(address).unwrap());
+        let mut output = Vec::new();
+        let err = client
+            .get(&hash, &[0..input.len() as u64], &mut output)
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(&input[..5 * CHUNK_SIZE], &output[..]);
+
+        // A provider that fails.
+        let provider = MemoryProvider {
+            fail: true,
+            ..Default::default()
+        };
+        let (address, server) = spawn_server(provider);
+        let mut client = Client::new(TcpStream::connect(address).unwrap());
+        let err = client.get(&hash, &[0..1], io::sink()).unwrap_err();
+        assert_eq!(io::ErrorKind::Other, err.kind());
+        assert_eq!(
+            io::ErrorKind::Other,
+            server.join().unwrap().unwrap_err().kind()
+        );
+
+        // Requests with another version or the wrong magic.
+        let mut requests = Vec::new();
+        let mut other_version = request_bytes(&hash, &[0..1]).unwrap();
+        other_version[MAGIC.len()] = VERSION + 1;
+        requests.push((other_version, Error::UnsupportedVersion));
+        let mut bad_magic = request_bytes(&hash, &[0..1]).unwrap();
+        bad_magic[0] ^= 1;
+        requests.push((bad_magic, Error::BadRequest));
+        let mut no_ranges = request_bytes(&hash, &[0..1]).unwrap();
+        no_ranges.truncate(REQUEST_FIXED_SIZE);
+        no_ranges[PREFIX_SIZE + HASH_SIZE..].copy_from_slice(&0u32.to_le_bytes());
+        requests.push((no_ranges, Error::BadRequest));
+        for (request, expected) in requests {
+            let (address, server) = spawn_server(MemoryProvider::default());
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(&request).unwrap();
+            let mut head = [0; RESPONSE_HEAD_SIZE];
+            stream.read_exact(&mut head).unwrap();
+            assert_eq!(response_head(Some(expected)), head);
+            let err = parse_response_head(&head).unwrap_err();
+            assert_eq!(io::Error::from(expected).kind(), err.kind());
+            let err = server.join().unwrap().unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_client_server() {
+        let mut provider = MemoryProvider::default();
+        let mut inputs = Vec::new();
+        for &case in crate::test::TEST_CASES {
+            let input = make_test_input(case);
+            inputs.push((provider.insert(input.clone()), input));
+        }
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            serve_async(&provider, stream).await
+        });
+
+        let mut client = AsyncClient::new(tokio::net::TcpStream::connect(address).await.unwrap());
+        for (hash, input) in &inputs {
+            println!("case {}", input.len());
+            let ranges = test_ranges(input.len());
+            let mut output = Vec::new();
+            let lens = client.get(hash, &ranges, &mut output).await.unwrap();
+            let (expected, expected_lens) = expected_output(input, &ranges);
+            assert_eq!(expected_lens, lens);
+            assert_eq!(expected, output);
+        }
+        let err = client
+            .get(&blake3::hash(b"foo"), &[0..1], tokio::io::sink())
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        drop(client);
+        server.await.unwrap().unwrap();
+
+        // The async client talks to the blocking server, and the other way around.
+        let mut provider = MemoryProvider::default();
+        let (hash, input) = inputs.pop().unwrap();
+        provider.insert(input.clone());
+        let (address, server) = spawn_server(provider);
+        let mut client = AsyncClient::new(tokio::net::TcpStream::connect(address).await.unwrap());
+        let mut output = Vec::new();
+        client
+            .get(&hash, &[0..u64::MAX], &mut output)
+            .await
+            .unwrap();
+        assert_eq!(input, output);
+        drop(client);
+        tokio::task::spawn_blocking(move || server.join().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+
+        let mut provider = MemoryProvider::default();
+        provider.insert(input.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await?;
+            serve_async(&provider, stream).await
+        });
+        let output = tokio::task::spawn_blocking(move || {
+            let mut client = Client::new(TcpStream::connect(address).unwrap());
+            let mut output = Vec::new();
+            client.get(&hash, &[0..u64::MAX], &mut output).unwrap();
+            output
+        })
+        .await
+        .unwrap();
+        assert_eq!(input, output);
+        server.await.unwrap().unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_client_errors() {
+        // Corrupt content fails verification, after the content before it is written.
+        let input = make_test_input(100 * CHUNK_SIZE);
+        let mut provider = MemoryProvider::default();
+        let hash = provider.insert(input.clone());
+        provider.inputs.get_mut(&hash).unwrap().0[50 * CHUNK_SIZE] ^= 1;
+        let (address, _) = spawn_server(provider);
+        let mut client = AsyncClient::new(tokio::net::TcpStream::connect(address).await.unwrap());
+        let mut output = Vec::new();
+        let err = client
+            .get(&hash, &[0..input.len() as u64], &mut output)
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(&input[..50 * CHUNK_SIZE], &output[..]);
+
+        // A server that claims the longest possible content, and then sends garbage without
+        // closing the stream, fails on the first parent node.
+        let (client_end, mut server_end) = tokio::io::duplex(1 << 20);
+        let mut response = response_head(None).to_vec();
+        response.extend_from_slice(&crate::encode_len(u64::MAX));
+        response.extend_from_slice(&[0; 1 << 16]);
+        tokio::io::AsyncWriteExt::write_all(&mut server_end, &response)
+            .await
+            .unwrap();
+        let mut client = AsyncClient::new(client_end);
+        let err = client
+            .get(&hash, &[0..u64::MAX], tokio::io::sink())
+            .await
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        drop(server_end);
+    }
+}
@@ -0,0 +1,501 @@
+//! Hashing content in pieces, for example across several machines, and combining the results.
+//!
+//! Each worker hashes an aligned piece of the content with
+//! [`SubtreeCv::new`](struct.SubtreeCv.html#method.new), which gives the chaining value (CV) of the
+//! piece's subtree. The BLAKE3 tree only has subtrees at certain positions: a piece starting at
+//! chunk `i` can be at most as many chunks as the largest power of two dividing `i`, and
+//! [`is_aligned`](fn.is_aligned.html) checks that. Every piece but the last has to be a whole
+//! number of chunks. Neighboring pieces are combined with
+//! [`SubtreeCv::parent`](struct.SubtreeCv.html#method.parent) and
+//! [`SubtreeCv::root`](struct.SubtreeCv.html#method.root), or all at once with
+//! [`root_hash`](fn.root_hash.html).
+//!
+//! Workers can also make outboard encodings of their pieces with
+//! [`outboard_fragment`](fn.outboard_fragment.html), and
+//! [`merge_outboard_fragments`](fn.merge_outboard_fragments.html) puts those together into the
+//! outboard encoding of the whole content, the same bytes as
+//! [`encode::outboard`](../encode/fn.outboard.html) would give.
+//!
+//! CVs are hashed in the regular BLAKE3 hash mode. Note that CVs aren't hashes, and a CV should
+//! never be used on its own as a hash of anything. Only a root hash is that.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use bao::subtree;
+//!
+//! let input = vec![0xab; 1_000_000];
+//!
+//! // Three workers hash aligned pieces of 512, 256, and the remaining 209 chunks.
+//! let pieces = [
+//!     (0, 0..512 * 1024),
+//!     (512, 512 * 1024..768 * 1024),
+//!     (768, 768 * 1024..input.len()),
+//! ];
+//! let mut cvs = Vec::new();
+//! let mut fragments = Vec::new();
+//! for (start_chunk, range) in pieces.iter().cloned() {
+//!     let (cv, fragment) = subtree::outboard_fragment(start_chunk, &input[range])?;
+//!     cvs.push(cv);
+//!     fragments.push((cv, fragment));
+//! }
+//!
+//! // The root hash, and the outboard encoding, match hashing the whole input at once.
+//! let (outboard, hash) = bao::encode::outboard(&input);
+//! assert_eq!(hash, subtree::root_hash(&cvs)?);
+//! let mut merged = Vec::new();
+//! assert_eq!(hash, subtree::merge_outboard_fragments(&fragments, &mut merged)?);
+//! assert_eq!(outboard, merged);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::encode;
+use crate::outboard::{self, Subtree};
+use crate::{Finalization, Hash, CHUNK_SIZE, PARENT_SIZE};
+use arrayref::array_ref;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+
+/// Whether `len` bytes of content starting at chunk `start_chunk` can be a subtree. That's the
+/// case when the piece isn't empty, doesn't run past the end of a `u64` byte offset, and is at
+/// most as many chunks as the largest power of two dividing `start_chunk`. Any length can start
+/// at chunk zero.
+///
+/// A piece that isn't a power of two number of whole chunks can only be at the end of the content.
+/// That can't be checked here, but combining it with anything on its right will fail.
+pub fn is_aligned(start_chunk: u64, len: u64) -> bool {
+    let fits = start_chunk
+        .checked_mul(CHUNK_SIZE as u64)
+        .and_then(|start| start.checked_add(len))
+        .is_some();
+    if len == 0 || !fits {
+        return false;
+    }
+    start_chunk == 0 || encode::count_chunks(len) <= 1 << start_chunk.trailing_zeros()
+}
+
+/// The chaining value of an aligned piece of content, along with its position. See the
+/// [module documentation](index.html).
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SubtreeCv {
+    start_chunk: u64,
+    len: u64,
+    cv: Hash,
+}
+
+impl SubtreeCv {
+    /// Hash `input`, a piece of content starting at chunk `start_chunk`. This returns an error of
+    /// kind `InvalidInput` if the piece isn't [aligned](fn.is_aligned.html).
+    pub fn new(start_chunk: u64, input: &[u8]) -> io::Result<Self> {
+        check_aligned(start_chunk, input.len() as u64)?;
+        Ok(Self {
+            start_chunk,
+            len: input.len() as u64,
+            cv: crate::Mode::Hash.subtree_cv(start_chunk, input, false),
+        })
+    }
+
+    /// Put together a CV that was computed somewhere else, for example one sent by a worker. This
+    /// returns an error of kind `InvalidInput` if the piece isn't [aligned](fn.is_aligned.html).
+    /// The CV itself can't be checked until the root hash is.
+    pub fn from_parts(start_chunk: u64, len: u64, cv: Hash) -> io::Result<Self> {
+        check_aligned(start_chunk, len)?;
+        Ok(Self {
+            start_chunk,
+            len,
+            cv,
+        })
+    }
+
+    /// The index of the first chunk of the piece.
+    pub fn start_chunk(&self) -> u64 {
+        self.start_chunk
+    }
+
+    /// The length of the piece in bytes.
+    pub fn content_len(&self) -> u64 {
+        self.len
+    }
+
+    /// The chaining value. This isn't a hash, and it shouldn't be used as one.
+    pub fn cv(&self) -> Hash {
+        self.cv
+    }
+
+    fn num_chunks(&self) -> u64 {
+        encode::count_chunks(self.len)
+    }
+
+    /// Combine this subtree with `right`, the subtree next to it, into their parent. This returns
+    /// an error of kind `InvalidInput` if the two aren't the left and right children of a parent
+    /// node. That means this subtree is a power of two number of whole chunks, starting at a
+    /// multiple of twice that, and `right` starts where this one ends and is no bigger.
+    pub fn parent(&self, right: &Self) -> io::Result<Self> {
+        check_children(self, right)?;
+        Ok(Self {
+            start_chunk: self.start_chunk,
+            len: self.len + right.len,
+            cv: crate::Mode::Hash.parent_cv(&self.cv, &right.cv, false),
+        })
+    }
+
+    /// Combine this subtree with `right` into the root hash of the content. As with
+    /// [`parent`](#method.parent), this returns an error of kind `InvalidInput` if the two aren't
+    /// siblings, or if this subtree doesn't start at chunk zero.
+    ///
+    /// Content of a single chunk doesn't have a parent node, and its root hash has to come from
+    /// hashing it directly.
+    pub fn root(&self, right: &Self) -> io::Result<Hash> {
+        check_children(self, right)?;
+        if self.start_chunk != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the root starts at chunk zero",
+            ));
+        }
+        Ok(crate::Mode::Hash.parent_cv(&self.cv, &right.cv, true))
+    }
+}
+
+impl fmt::Debug for SubtreeCv {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Avoid printing hashes, they might be secret.
+        write!(
+            f,
+            "SubtreeCv {{ start_chunk: {}, len: {} }}",
+            self.start_chunk, self.len,
+        )
+    }
+}
+
+/// Compute the root hash of the content from the CVs of its pieces, in order. This returns an
+/// error of kind `InvalidInput` if the pieces don't cover the content from the start, if any piece
+/// but the last is a partial chunk, or if any piece straddles the boundary between two subtrees
+/// of the whole tree. It also fails if there's only one piece, which needs to be hashed as the
+/// root.
+pub fn root_hash(pieces: &[SubtreeCv]) -> io::Result<Hash> {
+    let root = pieces_root(pieces)?;
+    if pieces.len() == 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a single piece has to be hashed as the root",
+        ));
+    }
+    node_cv(root, pieces, true)
+}
+
+/// Make the outboard encoding of a piece of content starting at chunk `start_chunk`. This returns
+/// the CV of the piece, along with its parent nodes in pre-order. There's no length header, and a
+/// piece of a single chunk has no parent nodes at all. This returns an error of kind
+/// `InvalidInput` if the piece isn't [aligned](fn.is_aligned.html).
+pub fn outboard_fragment(start_chunk: u64, input: &[u8]) -> io::Result<(SubtreeCv, Vec<u8>)> {
+    check_aligned(start_chunk, input.len() as u64)?;
+    let num_chunks = encode::count_chunks(input.len() as u64);
+    let mut parents = Vec::with_capacity((num_chunks as usize - 1) * PARENT_SIZE);
+    let cv = outboard::group_parents(input, start_chunk, Finalization::NotRoot, &mut parents);
+    let subtree = SubtreeCv {
+        start_chunk,
+        len: input.len() as u64,
+        cv,
+    };
+    Ok((subtree, parents))
+}
+
+/// Merge the outboard fragments of the pieces of the content, in order, into the outboard encoding
+/// of the whole content, and return the root hash. Each fragment is the output of
+/// [`outboard_fragment`](fn.outboard_fragment.html).
+///
+/// The pieces have to fit together as they do for [`root_hash`](fn.root_hash.html), or this
+/// returns an error of kind `InvalidInput`. It's also an `InvalidInput` error if a fragment is the
+/// wrong length or its top parent node doesn't match its CV, or if the content is a single chunk,
+/// which has no parent nodes to get the root hash from. Otherwise the fragments aren't verified,
+/// and a bad one will give a bad outboard encoding.
+pub fn merge_outboard_fragments<T: AsRef<[u8]>>(
+    fragments: &[(SubtreeCv, T)],
+    mut output: impl Write,
+) -> io::Result<Hash> {
+    let pieces: Vec<SubtreeCv> = fragments.iter().map(|(piece, _)| *piece).collect();
+    let root = pieces_root(&pieces)?;
+    if root.num_chunks == 1 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "a single chunk has no parent nodes",
+        ));
+    }
+    for (piece, fragment) in fragments {
+        let fragment = fragment.as_ref();
+        if fragment.len() as u64 != (piece.num_chunks() - 1) * PARENT_SIZE as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "outboard fragment is the wrong length",
+            ));
+        }
+        if !fragment.is_empty() {
+            let (left_cv, right_cv) = crate::split_parent(array_ref!(fragment, 0, PARENT_SIZE));
+            // Hash implements constant time equality.
+            if crate::Mode::Hash.parent_cv(&left_cv, &right_cv, false) != piece.cv {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "outboard fragment doesn't match its CV",
+                ));
+            }
+        }
+    }
+    let hash = if pieces.len() == 1 {
+        let (left_cv, right_cv) =
+            crate::split_parent(array_ref!(fragments[0].1.as_ref(), 0, PARENT_SIZE));
+        crate::Mode::Hash.parent_cv(&left_cv, &right_cv, true)
+    } else {
+        node_cv(root, &pieces, true)?
+    };
+    output.write_all(&crate::encode_len(root_len(&pieces)))?;
+    write_node(root, &pieces, fragments, &mut output)?;
+    Ok(hash)
+}
+
+fn check_aligned(start_chunk: u64, len: u64) -> io::Result<()> {
+    if !is_aligned(start_chunk, len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "piece isn't aligned to a subtree",
+        ));
+    }
+    Ok(())
+}
+
+fn check_children(left: &SubtreeCv, right: &SubtreeCv) -> io::Result<()> {
+    let left_chunks = left.num_chunks();
+    let siblings = left_chunks.is_power_of_two()
+        && left.len == left_chunks * CHUNK_SIZE as u64
+        && left.start_chunk % (2 * left_chunks) == 0
+        && right.start_chunk == left.start_chunk + left_chunks
+        && right.num_chunks() <= left_chunks;
+    if !siblings {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "subtrees aren't siblings",
+        ));
+    }
+    Ok(())
+}
+
+fn root_len(pieces: &[SubtreeCv]) -> u64 {
+    let last = pieces.last().expect("checked by pieces_root");
+    last.start_chunk * CHUNK_SIZE as u64 + last.len
+}
+
+// Check that the pieces cover the content from the start, and return the root of the tree.
+fn pieces_root(pieces: &[SubtreeCv]) -> io::Result<Subtree> {
+    let mut next_chunk = 0;
+    for (i, piece) in pieces.iter().enumerate() {
+        let whole_chunks = piece.len == piece.num_chunks() * CHUNK_SIZE as u64;
+        if piece.start_chunk != next_chunk || (i + 1 < pieces.len() && !whole_chunks) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "pieces don't cover the content",
+            ));
+        }
+        next_chunk += piece.num_chunks();
+    }
+    if pieces.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "no pieces"));
+    }
+    Ok(Subtree::root(root_len(pieces)))
+}
+
+// Split the pieces of a subtree between its children. A piece that straddles the two means the
+// pieces weren't aligned.
+fn split_pieces(subtree: Subtree, pieces: &[SubtreeCv]) -> io::Result<(Subtree, Subtree, usize)> {
+    let (left, right) = subtree.children();
+    let split = pieces
+        .iter()
+        .position(|piece| piece.start_chunk >= right.start_chunk)
+        .unwrap_or(pieces.len());
+    let left_end = split
+        .checked_sub(1)
+        .map(|i| pieces[i].start_chunk + pieces[i].num_chunks());
+    if left_end != Some(right.start_chunk) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "piece straddles two subtrees",
+        ));
+    }
+    Ok((left, right, split))
+}
+
+// The CV of a subtree made of one or more whole pieces.
+fn node_cv(subtree: Subtree, pieces: &[SubtreeCv], is_root: bool) -> io::Result<Hash> {
+    if let [piece] = pieces {
+        debug_assert!(!is_root);
+        debug_assert_eq!(subtree.start_chunk, piece.start_chunk);
+        if piece.num_chunks() != subtree.num_chunks {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "piece straddles two subtrees",
+            ));
+        }
+        return Ok(piece.cv);
+    }
+    let (left, right, split) = split_pieces(subtree, pieces)?;
+    let left_cv = node_cv(left, &pieces[..split], false)?;
+    let right_cv = node_cv(right, &pieces[split..], false)?;
+    Ok(crate::Mode::Hash.parent_cv(&left_cv, &right_cv, is_root))
+}
+
+// Write the parent nodes of a subtree made of one or more whole pieces, in pre-order.
+fn write_node<T: AsRef<[u8]>>(
+    subtree: Subtree,
+    pieces: &[SubtreeCv],
+    fragments: &[(SubtreeCv, T)],
+    output: &mut impl Write,
+) -> io::Result<()> {
+    if let [(_, fragment)] = fragments {
+        return output.write_all(fragment.as_ref());
+    }
+    let (left, right, split) = split_pieces(subtree, pieces)?;
+    let left_cv = node_cv(left, &pieces[..split], false)?;
+    let right_cv = node_cv(right, &pieces[split..], false)?;
+    output.write_all(left_cv.as_bytes())?;
+    output.write_all(right_cv.as_bytes())?;
+    write_node(left, &pieces[..split], &fragments[..split], output)?;
+    write_node(right, &pieces[split..], &fragments[split..], output)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decode::make_test_input;
+
+    // Split the content into aligned pieces of up to `max_chunks` chunks, a power of two.
+    fn split(input: &[u8], max_chunks: u64) -> Vec<(u64, &[u8])> {
+        input
+            .chunks(max_chunks as usize * CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, piece)| (i as u64 * max_chunks, piece))
+            .collect()
+    }
+
+    #[test]
+    fn test_subtree_cvs() {
+        for &case in crate::test::TEST_CASES {
+            if case <= CHUNK_SIZE {
+                continue;
+            }
+            let input = make_test_input(case);
+            let hash = blake3::hash(&input);
+            let (outboard, _) = encode::outboard(&input);
+            for &max_chunks in &[1, 2, 4, 1 << 20] {
+                println!("case {} max_chunks {}", case, max_chunks);
+                let mut pieces = Vec::new();
+                let mut fragments = Vec::new();
+                for (start_chunk, piece) in split(&input, max_chunks) {
+                    assert!(is_aligned(start_chunk, piece.len() as u64));
+                    let (cv, fragment) = outboard_fragment(start_chunk, piece).unwrap();
+                    assert_eq!(cv, SubtreeCv::new(start_chunk, piece).unwrap());
+                    pieces.push(cv);
+                    fragments.push((cv, fragment));
+                }
+                if pieces.len() > 1 {
+                    assert_eq!(hash, root_hash(&pieces).unwrap());
+                }
+                let mut merged = Vec::new();
+                assert_eq!(
+                    hash,
+                    merge_outboard_fragments(&fragments, &mut merged).unwrap()
+                );
+                assert_eq!(outboard, merged);
+            }
+
+            // Combine the two children of the root by hand.
+            let left_len = encode::left_subtree_chunks(encode::count_chunks(case as u64)) as usize
+                * CHUNK_SIZE;
+            let left = SubtreeCv::new(0, &input[..left_len]).unwrap();
+            let right =
+                SubtreeCv::new(left_len as u64 / CHUNK_SIZE as u64, &input[left_len..]).unwrap();
+            assert_eq!(hash, left.root(&right).unwrap());
+            let parent = left.parent(&right).unwrap();
+            assert_eq!(parent, SubtreeCv::new(0, &input).unwrap());
+            let copy = SubtreeCv::from_parts(0, case as u64, parent.cv()).unwrap();
+            assert_eq!(parent, copy);
+        }
+    }
+
+    #[test]
+    fn test_subtree_errors() {
+        assert!(is_aligned(0, 100 * CHUNK_SIZE as u64 + 1));
+        assert!(is_aligned(4, 4 * CHUNK_SIZE as u64));
+        assert!(is_aligned(4, 3 * CHUNK_SIZE as u64 + 1));
+        assert!(!is_aligned(4, 4 * CHUNK_SIZE as u64 + 1));
+        assert!(!is_aligned(6, 3 * CHUNK_SIZE as u64));
+        assert!(!is_aligned(0, 0));
+        assert!(!is_aligned(u64::MAX / CHUNK_SIZE as u64, CHUNK_SIZE as u64));
+
+        let input = make_test_input(8 * CHUNK_SIZE);
+        let cv = |start: usize, end: usize| {
+            SubtreeCv::new(start as u64, &input[start * CHUNK_SIZE..end * CHUNK_SIZE]).unwrap()
+        };
+        let err = SubtreeCv::new(2, &input[..3 * CHUNK_SIZE]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        let err = SubtreeCv::from_parts(1, 0, cv(0, 1).cv()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        // Out of order, not next to each other, and not a parent node. A partial chunk on the left
+        // can't have a sibling either.
+        let partial = SubtreeCv::new(0, &input[..CHUNK_SIZE - 1]).unwrap();
+        let not_siblings = [
+            (cv(1, 2), cv(0, 1)),
+            (cv(0, 1), cv(2, 3)),
+            (cv(1, 2), cv(2, 3)),
+            (partial, cv(1, 2)),
+        ];
+        for (left, right) in &not_siblings {
+            let err = left.parent(right).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+            let err = left.root(right).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        }
+        let err = cv(4, 6).root(&cv(6, 8)).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        // Gaps, not starting at zero, a partial chunk in the middle, a single piece, and nothing.
+        let bad_pieces = [
+            vec![cv(0, 4), cv(6, 8)],
+            vec![cv(1, 2), cv(2, 4)],
+            vec![cv(0, 2), cv(2, 3), cv(4, 8)],
+            vec![partial, cv(1, 2)],
+            vec![cv(0, 8)],
+            vec![],
+        ];
+        for pieces in &bad_pieces {
+            let err = root_hash(pieces).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        }
+        let straddling = SubtreeCv::new(0, &input[..3 * CHUNK_SIZE]).unwrap();
+        let err = root_hash(&[straddling, cv(3, 4)]).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+        // Fragments that are the wrong length or don't match their CVs, and a single chunk.
+        let (left, left_fragment) = outboard_fragment(0, &input[..4 * CHUNK_SIZE]).unwrap();
+        let (right, right_fragment) = outboard_fragment(4, &input[4 * CHUNK_SIZE..]).unwrap();
+        let mut bad_fragment = right_fragment.clone();
+        bad_fragment[0] ^= 1;
+        let bad_fragments = [
+            vec![(left, left_fragment.clone()), (right, Vec::new())],
+            vec![(left, left_fragment.clone()), (right, bad_fragment)],
+            vec![
+                (left, right_fragment.clone()),
+                (right, left_fragment.clone()),
+            ],
+        ];
+        for fragments in &bad_fragments {
+            let err = merge_outboard_fragments(fragments, io::sink()).unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        }
+        let (single, single_fragment) = outboard_fragment(0, &input[..10]).unwrap();
+        let err = merge_outboard_fragments(&[(single, single_fragment)], io::sink()).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+}
@@ -0,0 +1,652 @@
+//! Syncing content over a stream, transferring only the parts that changed.
+//!
+//! The sender has the new version of some content, with its outboard encoding. The receiver has
+//! the root hash of the new version, and an old version of the content with its outboard
+//! encoding. They talk over any `Read + Write` stream, like a `TcpStream`, and the receiver asks
+//! the questions:
+//!
+//! 1. The sender starts by sending the length of the new content. If it's the same as the old
+//!    length, the receiver walks down the tree one level at a time, asking for the sender's parent
+//!    nodes and verifying them against the root hash. A subtree whose CV matches the one in the
+//!    old outboard encoding has the same content, and the receiver doesn't look any further into
+//!    it. Only the parent nodes above the changes are transferred.
+//! 2. The receiver asks for a slice of each changed range, and decodes it with a
+//!    [`SliceDecoder`](../decode/struct.SliceDecoder.html).
+//! 3. The rest of the content comes from the old version, and that's verified too, against the
+//!    CVs of its subtrees from step 1. If the old outboard encoding turns out to be wrong about
+//!    some part of the old content, that part is fetched from the sender instead.
+//!
+//! Everything the receiver writes has been verified against the root hash, and it's written in
+//! order, so the output doesn't need to be seekable. If the lengths are different, all of the new
+//! content is fetched. To get the new outboard encoding afterwards, encode the output again.
+//!
+//! The messages from the receiver are a one-byte type and its fields, with integers in little
+//! endian:
+//!
+//! - Type 1, parent nodes: a 4-byte count of up to 4096, then that many positions, each an
+//!   8-byte start chunk and a 1-byte height. The sender replies with the 64-byte parent nodes.
+//! - Type 2, a slice: the 8-byte start and length. The sender replies with the slice, as
+//!   [`SliceExtractor`](../encode/struct.SliceExtractor.html) makes it.
+//! - Type 0, done. The sender returns.
+//!
+//! # Example
+//!
+//! ```
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use std::net::{TcpListener, TcpStream};
+//! use std::io::Cursor;
+//!
+//! let old_content = vec![0xab; 1_000_000];
+//! let mut new_content = old_content.clone();
+//! new_content[500_000] ^= 1;
+//! let (old_outboard, _) = bao::encode::outboard(&old_content);
+//! let (new_outboard, hash) = bao::encode::outboard(&new_content);
+//! let expected = new_content.clone();
+//!
+//! let listener = TcpListener::bind("127.0.0.1:0")?;
+//! let address = listener.local_addr()?;
+//! let sender = std::thread::spawn(move || -> std::io::Result<()> {
+//!     let (stream, _) = listener.accept()?;
+//!     bao::sync::serve_sync(Cursor::new(&new_content), &*new_outboard, stream)
+//! });
+//!
+//! let stream = TcpStream::connect(address)?;
+//! let mut output = Vec::new();
+//! let stats = bao::sync::receive_sync(
+//!     Cursor::new(&old_content),
+//!     &*old_outboard,
+//!     &hash,
+//!     stream,
+//!     &mut output,
+//! )?;
+//! sender.join().unwrap()?;
+//! assert_eq!(expected, output);
+//! // Only the chunk with the change came over the stream.
+//! assert_eq!(1024, stats.fetched_bytes);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::decode::{self, SliceDecoder};
+use crate::encode::{self, SliceExtractor};
+use crate::outboard::{OutboardStore, Subtree, TreePosition};
+use crate::{Hash, CHUNK_SIZE, HEADER_SIZE, PARENT_SIZE};
+use arrayref::array_ref;
+use std::cmp;
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::ops::Range;
+
+const DONE: u8 = 0;
+const PARENTS: u8 = 1;
+const SLICE: u8 = 2;
+
+// The most parent nodes in one request, 256 KiB of them.
+const MAX_PARENTS_PER_REQUEST: usize = 4096;
+const POSITION_SIZE: usize = 9;
+
+// Reused content is verified in pieces of up to this many chunks, at least 16 KiB for AVX-512.
+const REUSE_CHUNKS: u64 = 16;
+
+/// What [`receive_sync`](fn.receive_sync.html) did.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncStats {
+    /// The length of the new content, which is the number of bytes written to the output.
+    pub content_len: u64,
+    /// The bytes of content that came from the old version.
+    pub reused_bytes: u64,
+    /// The bytes of content that came from the sender.
+    pub fetched_bytes: u64,
+    /// All the bytes read from the stream, including parent nodes and the overhead of slices.
+    pub received_bytes: u64,
+}
+
+/// Serve the new version of some content to a [`receive_sync`](fn.receive_sync.html) call on
+/// the other end of `stream`, until it's done. `outboard` is the outboard encoding of `content`.
+///
+/// This returns an error of kind `InvalidData` if the receiver sends a malformed message, and an
+/// error of kind `UnexpectedEof` if the stream ends before the receiver is done. Errors from
+/// `content` and `outboard`, like asking for a parent node that doesn't exist, are returned
+/// as-is.
+pub fn serve_sync(
+    mut content: impl Read + Seek,
+    mut outboard: impl OutboardStore,
+    mut stream: impl Read + Write,
+) -> io::Result<()> {
+    stream.write_all(&crate::encode_len(outboard.content_len()?))?;
+    stream.flush()?;
+    loop {
+        let mut kind = [0];
+        stream.read_exact(&mut kind)?;
+        match kind[0] {
+            DONE => return Ok(()),
+            PARENTS => {
+                let mut count = [0; 4];
+                stream.read_exact(&mut count)?;
+                let count = u32::from_le_bytes(count) as usize;
+                if count > MAX_PARENTS_PER_REQUEST {
+                    return Err(invalid_message());
+                }
+                let mut positions = vec![0; count * POSITION_SIZE];
+                stream.read_exact(&mut positions)?;
+                let mut parents = Vec::with_capacity(count * PARENT_SIZE);
+                for position in positions.chunks_exact(POSITION_SIZE) {
+                    let position = TreePosition {
+                        start_chunk: u64::from_le_bytes(*array_ref!(position, 0, 8)),
+                        height: position[8],
+                    };
+                    parents.extend_from_slice(&outboard.parent(position)?);
+                }
+                stream.write_all(&parents)?;
+            }
+            SLICE => {
+                let mut fields = [0; 16];
+                stream.read_exact(&mut fields)?;
+                let start = u64::from_le_bytes(*array_ref!(fields, 0, 8));
+                let len = u64::from_le_bytes(*array_ref!(fields, 8, 8));
+                // The extractor expects to start at the beginning of the content.
+                content.seek(SeekFrom::Start(0))?;
+                let mut extractor =
+                    SliceExtractor::new_outboard_store(&mut content, &mut outboard, start, len);
+                io::copy(&mut extractor, &mut stream)?;
+            }
+            _ => return Err(invalid_message()),
+        }
+        stream.flush()?;
+    }
+}
+
+fn invalid_message() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "invalid sync message")
+}
+
+/// Sync the content with root hash `hash` from a [`serve_sync`](fn.serve_sync.html) call on the
+/// other end of `stream`, reusing what's unchanged from `old_content`, whose outboard encoding
+/// is `old_outboard`. The new content is written to `output`. See the
+/// [module documentation](index.html).
+///
+/// This returns an error of kind `InvalidData` if anything from the sender doesn't match the hash.
+/// Errors from the old content and its outboard encoding are returned as-is. After an error, the
+/// output might have some of the new content, all of it verified.
+pub fn receive_sync(
+    old_content: impl Read + Seek,
+    old_outboard: impl OutboardStore,
+    hash: &Hash,
+    stream: impl Read + Write,
+    output: impl Write,
+) -> io::Result<SyncStats> {
+    let mut receiver = Receiver {
+        old_content,
+        old_outboard,
+        hash: *hash,
+        stream: CountingStream {
+            inner: stream,
+            read: 0,
+        },
+        output,
+        content_len: 0,
+        stats: SyncStats::default(),
+        buf: vec![0; REUSE_CHUNKS as usize * CHUNK_SIZE],
+    };
+    let mut header = [0; HEADER_SIZE];
+    receiver.stream.read_exact(&mut header)?;
+    receiver.content_len = crate::decode_len(&header);
+    let segments = receiver.find_segments()?;
+    for segment in segments {
+        match segment {
+            Segment::Reuse(subtree, cv) => receiver.reuse(subtree, cv)?,
+            Segment::Fetch(range) => receiver.fetch(range)?,
+        }
+    }
+    receiver.stream.write_all(&[DONE])?;
+    receiver.stream.flush()?;
+    receiver.output.flush()?;
+    receiver.stats.content_len = receiver.content_len;
+    receiver.stats.received_bytes = receiver.stream.read;
+    Ok(receiver.stats)
+}
+
+// A part of the new content, and where it comes from.
+enum Segment {
+    // A subtree with the same CV in the old content, and the verified CV.
+    Reuse(Subtree, Hash),
+    // A range of bytes to fetch from the sender.
+    Fetch(Range<u64>),
+}
+
+impl Segment {
+    fn start(&self) -> u64 {
+        match self {
+            Segment::Reuse(subtree, _) => subtree.start_chunk * CHUNK_SIZE as u64,
+            Segment::Fetch(range) => range.start,
+        }
+    }
+}
+
+struct Receiver<C, S, T, W> {
+    old_content: C,
+    old_outboard: S,
+    hash: Hash,
+    stream: CountingStream<T>,
+    output: W,
+    content_len: u64,
+    stats: SyncStats,
+    buf: Vec<u8>,
+}
+
+impl<C, S, T, W> Receiver<C, S, T, W>
+where
+    C: Read + Seek,
+    S: OutboardStore,
+    T: Read + Write,
+    W: Write,
+{
+    // Compare the trees one level at a time, and return the segments of the new content in order.
+    fn find_segments(&mut self) -> io::Result<Vec<Segment>> {
+        let root = Subtree::root(self.content_len);
+        let all = Segment::Fetch(0..self.content_len);
+        if self.old_outboard.content_len()? != self.content_len {
+            return Ok(vec![all]);
+        }
+        if root.num_chunks == 1 {
+            // There are no parent nodes, so compare the whole content to the root hash.
+            let len = self.content_len as usize;
+            self.old_content.seek(SeekFrom::Start(0))?;
+            self.old_content.read_exact(&mut self.buf[..len])?;
+            // Hash implements constant time equality.
+            if crate::Mode::Hash.chunk_cv(0, &self.buf[..len], true) == self.hash {
+                return Ok(vec![Segment::Reuse(root, self.hash)]);
+            }
+            return Ok(vec![all]);
+        }
+        let mut segments = Vec::new();
+        let mut level = vec![(root, self.hash)];
+        while !level.is_empty() {
+            let mut next_level = Vec::new();
+            for batch in level.chunks(MAX_PARENTS_PER_REQUEST) {
+                let parents = self.request_parents(batch)?;
+                for (&(subtree, cv), parent) in batch.iter().zip(parents.chunks_exact(PARENT_SIZE))
+                {
+                    let (left_cv, right_cv) =
+                        crate::split_parent(array_ref!(parent, 0, PARENT_SIZE));
+                    let is_root = subtree.num_chunks == root.num_chunks;
+                    // Hash implements constant time equality.
+                    if crate::Mode::Hash.parent_cv(&left_cv, &right_cv, is_root) != cv {
+                        return Err(decode::Error::HashMismatch.into());
+                    }
+                    let old_parent = self.old_outboard.parent(subtree.position())?;
+                    let (old_left_cv, old_right_cv) = crate::split_parent(&old_parent);
+                    let (left, right) = subtree.children();
+                    for (child, cv, old_cv) in [
+                        (left, left_cv, old_left_cv),
+                        (right, right_cv, old_right_cv),
+                    ] {
+                        if cv == old_cv {
+                            segments.push(Segment::Reuse(child, cv));
+                        } else if child.num_chunks > 1 {
+                            next_level.push((child, cv));
+                        } else {
+                            segments.push(Segment::Fetch(self.byte_range(child)));
+                        }
+                    }
+                }
+            }
+            level = next_level;
+        }
+        segments.sort_unstable_by_key(Segment::start);
+        // Merge neighboring fetches, to save on slice headers and parent nodes.
+        let mut merged: Vec<Segment> = Vec::with_capacity(segments.len());
+        for segment in segments {
+            match (merged.last_mut(), segment) {
+                (Some(Segment::Fetch(last)), Segment::Fetch(range)) if last.end == range.start => {
+                    last.end = range.end;
+                }
+                (_, segment) => merged.push(segment),
+            }
+        }
+        Ok(merged)
+    }
+
+    fn byte_range(&self, subtree: Subtree) -> Range<u64> {
+        let start = subtree.start_chunk * CHUNK_SIZE as u64;
+        let end = cmp::min(
+            start + subtree.num_chunks * CHUNK_SIZE as u64,
+            self.content_len,
+        );
+        start..end
+    }
+
+    fn request_parents(&mut self, subtrees: &[(Subtree, Hash)]) -> io::Result<Vec<u8>> {
+        let mut request = Vec::with_capacity(5 + subtrees.len() * POSITION_SIZE);
+        request.push(PARENTS);
+        request.extend_from_slice(&(subtrees.len() as u32).to_le_bytes());
+        for &(subtree, _) in subtrees {
+            let position = subtree.position();
+            request.extend_from_slice(&position.start_chunk.to_le_bytes());
+            request.push(position.height);
+        }
+        self.stream.write_all(&request)?;
+        self.stream.flush()?;
+        let mut parents = vec![0; subtrees.len() * PARENT_SIZE];
+        self.stream.read_exact(&mut parents)?;
+        Ok(parents)
+    }
+
+    // Copy a subtree from the old content, checking it against its verified CV along the way. If
+    // any part of it doesn't match, fetch that part instead.
+    fn reuse(&mut self, subtree: Subtree, cv: Hash) -> io::Result<()> {
+        let is_root = subtree.num_chunks == encode::count_chunks(self.content_len);
+        if subtree.num_chunks > REUSE_CHUNKS {
+            let parent = self.old_outboard.parent(subtree.position())?;
+            let (left_cv, right_cv) = crate::split_parent(&parent);
+            // Hash implements constant time equality.
+            if crate::Mode::Hash.parent_cv(&left_cv, &right_cv, is_root) != cv {
+                return self.fetch(self.byte_range(subtree));
+            }
+            let (left, right) = subtree.children();
+            self.reuse(left, left_cv)?;
+            return self.reuse(right, right_cv);
+        }
+        let range = self.byte_range(subtree);
+        let len = (range.end - range.start) as usize;
+        self.old_content.seek(SeekFrom::Start(range.start))?;
+        self.old_content.read_exact(&mut self.buf[..len])?;
+        let bytes = &self.buf[..len];
+        // Hash implements constant time equality.
+        if crate::Mode::Hash.subtree_cv(subtree.start_chunk, bytes, is_root) != cv {
+            return self.fetch(range);
+        }
+        self.output.write_all(bytes)?;
+        self.stats.reused_bytes += len as u64;
+        Ok(())
+    }
+
+    fn fetch(&mut self, range: Range<u64>) -> io::Result<()> {
+        let len = range.end - range.start;
+        let mut request = [0; 17];
+        request[0] = SLICE;
+        request[1..9].copy_from_slice(&range.start.to_le_bytes());
+        request[9..17].copy_from_slice(&len.to_le_bytes());
+        self.stream.write_all(&request)?;
+        self.stream.flush()?;
+        let slice_size = encode::slice_size(self.content_len, range.start, len) as u64;
+        let mut slice = (&mut self.stream).take(slice_size);
+        let mut decoder = SliceDecoder::new(&mut slice, &self.hash, range.start, len);
+        let copied = io::copy(&mut decoder, &mut self.output)?;
+        if copied != len || slice.limit() != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "slice is the wrong length",
+            ));
+        }
+        self.stats.fetched_bytes += len;
+        Ok(())
+    }
+}
+
+// Counts the bytes read from the stream.
+struct CountingStream<T> {
+    inner: T,
+    read: u64,
+}
+
+impl<T: Read> Read for CountingStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for CountingStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::decode::make_test_input;
+    use std::io::Cursor;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    // Run a sync over a localhost socket, and return the receiver's result and the sender's.
+    fn sync_over_tcp(
+        old_content: &[u8],
+        old_outboard: &[u8],
+        new_content: &[u8],
+        new_outboard: &[u8],
+        hash: &Hash,
+        output: &mut Vec<u8>,
+    ) -> (io::Result<SyncStats>, io::Result<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let new_content = new_content.to_vec();
+        let new_outboard = new_outboard.to_vec();
+        let sender = thread::spawn(move || {
+            let (stream, _) = listener.accept()?;
+            serve_sync(Cursor::new(&new_content), &*new_outboard, stream)
+        });
+        let stream = TcpStream::connect(address).unwrap();
+        let received = receive_sync(
+            Cursor::new(old_content),
+            old_outboard,
+            hash,
+            &stream,
+            output,
+        );
+        // If the receiver failed, hang up so that the sender doesn't wait for it.
+        drop(stream);
+        (received, sender.join().unwrap())
+    }
+
+    #[test]
+    fn test_sync() {
+        for &case in crate::test::TEST_CASES {
+            println!("case {}", case);
+            let old_content = make_test_input(case);
+            let (old_outboard, _) = encode::outboard(&old_content);
+            let mut changes = vec![Vec::new(), vec![0], vec![case / 2, case / 2 + 1]];
+            if case > 0 {
+                changes.push(vec![case - 1]);
+            }
+            for offsets in &changes {
+                if offsets.iter().any(|&offset| offset >= case) {
+                    continue;
+                }
+                let mut new_content = old_content.clone();
+                for &offset in offsets {
+                    new_content[offset] ^= 1;
+                }
+                let (new_outboard, hash) = encode::outboard(&new_content);
+                let mut output = Vec::new();
+                let (received, sent) = sync_over_tcp(
+                    &old_content,
+                    &old_outboard,
+                    &new_content,
+                    &new_outboard,
+                    &hash,
+                    &mut output,
+                );
+                let stats = received.unwrap();
+                sent.unwrap();
+                assert_eq!(new_content, output);
+                assert_eq!(case as u64, stats.content_len);
+                assert_eq!(case as u64, stats.reused_bytes + stats.fetched_bytes);
+                // Each change is at most one chunk fetched.
+                assert!(stats.fetched_bytes <= (offsets.len() * CHUNK_SIZE) as u64);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sync_mostly_unchanged() {
+        // A few changes in a big input move a small fraction of it.
+        let old_content = make_test_input(1 << 24);
+        let mut new_content = old_content.clone();
+        for &offset in &[1_000, 5_000_000, 12_345_678] {
+            new_content[offset] ^= 1;
+        }
+        let (old_outboard, _) = encode::outboard(&old_content);
+        let (new_outboard, hash) = encode::outboard(&new_content);
+        let mut output = Vec::new();
+        let (received, sent) = sync_over_tcp(
+            &old_content,
+            &old_outboard,
+            &new_content,
+            &new_outboard,
+            &hash,
+            &mut output,
+        );
+        let stats = received.unwrap();
+        sent.unwrap();
+        assert_eq!(new_content, output);
+        assert_eq!(3 * CHUNK_SIZE as u64, stats.fetched_bytes);
+        assert!(stats.received_bytes < 20_000);
+    }
+
+    #[test]
+    fn test_sync_fallbacks() {
+        let old_content = make_test_input(100 * CHUNK_SIZE);
+        let (old_outboard, _) = encode::outboard(&old_content);
+
+        // A different length fetches everything.
+        let new_content = make_test_input(100 * CHUNK_SIZE + 1);
+        let (new_outboard, hash) = encode::outboard(&new_content);
+        let mut output = Vec::new();
+        let (received, sent) = sync_over_tcp(
+            &old_content,
+            &old_outboard,
+            &new_content,
+            &new_outboard,
+            &hash,
+            &mut output,
+        );
+        sent.unwrap();
+        assert_eq!(new_content.len() as u64, received.unwrap().fetched_bytes);
+        assert_eq!(new_content, output);
+
+        // Old content that changed without updating its outboard encoding is fetched instead,
+        // both in a small subtree and in a big one.
+        let mut stale_content = old_content.clone();
+        stale_content[0] ^= 1;
+        stale_content[60 * CHUNK_SIZE] ^= 1;
+        let mut stale_outboard = old_outboard.clone();
+        stale_outboard[HEADER_SIZE + 2 * PARENT_SIZE] ^= 1;
+        let (new_outboard, hash) = encode::outboard(&old_content);
+        let mut output = Vec::new();
+        let (received, sent) = sync_over_tcp(
+            &stale_content,
+            &stale_outboard,
+            &old_content,
+            &new_outboard,
+            &hash,
+            &mut output,
+        );
+        sent.unwrap();
+        let stats = received.unwrap();
+        assert_eq!(old_content, output);
+        assert!(stats.fetched_bytes > 0);
+        assert!(stats.fetched_bytes < old_content.len() as u64 / 2);
+    }
+
+    #[test]
+    fn test_sync_errors() {
+        let old_content = make_test_input(100 * CHUNK_SIZE);
+        let (old_outboard, _) = encode::outboard(&old_content);
+        let mut new_content = old_content.clone();
+        new_content[50 * CHUNK_SIZE] ^= 1;
+        let (new_outboard, hash) = encode::outboard(&new_content);
+
+        // The wrong hash, a corrupt parent node, and corrupt content from the sender.
+        let wrong_hash = blake3::hash(b"foo");
+        let mut bad_outboard = new_outboard.clone();
+        bad_outboard[HEADER_SIZE + PARENT_SIZE] ^= 1;
+        let mut bad_content = new_content.clone();
+        bad_content[50 * CHUNK_SIZE] ^= 2;
+        let cases = [
+            (&new_content, &new_outboard, &wrong_hash),
+            (&new_content, &bad_outboard, &hash),
+            (&bad_content, &new_outboard, &hash),
+        ];
+        for &(content, outboard, hash) in &cases {
+            let mut output = Vec::new();
+            let (received, _) = sync_over_tcp(
+                &old_content,
+                &old_outboard,
+                content,
+                outboard,
+                hash,
+                &mut output,
+            );
+            assert_eq!(io::ErrorKind::InvalidData, received.unwrap_err().kind());
+            assert!(output.len() < new_content.len());
+        }
+
+        // Malformed messages to the sender.
+        for request in [&[3][..], &[PARENTS, 0xff, 0xff, 0, 0]] {
+            let mut stream = Cursor::new(request.to_vec());
+            let mut output = Vec::new();
+            let err = serve_sync(
+                Cursor::new(&new_content),
+                &*new_outboard,
+                ReadWrite(&mut stream, &mut output),
+            )
+            .unwrap_err();
+            assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        }
+    }
+
+    #[test]
+    fn test_serve_sync_out_of_order() {
+        // Slices come out right in any order, even though a receiver asks in order.
+        let content = make_test_input(10 * CHUNK_SIZE);
+        let (outboard, _) = encode::outboard(&content);
+        let ranges = [(5000, 100), (0, 10), (3000, 5000)];
+        let mut requests = Vec::new();
+        let mut expected = crate::encode_len(content.len() as u64).to_vec();
+        for &(start, len) in &ranges {
+            requests.push(SLICE);
+            requests.extend_from_slice(&u64::to_le_bytes(start));
+            requests.extend_from_slice(&u64::to_le_bytes(len));
+            SliceExtractor::new_outboard(Cursor::new(&content), Cursor::new(&outboard), start, len)
+                .read_to_end(&mut expected)
+                .unwrap();
+        }
+        requests.push(DONE);
+        let mut output = Vec::new();
+        serve_sync(
+            Cursor::new(&content),
+            &*outboard,
+            ReadWrite(Cursor::new(requests), &mut output),
+        )
+        .unwrap();
+        assert_eq!(expected, output);
+    }
+
+    // Reads from one side and writes to the other.
+    struct ReadWrite<R, W>(R, W);
+
+    impl<R: Read, W> Read for ReadWrite<R, W> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl<R, W: Write> Write for ReadWrite<R, W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.1.flush()
+        }
+    }
+}